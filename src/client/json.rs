@@ -0,0 +1,72 @@
+// Just enough JSON to print the client's results for scripts.
+
+use std::fmt::{self, Write};
+
+#[ derive( Clone, Debug, PartialEq ) ]
+pub enum Json {
+    Bool( bool ),
+    Number( u64 ),
+    String( String ),
+    Array( Vec< Json > ),
+    // keys in the order they are printed
+    Object( Vec< ( &'static str, Json ) > ),
+}
+
+impl Json {
+    pub fn string< S: ToString >( value: S ) -> Json {
+        Json::String( value.to_string() )
+    }
+
+    pub fn strings< I, S >( values: I ) -> Json
+        where I: IntoIterator< Item = S >, S: ToString
+    {
+        Json::Array( values.into_iter().map( Json::string ).collect() )
+    }
+}
+
+fn write_string( fmt: &mut fmt::Formatter, value: &str ) -> fmt::Result {
+    fmt.write_char( '"' )?;
+    for c in value.chars() {
+        match c {
+            '"' => fmt.write_str( "\\\"" )?,
+            '\\' => fmt.write_str( "\\\\" )?,
+            '\n' => fmt.write_str( "\\n" )?,
+            '\r' => fmt.write_str( "\\r" )?,
+            '\t' => fmt.write_str( "\\t" )?,
+            c if ( c as u32 ) < 0x20 => write!( fmt, "\\u{:04x}", c as u32 )?,
+            c => fmt.write_char( c )?,
+        }
+    }
+    fmt.write_char( '"' )
+}
+
+impl fmt::Display for Json {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            Json::Bool( value ) => write!( fmt, "{}", value ),
+            Json::Number( value ) => write!( fmt, "{}", value ),
+            Json::String( ref value ) => write_string( fmt, value ),
+            Json::Array( ref values ) => {
+                fmt.write_char( '[' )?;
+                for ( index, value ) in values.iter().enumerate() {
+                    if index > 0 {
+                        fmt.write_char( ',' )?;
+                    }
+                    write!( fmt, "{}", value )?;
+                }
+                fmt.write_char( ']' )
+            },
+            Json::Object( ref fields ) => {
+                fmt.write_char( '{' )?;
+                for ( index, &( key, ref value ) ) in fields.iter().enumerate() {
+                    if index > 0 {
+                        fmt.write_char( ',' )?;
+                    }
+                    write_string( fmt, key )?;
+                    write!( fmt, ":{}", value )?;
+                }
+                fmt.write_char( '}' )
+            },
+        }
+    }
+}
@@ -1,260 +1,538 @@
-extern crate rand;
-extern crate yaml_rust;
-extern crate crc;
+extern crate turtlefs;
+
+mod json;
 
 use std::io::prelude::*;
-use std::io::{self, Write};
-use std::net::TcpStream;
+use std::io;
 use std::env;
 use std::fmt;
-use std::fs::File;
-use std::path::PathBuf;
-use rand::Rng;
-use std::str;
-use yaml_rust::{Yaml,YamlLoader,yaml};
-use crc::crc32;
+use std::fs;
+use std::process;
+use turtlefs::{ClientError, NamespaceEntry, TurtleClient};
+use turtlefs::manifest::{BlockChecksums, ManifestEdit, DEFAULT_BLOCK_SIZE};
+use turtlefs::request::{Retrieved, CHUNK_SIZE};
+use json::Json;
+
+const USAGE: &str = "\
+usage: client <turtlefs-root> <command> [--json]
+
+commands:
+  get <file> [--output <local-file>]  retrieve a file, to stdout unless an output is given,
+                                      resuming an interrupted download to the same output
+  cat <file> [--range <start>:<end>]  write a file, or the bytes from start up to end, to stdout
+  stat <file>                         show the metadata of a file on each of its nodes
+  ls [<dir>]                          list what file_store.yaml holds under a directory
+  verify <file>                       have every node check its copy against file_store.yaml
+  put <file> <local-file>             upload a local file to the nodes listed for it
+  rm <file>                           remove a file from file_store.yaml and from its nodes
+  edit <file> <edit>                  change the file's entry in file_store.yaml, one of:
+        --add <checksum> <node>...   --remove   --set-nodes <node>...
+        --set-checksum <checksum>    --set-blocks <local-file> [block-size]   --clear-blocks
+
+exit status: 0 success, 1 local failure, 2 usage, 3 configuration, 4 not found,
+             5 nodes unavailable, 6 refused by a node, 7 checksum mismatch";
+
+// why the client failed, which decides its exit status
+#[ derive( Debug ) ]
+enum Failure {
+    // the command line doesn't make sense
+    Usage( String ),
+    Client( ClientError ),
+    // file_store.yaml has nothing under a directory
+    NotListed( String ),
+    // a local file could not be read or written
+    Local( String ),
+    // some copies of a file are missing or don't match file_store.yaml
+    Unverified( String ),
+}
+
+impl Failure {
+    fn exit_status( &self ) -> i32 {
+        match *self {
+            Failure::Local( _ ) | Failure::Client( ClientError::Io( _ ) ) => 1,
+            Failure::Usage( _ ) => 2,
+            Failure::Client( ClientError::Config( _ ) ) => 3,
+            Failure::Client( ClientError::NotFound( _ ) ) | Failure::NotListed( _ ) => 4,
+            Failure::Client( ClientError::Unavailable( _ ) ) => 5,
+            Failure::Client( ClientError::Rejected( _ ) ) => 6,
+            Failure::Client( ClientError::ChecksumMismatch { .. } ) | Failure::Unverified( _ ) => 7,
+        }
+    }
 
-#[ derive( Clone, Debug ) ]
-struct Node {
-    ip: String,
-    port: String,
+    // the exit status as a word, for --json
+    fn kind( &self ) -> &'static str {
+        match self.exit_status() {
+            1 => "local",
+            2 => "usage",
+            3 => "config",
+            4 => "not_found",
+            5 => "unavailable",
+            6 => "rejected",
+            _ => "checksum_mismatch",
+        }
+    }
 }
 
-impl fmt::Display for Node {
+impl fmt::Display for Failure {
     fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
-        fmt.write_str( &format!( "{}:{}", self.ip, self.port ) )?;
-        Ok( () )
+        match *self {
+            Failure::Client( ref why ) => write!( fmt, "{}", why ),
+            Failure::Usage( ref why ) |
+            Failure::NotListed( ref why ) |
+            Failure::Local( ref why ) |
+            Failure::Unverified( ref why ) => write!( fmt, "{}", why ),
+        }
     }
 }
 
-#[ derive( Clone ) ]
-struct Request<'a> {
-    node: &'a Node,
-    request_string: String,
+impl From< ClientError > for Failure {
+    fn from( why: ClientError ) -> Failure {
+        Failure::Client( why )
+    }
 }
 
-#[ derive( Clone ) ]
-struct Response<'a> {
-    status: u8,
-    message: &'a [u8],
+enum Command {
+    Get { file_name: String, output: Option< String > },
+    // a range runs from its start up to (but not including) its end, or to
+    // the end of the file
+    Cat { file_name: String, range: Option< ( u64, Option< u64 > ) > },
+    Stat( String ),
+    Ls( String ),
+    Verify( String ),
+    Put { file_name: String, local_path: String },
+    Rm( String ),
+    Edit { file_name: String, edit: ManifestEdit },
 }
 
-fn retrieve_root_nodes( turtlefs_root: &str ) -> Vec< Node > {
-    let mut root_nodes = Vec::new();
-
-    let nodes_path: PathBuf = [ turtlefs_root, "nodes.yaml" ].iter().collect();
-    let mut f = File::open( nodes_path ).unwrap();
-    let mut contents = String::new();
-    f.read_to_string( &mut contents ).unwrap();
+// parse the manifest edit options:
+//   --add <checksum> <node>..., --remove, --set-nodes <node>...,
+//   --set-checksum <checksum>, --set-blocks <local-file> [block-size],
+//   --clear-blocks
+fn parse_manifest_edit( options: &[String] ) -> Result< Option< ManifestEdit >, Failure > {
+    let option_args = | operation: &str | {
+        let mut edit_args = vec![ operation ];
+        edit_args.extend( options[ 1 .. ].iter().map( | arg | arg.as_str() ) );
+        ManifestEdit::from_args( &edit_args ).map( Some ).map_err( Failure::Usage )
+    };
 
-    let node_yaml = YamlLoader::load_from_str( &contents ).unwrap();
-    for node_string in node_yaml[ 0 ].as_vec().unwrap() {
-        let node_info: Vec< &str > =
-            node_string.as_str().unwrap().split( ":" ).collect();
-        root_nodes.push(
-            Node { ip: node_info[ 0 ].to_string(),
-                   port: node_info[ 1 ].to_string() } );
+    match options.first().map( | option | option.as_str() ) {
+        Some( "--add" ) => option_args( "ADD" ),
+        Some( "--remove" ) => option_args( "REMOVE" ),
+        Some( "--set-nodes" ) => option_args( "NODES" ),
+        Some( "--set-checksum" ) => option_args( "CHECKSUM" ),
+        // block checksums are computed from a local copy of the file
+        Some( "--set-blocks" ) if options.len() == 2 || options.len() == 3 => {
+            let block_size = match options.get( 2 ) {
+                Some( block_size ) => block_size.parse().ok()
+                    .filter( | block_size | *block_size > 0 )
+                    .ok_or_else( || Failure::Usage( format!(
+                        "invalid block size: {}", block_size ) ) )?,
+                None => DEFAULT_BLOCK_SIZE,
+            };
+            let local_contents = fs::read( &options[ 1 ] ).map_err( | why | Failure::Local(
+                format!( "Could not read {}: {}", options[ 1 ], why ) ) )?;
+            Ok( Some( ManifestEdit::SetBlocks(
+                Some( BlockChecksums::compute( &local_contents, block_size ) ) ) ) )
+        },
+        Some( "--clear-blocks" ) => option_args( "BLOCKS" ),
+        _ => Ok( None ),
     }
-
-    return root_nodes;
 }
 
-fn retrieve_active_nodes( file_name: &str, file_store_map: &yaml::Hash )
-                          -> ( u32, Vec< Node > ) {
-    let mut active_nodes = Vec::new();
-    let mut checksum = 0;
-    for ( key_file, key_info ) in file_store_map {
-        if key_file.as_str().unwrap() == file_name {
-            let key_info_hash = key_info.as_hash().unwrap();
+// parse "start:end", where either may be left out
+fn parse_range( range: &str ) -> Result< ( u64, Option< u64 > ), Failure > {
+    let invalid = || Failure::Usage( format!(
+        "range ({}) must be: <start>:<end>, with either left out", range ) );
+    let ( start, end ) = range.split_once( ':' ).ok_or_else( invalid )?;
+    let start = if start.is_empty() { 0 } else { start.parse().map_err( | _ | invalid() )? };
+    let end = if end.is_empty() { None } else { Some( end.parse().map_err( | _ | invalid() )? ) };
+    if end.is_some_and( | end | end < start ) {
+        return Err( Failure::Usage( format!( "range ({}) must not end before it starts",
+                                             range ) ) );
+    }
+    Ok( ( start, end ) )
+}
 
-            // get checksum
-            let checksum_str = key_info_hash.get(
-                &Yaml::from_str( "checksum" ) ).unwrap().as_str().unwrap();
-            checksum = u32::from_str_radix( checksum_str, 16 ).unwrap();
+// the command in the client's args after turtlefs-root. The older
+// "<file> [--option]" form is still accepted.
+fn parse_command( args: &[String] ) -> Result< Command, Failure > {
+    let arg = | index: usize | args.get( index ).map( | arg | arg.as_str() );
+    let file_name = || arg( 1 ).map( String::from ).ok_or_else( || Failure::Usage( format!(
+        "{} needs the path of a file", args[ 0 ] ) ) );
+    let no_more_args = | count: usize | if args.len() > count {
+        Err( Failure::Usage( format!( "unexpected arguments: {}", args[ count .. ].join( " " ) ) ) )
+    } else {
+        Ok( () )
+    };
 
-            // get nodes
-            let val_node_list = key_info_hash.get(
-                &Yaml::from_str( "nodes" ) ).unwrap();
-            for node_string in val_node_list.as_vec().unwrap() {
-                let node_info: Vec< &str > =
-                    node_string.as_str().unwrap().split( ":" ).collect();
-                active_nodes.push(
-                    Node { ip: node_info[ 0 ].to_string(),
-                           port: node_info[ 1 ].to_string() } );
-            }
+    if args[ 0 ].starts_with( '/' ) {
+        let file_name = args[ 0 ].clone();
+        if let Some( edit ) = parse_manifest_edit( &args[ 1 .. ] )? {
+            return Ok( Command::Edit { file_name, edit } );
         }
+        return match ( arg( 1 ), arg( 2 ), args.len() ) {
+            ( None, _, _ ) => Ok( Command::Get { file_name, output: None } ),
+            ( Some( "--upload" ), Some( local_path ), 3 ) =>
+                Ok( Command::Put { file_name, local_path: String::from( local_path ) } ),
+            ( Some( "--stat" ), None, _ ) => Ok( Command::Stat( file_name ) ),
+            ( Some( "--verify" ), None, _ ) => Ok( Command::Verify( file_name ) ),
+            ( Some( "--rm" ), None, _ ) => Ok( Command::Rm( file_name ) ),
+            ( Some( "--ls" ), None, _ ) => Ok( Command::Ls( file_name ) ),
+            _ => Err( Failure::Usage( format!( "unexpected arguments: {}",
+                                               args[ 1 .. ].join( " " ) ) ) ),
+        };
     }
-    if active_nodes.len() == 0 {
-        panic!( "ERROR: no active nodes for file {}", file_name );
-    }
-    return ( checksum, active_nodes );
-}
 
-fn perform_request<'a>( request: Request, response_buffer: &'a mut Vec<u8> )
-                        -> Response<'a> {
-    let mut stream = match TcpStream::connect( request.node.to_string() ) {
-        Ok( s ) => s,
-        Err( _ ) => {
-            let error_string = format!( "could not connect to {}",
-                                         request.node.to_string() );
-            response_buffer.extend_from_slice( error_string.as_bytes() );
-            return Response {
-                status: 1,
-                message: &response_buffer[ .. error_string.len() ],
+    match args[ 0 ].as_str() {
+        "get" => {
+            let file_name = file_name()?;
+            let output = match arg( 2 ) {
+                Some( "--output" ) | Some( "-o" ) => Some( arg( 3 ).map( String::from )
+                    .ok_or_else( || Failure::Usage( String::from(
+                        "--output needs the path of a local file" ) ) )? ),
+                _ => None,
             };
+            no_more_args( if output.is_some() { 4 } else { 2 } )?;
+            Ok( Command::Get { file_name, output } )
         },
-    };
-    let request_bytes: &[u8] = request.request_string.as_bytes();
-
-    let _ = stream.write( request_bytes );
-    stream.shutdown( std::net::Shutdown::Write ).unwrap();
-
-    let _ = stream.read_to_end( response_buffer );
-
-    Response {
-        status: response_buffer[ 0 ],
-        message: &response_buffer[ 1.. ],
+        "cat" => {
+            let file_name = file_name()?;
+            let range = match arg( 2 ) {
+                Some( "--range" ) => Some( parse_range( arg( 3 ).ok_or_else( ||
+                    Failure::Usage( String::from( "--range needs <start>:<end>" ) ) )? )? ),
+                _ => None,
+            };
+            no_more_args( if range.is_some() { 4 } else { 2 } )?;
+            Ok( Command::Cat { file_name, range } )
+        },
+        "stat" => file_name().and_then( | file_name | {
+            no_more_args( 2 )?;
+            Ok( Command::Stat( file_name ) )
+        } ),
+        "ls" => {
+            no_more_args( 2 )?;
+            Ok( Command::Ls( String::from( arg( 1 ).unwrap_or( "/" ) ) ) )
+        },
+        "verify" => file_name().and_then( | file_name | {
+            no_more_args( 2 )?;
+            Ok( Command::Verify( file_name ) )
+        } ),
+        "put" => {
+            let file_name = file_name()?;
+            let local_path = arg( 2 ).map( String::from ).ok_or_else( || Failure::Usage(
+                String::from( "put needs the path of the local file to upload" ) ) )?;
+            no_more_args( 3 )?;
+            Ok( Command::Put { file_name, local_path } )
+        },
+        "rm" => file_name().and_then( | file_name | {
+            no_more_args( 2 )?;
+            Ok( Command::Rm( file_name ) )
+        } ),
+        "edit" => {
+            let file_name = file_name()?;
+            match parse_manifest_edit( &args[ 2 .. ] )? {
+                Some( edit ) => Ok( Command::Edit { file_name, edit } ),
+                None => Err( Failure::Usage( String::from( "edit needs one of the edit options" ) ) ),
+            }
+        },
+        command => Err( Failure::Usage( format!( "unknown command: {}", command ) ) ),
     }
 }
 
-fn perform_request_with_retry<'a>( request_string: &String,
-                                   primary_node_option: Option< &Node >,
-                                   backup_nodes: Vec< Node >,
-                                   response_buffer: &'a mut Vec< u8 > )
-                                   -> Option< Response<'a> > {
-    let mut random_backup_nodes = backup_nodes.clone();
-    let random_backup_nodes_slice = random_backup_nodes.as_mut_slice();
-    rand::thread_rng().shuffle( random_backup_nodes_slice );
-
-    let mut nodes: Vec< Node > = match primary_node_option {
-        Some( primary_node ) => vec![ primary_node.clone() ],
-        None => Vec::new(),
-    };
-    nodes.extend_from_slice( random_backup_nodes_slice );
-
-    for node in nodes.iter() {
-        let request = Request {
-            node: node,
-            request_string: request_string.clone(),
-        };
-        let temp_response_buffer = &mut Vec::new();
-        let temp_response = perform_request( request, temp_response_buffer );
-        if temp_response.status == 0 {
-            response_buffer.clear();
-            response_buffer.extend_from_slice( temp_response.message );
-            return Some( Response {
-                status: 0,
-                message: &response_buffer[ 0.. ],
-            } );
-        }
+// warn about nodes which sent bad blocks, which were retrieved again from
+// other nodes
+fn report_corrupt_nodes( file_name: &str, retrieved: &Retrieved ) {
+    for node in retrieved.corrupt_nodes.iter() {
+        eprintln!( "{} sent bad blocks of {}, which were retrieved from other nodes",
+                   node, file_name );
     }
-    None
 }
 
-fn request_length( nodes: Vec< Node >, file_name: &String ) -> u16 {
-    let response_buffer = &mut Vec::new();
-    let response_option = perform_request_with_retry(
-        &format!( "{}:(LENGTH)", file_name ), None, nodes, response_buffer );
+// retrieve a file into a local file, resuming an interrupted download to
+// the same file
+fn get_to_file( client: &TurtleClient, file_name: &str, output: &str, json: bool )
+                -> Result< (), Failure > {
+    let downloaded = client.download( file_name, output )?;
+    let retrieved = downloaded.retrieved;
+    if json {
+        println!( "{}", Json::Object( vec![
+            ( "file", Json::string( file_name ) ),
+            ( "output", Json::string( output ) ),
+            ( "length", Json::Number( retrieved.length ) ),
+            ( "checksum", Json::string( retrieved.checksum ) ),
+            ( "resumed", Json::Bool( downloaded.resumed ) ),
+            ( "corrupt_nodes", Json::strings( retrieved.corrupt_nodes.iter() ) ),
+        ] ) );
+    } else {
+        report_corrupt_nodes( file_name, &retrieved );
+        println!( "retrieved {} to {} ({} bytes{})", file_name, output, retrieved.length,
+                  if downloaded.resumed { ", resumed" } else { "" } );
+    }
+    Ok( () )
+}
 
-    match response_option {
-        Some( response ) => {
-            str::from_utf8( response.message ).unwrap().parse::<u16>().unwrap()
-        },
-        None => panic!( "Could not retrieve file length for {}", file_name ),
+// write a range of a file to stdout, a chunk at a time. A range which goes
+// past the end of the file is a usage error, like one which ends before it
+// starts.
+fn cat_range( client: &TurtleClient, file_name: &str, start_offset: u64,
+              end_offset: Option< u64 > ) -> Result< (), Failure > {
+    let entry = client.entry( file_name )?;
+    let length = client.entry_length( file_name, &entry )?;
+    let end_offset = end_offset.unwrap_or( length );
+    if start_offset > length || end_offset > length {
+        return Err( Failure::Usage( format!(
+            "range ({}:{}) must not go past the end of {}, which is {} bytes long",
+            start_offset, end_offset, file_name, length ) ) );
+    }
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut offset = start_offset;
+    while offset < end_offset {
+        let chunk_end = std::cmp::min( offset + CHUNK_SIZE, end_offset );
+        let chunk = client.entry_range( file_name, &entry, offset, chunk_end )?;
+        output.write_all( &chunk ).map_err( ClientError::Io )?;
+        offset = chunk_end;
     }
+    output.flush().map_err( ClientError::Io )?;
+    Ok( () )
 }
 
-fn request_whole_file<'a>( nodes: Vec< Node >, file_name: &String,
-                           response_buffer: &'a mut Vec<u8> )
-                           -> &'a [u8] {
-    let response_option = perform_request_with_retry(
-        &format!( "{}:(READ)", file_name ), None, nodes, response_buffer );
+// print the metadata of a file on each of its nodes, flagging any replica
+// whose checksum differs from file_store.yaml
+fn print_stat( client: &TurtleClient, file_name: &str, json: bool ) -> Result< (), Failure > {
+    let entry = client.entry( file_name )?;
+    let node_stats = client.stat( file_name )?;
+    if json {
+        let nodes = node_stats.into_iter().map( | ( node, file_stat ) | match file_stat {
+            Ok( file_stat ) => Json::Object( vec![
+                ( "node", Json::string( node ) ),
+                ( "size", Json::Number( file_stat.size ) ),
+                ( "modified", Json::Number( file_stat.modified ) ),
+                ( "permissions", Json::Number( file_stat.permissions as u64 ) ),
+                ( "checksum", Json::string( file_stat.checksum ) ),
+                ( "matches", Json::Bool( file_stat.checksum == entry.checksum ) ),
+            ] ),
+            Err( why ) => Json::Object( vec![
+                ( "node", Json::string( node ) ),
+                ( "error", Json::string( why ) ),
+            ] ),
+        } ).collect();
+        println!( "{}", Json::Object( vec![
+            ( "file", Json::string( file_name ) ),
+            ( "checksum", Json::string( entry.checksum ) ),
+            ( "nodes", Json::Array( nodes ) ),
+        ] ) );
+        return Ok( () );
+    }
 
-    match response_option {
-        Some( response ) => {
-            response.message
-        },
-        None => panic!( "Could not retrieve file contents for {}", file_name ),
+    println!( "{} (file_store.yaml checksum {})", file_name, entry.checksum );
+    for ( node, file_stat ) in node_stats {
+        match file_stat {
+            Ok( file_stat ) => println!(
+                "  {}: size {}, modified {}, permissions {:04o}, checksum {}{}",
+                node, file_stat.size, file_stat.modified, file_stat.permissions,
+                file_stat.checksum,
+                if file_stat.checksum == entry.checksum { "" } else { " (differs)" } ),
+            Err( why ) => println!( "  {}: {}", node, why ),
+        }
     }
+    Ok( () )
 }
 
-fn request_file_chunk<'a>( primary_node: &Node, backup_nodes: Vec< Node >,
-                     file_name: &String, start_offset: u16, end_offset: u16,
-                     response_buffer: &'a mut Vec<u8> ) -> &'a [u8] {
-    let response_option = perform_request_with_retry(
-        &format!( "{}:(READ,{},{})", file_name, start_offset, end_offset ),
-        Some( primary_node ), backup_nodes, response_buffer );
+// list the namespace under dir_name, marking files with unreachable nodes
+fn print_ls( client: &TurtleClient, dir_name: &str, json: bool ) -> Result< (), Failure > {
+    let listed = client.ls( dir_name )?;
+    // an empty namespace still has a root to list
+    if listed.is_empty() && !dir_name.trim_end_matches( '/' ).is_empty() {
+        return Err( Failure::NotListed( format!(
+            "nothing in file_store.yaml under {}", dir_name ) ) );
+    }
+    if json {
+        let entries = listed.into_iter().map( | namespace_entry | match namespace_entry {
+            NamespaceEntry::Directory( dir_name ) => Json::Object( vec![
+                ( "type", Json::string( "directory" ) ),
+                ( "path", Json::String( dir_name ) ),
+            ] ),
+            NamespaceEntry::File { file_name, entry, unreachable_nodes } => Json::Object( vec![
+                ( "type", Json::string( "file" ) ),
+                ( "path", Json::String( file_name ) ),
+                ( "checksum", Json::string( entry.checksum ) ),
+                ( "nodes", Json::strings( entry.nodes.iter() ) ),
+                ( "unreachable_nodes", Json::strings( unreachable_nodes.iter() ) ),
+            ] ),
+        } ).collect();
+        println!( "{}", Json::Array( entries ) );
+        return Ok( () );
+    }
 
-    match response_option {
-        Some( response ) => {
-            response.message
-        },
-        None => panic!( "Could not retrieve file contents between {} and {} for {}",
-                         start_offset, end_offset, file_name ),
+    for namespace_entry in listed {
+        match namespace_entry {
+            NamespaceEntry::Directory( dir_name ) => println!( "{}/", dir_name ),
+            NamespaceEntry::File { file_name, ref entry, ref unreachable_nodes }
+                if !unreachable_nodes.is_empty() => {
+                let nodes: Vec< String > = unreachable_nodes.iter()
+                    .map( | node | node.to_string() )
+                    .collect();
+                let marker = if unreachable_nodes.len() == entry.nodes.len() {
+                    "unreachable"
+                } else {
+                    "partly unreachable"
+                };
+                println!( "{} ({}: {})", file_name, marker, nodes.join( ", " ) );
+            },
+            NamespaceEntry::File { file_name, .. } => println!( "{}", file_name ),
+        }
     }
+    Ok( () )
 }
 
-// split the file into even chunks based on how many nodes there are,
-// then request the file chunks from the nodes
-fn request_file_distributed( nodes: Vec< Node >, file_name: &String,
-                             file_length: u16, file_contents: &mut Vec< u8 > ) {
-    let num_nodes = nodes.len() as u16;
-    let chunk_size: u16 = std::cmp::max( file_length / num_nodes, 1 );
-    let mut start_offset = 0;
-    let mut end_offset = 0;
-    let mut count = 0;
-    for node in nodes.clone() {
-        end_offset += chunk_size;
-        if end_offset > file_length {
-            break;
+// compare the checksum of every replica of a file against file_store.yaml
+// without retrieving it, failing if any replica is bad
+fn print_verify( client: &TurtleClient, file_name: &str, json: bool ) -> Result< (), Failure > {
+    let entry = client.entry( file_name )?;
+    let node_checksums = client.checksum_replicas( file_name, None )?;
+    let mut bad_nodes = Vec::new();
+    let mut nodes = Vec::new();
+    for ( node, checksum ) in node_checksums {
+        let mut fields = vec![ ( "node", Json::string( &node ) ) ];
+        match checksum {
+            Ok( checksum ) if checksum == entry.checksum => {
+                if !json {
+                    println!( "{}: ok", node );
+                }
+                fields.push( ( "ok", Json::Bool( true ) ) );
+                fields.push( ( "checksum", Json::string( checksum ) ) );
+            },
+            Ok( checksum ) => {
+                if !json {
+                    println!( "{}: checksum {}, expected {}", node, checksum, entry.checksum );
+                }
+                fields.push( ( "ok", Json::Bool( false ) ) );
+                fields.push( ( "checksum", Json::string( checksum ) ) );
+                bad_nodes.push( node.to_string() );
+            },
+            Err( why ) => {
+                if !json {
+                    println!( "{}: {}", node, why );
+                }
+                fields.push( ( "ok", Json::Bool( false ) ) );
+                fields.push( ( "error", Json::string( why ) ) );
+                bad_nodes.push( node.to_string() );
+            },
         }
-        if count == num_nodes - 1 {
-            end_offset = file_length;
-        }
-        let response_buffer = &mut Vec::new();
-        let file_chunk = request_file_chunk( &node, nodes.clone(), file_name,
-                                              start_offset, end_offset,
-                                              response_buffer );
-        file_contents.extend_from_slice( file_chunk );
-        start_offset = end_offset;
-        count += 1;
+        nodes.push( Json::Object( fields ) );
+    }
+    if json {
+        println!( "{}", Json::Object( vec![
+            ( "file", Json::string( file_name ) ),
+            ( "checksum", Json::string( entry.checksum ) ),
+            ( "nodes", Json::Array( nodes ) ),
+        ] ) );
     }
+    if !bad_nodes.is_empty() {
+        return Err( Failure::Unverified( format!(
+            "{} could not be verified on: {}", file_name, bad_nodes.join( ", " ) ) ) );
+    }
+    Ok( () )
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let turtlefs_root = &args[ 1 ];
-    let file_name = &args[ 2 ];
-
-    // retrieve /file_store.yaml
-    let root_nodes = retrieve_root_nodes( turtlefs_root );
-    let response_buffer = &mut Vec::new();
-    let file_store = request_whole_file( root_nodes.clone(),
-                                         &"/file_store.yaml".to_string(),
-                                         response_buffer );
-
-    // extract the file store map
-    let file_store_string = str::from_utf8( file_store ).unwrap();
-    let file_store_yaml = YamlLoader::load_from_str( &file_store_string ).unwrap();
-    let file_store_map = file_store_yaml[ 0 ].as_hash().unwrap();
+fn run_command( client: &TurtleClient, command: Command, json: bool ) -> Result< (), Failure > {
+    // a file written to stdout leaves no room for --json output
+    let no_json = | command: &str | if json {
+        Err( Failure::Usage( format!(
+            "{} writes the file to stdout, so it has no --json output", command ) ) )
+    } else {
+        Ok( () )
+    };
 
-    // find nodes that have this file
-    let ( checksum, active_nodes ) =
-        retrieve_active_nodes( file_name, file_store_map );
+    match command {
+        Command::Get { file_name, output: Some( output ) } =>
+            get_to_file( client, &file_name, &output, json ),
+        Command::Get { file_name, output: None } | Command::Cat { file_name, range: None } => {
+            no_json( "get without --output" )?;
+            let stdout = io::stdout();
+            let mut output = stdout.lock();
+            let retrieved = client.get_to( &file_name, &mut output )?;
+            report_corrupt_nodes( &file_name, &retrieved );
+            Ok( () )
+        },
+        Command::Cat { file_name, range: Some( ( start_offset, end_offset ) ) } => {
+            no_json( "cat" )?;
+            cat_range( client, &file_name, start_offset, end_offset )
+        },
+        Command::Stat( file_name ) => print_stat( client, &file_name, json ),
+        Command::Ls( dir_name ) => print_ls( client, &dir_name, json ),
+        Command::Verify( file_name ) => print_verify( client, &file_name, json ),
+        Command::Put { file_name, local_path } => {
+            let local_contents = fs::read( &local_path ).map_err( | why | Failure::Local(
+                format!( "Could not read {}: {}", local_path, why ) ) )?;
+            client.put( &file_name, &local_contents )?;
+            if json {
+                println!( "{}", Json::Object( vec![
+                    ( "file", Json::string( &file_name ) ),
+                    ( "length", Json::Number( local_contents.len() as u64 ) ),
+                ] ) );
+            } else {
+                println!( "uploaded {} ({} bytes)", file_name, local_contents.len() );
+            }
+            Ok( () )
+        },
+        // removes the file from file_store.yaml and from its nodes
+        Command::Rm( file_name ) => {
+            client.remove( &file_name )?;
+            if json {
+                println!( "{}", Json::Object( vec![ ( "file", Json::string( &file_name ) ),
+                                                    ( "removed", Json::Bool( true ) ) ] ) );
+            } else {
+                println!( "removed {}", file_name );
+            }
+            Ok( () )
+        },
+        Command::Edit { file_name, edit } => {
+            let version = client.edit_manifest( &file_name, &edit )?;
+            if json {
+                println!( "{}", Json::Object( vec![ ( "file", Json::string( &file_name ) ),
+                                                    ( "version", Json::Number( version ) ) ] ) );
+            } else {
+                println!( "file_store.yaml is now at version {}", version );
+            }
+            Ok( () )
+        },
+    }
+}
 
-    // retrieve file length
-    let file_length = request_length( active_nodes.clone(), file_name );
+fn run( args: &[String], json: bool ) -> Result< (), Failure > {
+    let ( turtlefs_root, command_args ) = match args.split_first() {
+        Some( ( turtlefs_root, command_args ) ) if !command_args.is_empty() =>
+            ( turtlefs_root, command_args ),
+        _ => return Err( Failure::Usage( String::from(
+            "expected a turtlefs-root and a command" ) ) ),
+    };
+    let command = parse_command( command_args )?;
 
-    // retrieve the file
-    let file_contents = &mut Vec::new();
-    request_file_distributed( active_nodes.clone(), file_name,
-                              file_length, file_contents );
+    // connections are reused across all of the requests the command makes
+    let client = TurtleClient::from_root( turtlefs_root )?;
+    run_command( &client, command, json )
+}
 
-    // verify file checksum matches the one in file store
-    if checksum != crc32::checksum_ieee( file_contents ) {
-        panic!( "File does not match checksum from file_store.yaml" );
+fn main() {
+    let mut args: Vec< String > = env::args().skip( 1 ).collect();
+    let json = args.iter().any( | arg | arg == "--json" );
+    args.retain( | arg | arg != "--json" );
+
+    if let Err( failure ) = run( &args, json ) {
+        if json {
+            eprintln!( "{}", Json::Object( vec![
+                ( "error", Json::string( &failure ) ),
+                ( "kind", Json::string( failure.kind() ) ),
+                ( "exit_status", Json::Number( failure.exit_status() as u64 ) ),
+            ] ) );
+        } else {
+            eprintln!( "client: {}", failure );
+            if let Failure::Usage( _ ) = failure {
+                eprintln!( "{}", USAGE );
+            }
+        }
+        process::exit( failure.exit_status() );
     }
-
-    // output the file to stdout
-    io::stdout().write( file_contents ).unwrap();
 }
@@ -0,0 +1,155 @@
+// Typed file digests, as written in the checksum field of file_store.yaml:
+//
+//     checksum: DB588331            (crc32, also accepted as crc32:DB588331)
+//     checksum: sha256:<64 hex digits>
+//     checksum: blake3:<64 hex digits>
+//
+// crc32 catches accidental corruption, while sha256 and blake3 also catch
+// deliberate tampering.
+
+use std::fmt;
+use std::str::FromStr;
+use blake3;
+use crc::crc32;
+use sha2::{self, Digest as Sha2Digest};
+
+#[ derive( Clone, Copy, Debug, PartialEq, Eq, Hash ) ]
+pub enum Algorithm {
+    Crc32,
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    pub fn name( self ) -> &'static str {
+        match self {
+            Algorithm::Crc32 => "crc32",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_name( name: &str ) -> Option< Algorithm > {
+        match name {
+            "crc32" => Some( Algorithm::Crc32 ),
+            "sha256" => Some( Algorithm::Sha256 ),
+            "blake3" => Some( Algorithm::Blake3 ),
+            _ => None,
+        }
+    }
+}
+
+#[ derive( Clone, Copy, Debug, PartialEq, Eq, Hash ) ]
+pub enum Digest {
+    Crc32( u32 ),
+    Sha256( [ u8; 32 ] ),
+    Blake3( [ u8; 32 ] ),
+}
+
+impl Digest {
+    pub fn algorithm( &self ) -> Algorithm {
+        match *self {
+            Digest::Crc32( _ ) => Algorithm::Crc32,
+            Digest::Sha256( _ ) => Algorithm::Sha256,
+            Digest::Blake3( _ ) => Algorithm::Blake3,
+        }
+    }
+
+    // digest of contents held entirely in memory
+    pub fn compute( algorithm: Algorithm, contents: &[u8] ) -> Digest {
+        let mut hasher = Hasher::new( algorithm );
+        hasher.update( contents );
+        hasher.finish()
+    }
+}
+
+// crc32 digests are written as bare hex, as they always have been, so that
+// existing manifests read the same
+impl fmt::Display for Digest {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            Digest::Crc32( checksum ) => write!( fmt, "{:08X}", checksum ),
+            Digest::Sha256( ref bytes ) | Digest::Blake3( ref bytes ) => {
+                write!( fmt, "{}:", self.algorithm().name() )?;
+                for byte in bytes.iter() {
+                    write!( fmt, "{:02x}", byte )?;
+                }
+                Ok( () )
+            },
+        }
+    }
+}
+
+impl FromStr for Digest {
+    type Err = String;
+
+    fn from_str( digest_str: &str ) -> Result< Digest, String > {
+        let ( algorithm, hex ) = match digest_str.find( ':' ) {
+            Some( colon ) => {
+                let name = &digest_str[ .. colon ];
+                let algorithm = Algorithm::from_name( name ).ok_or_else( || format!(
+                    "unknown checksum algorithm \"{}\", expected one of: crc32, sha256, blake3",
+                    name ) )?;
+                ( algorithm, &digest_str[ colon + 1 .. ] )
+            },
+            None => ( Algorithm::Crc32, digest_str ),
+        };
+
+        match algorithm {
+            Algorithm::Crc32 => u32::from_str_radix( hex, 16 ).map( Digest::Crc32 )
+                .map_err( | why | format!( "invalid checksum \"{}\": {}", digest_str, why ) ),
+            Algorithm::Sha256 => parse_hex_32( hex ).map( Digest::Sha256 )
+                .map_err( | why | format!( "invalid checksum \"{}\": {}", digest_str, why ) ),
+            Algorithm::Blake3 => parse_hex_32( hex ).map( Digest::Blake3 )
+                .map_err( | why | format!( "invalid checksum \"{}\": {}", digest_str, why ) ),
+        }
+    }
+}
+
+fn parse_hex_32( hex: &str ) -> Result< [ u8; 32 ], String > {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err( String::from( "expected 64 hex digits" ) );
+    }
+    let mut bytes = [ 0u8; 32 ];
+    for ( index, byte ) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix( &hex[ index * 2 .. index * 2 + 2 ], 16 )
+            .map_err( | why | why.to_string() )?;
+    }
+    Ok( bytes )
+}
+
+// computes a digest of data fed to it a piece at a time
+pub enum Hasher {
+    Crc32( u32 ),
+    Sha256( sha2::Sha256 ),
+    Blake3( Box< blake3::Hasher > ),
+}
+
+impl Hasher {
+    pub fn new( algorithm: Algorithm ) -> Hasher {
+        match algorithm {
+            Algorithm::Crc32 => Hasher::Crc32( 0 ),
+            Algorithm::Sha256 => Hasher::Sha256( sha2::Sha256::new() ),
+            Algorithm::Blake3 => Hasher::Blake3( Box::new( blake3::Hasher::new() ) ),
+        }
+    }
+
+    pub fn update( &mut self, bytes: &[u8] ) {
+        match *self {
+            Hasher::Crc32( ref mut checksum ) =>
+                *checksum = crc32::update( *checksum, &crc32::IEEE_TABLE, bytes ),
+            Hasher::Sha256( ref mut hasher ) => hasher.update( bytes ),
+            Hasher::Blake3( ref mut hasher ) => {
+                hasher.update( bytes );
+            },
+        }
+    }
+
+    pub fn finish( self ) -> Digest {
+        match self {
+            Hasher::Crc32( checksum ) => Digest::Crc32( checksum ),
+            Hasher::Sha256( hasher ) => Digest::Sha256( hasher.finalize().into() ),
+            Hasher::Blake3( hasher ) => Digest::Blake3( *hasher.finalize().as_bytes() ),
+        }
+    }
+}
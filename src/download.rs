@@ -0,0 +1,167 @@
+// Downloads to local files which can be picked up again after being
+// interrupted. The file is written to <output>.part, and each range of it
+// is recorded in the sidecar <output>.part.ranges once it is on disk, so
+// downloading the same file to the same output again only retrieves the
+// ranges which are missing.
+//
+// The first line of the sidecar is the checksum, length and block size
+// (0 without block checksums) of the file being downloaded, and every
+// line after it is the start and end offset of a range that was written:
+//
+//     DB588331 12 0
+//     0 12
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use digest::{Algorithm, Digest, Hasher};
+
+// how much of the partial file is read at once to check its checksum
+const CHECKSUM_BUFFER_SIZE: usize = 1024 * 1024;
+
+pub fn part_path( output: &Path ) -> PathBuf {
+    with_suffix( output, ".part" )
+}
+
+pub fn ranges_path( output: &Path ) -> PathBuf {
+    with_suffix( output, ".part.ranges" )
+}
+
+fn with_suffix( output: &Path, suffix: &str ) -> PathBuf {
+    let mut path = OsString::from( output.as_os_str() );
+    path.push( suffix );
+    PathBuf::from( path )
+}
+
+// a download in progress, which becomes the output file once finished
+pub struct PartialFile {
+    output: PathBuf,
+    length: u64,
+    file: File,
+    ranges_file: File,
+    // ranges already written, in the order they were written
+    written: Vec< ( u64, u64 ) >,
+}
+
+impl PartialFile {
+    // pick up the partial download at output, or start a new one if there
+    // is none or it was for a file with another checksum, length or block
+    // size
+    pub fn open( output: &Path, checksum: &Digest, length: u64, block_size: u64 )
+                 -> io::Result< PartialFile > {
+        let header = format!( "{} {} {}", checksum, length, block_size );
+        let written = match fs::read_to_string( ranges_path( output ) ) {
+            Ok( ref ranges ) if part_path( output ).exists() => parse_ranges( ranges, &header,
+                                                                              length ),
+            _ => None,
+        };
+
+        let file = OpenOptions::new()
+            .read( true )
+            .write( true )
+            .create( true )
+            .truncate( written.is_none() )
+            .open( part_path( output ) )?;
+        file.set_len( length )?;
+
+        // rewrite the sidecar, dropping a line left half written when the
+        // last download was interrupted
+        let written = written.unwrap_or_default();
+        let mut ranges_file = File::create( ranges_path( output ) )?;
+        writeln!( ranges_file, "{}", header )?;
+        for &( start_offset, end_offset ) in written.iter() {
+            writeln!( ranges_file, "{} {}", start_offset, end_offset )?;
+        }
+
+        Ok( PartialFile { output: output.to_path_buf(), length, file, ranges_file, written } )
+    }
+
+    // whether an earlier download had already written some of the file
+    pub fn is_resumed( &self ) -> bool {
+        !self.written.is_empty()
+    }
+
+    // the ranges which haven't been written yet, in order
+    pub fn missing( &self ) -> Vec< ( u64, u64 ) > {
+        let mut written = self.written.clone();
+        written.sort();
+        let mut missing = Vec::new();
+        let mut offset = 0;
+        for ( start_offset, end_offset ) in written {
+            if start_offset > offset {
+                missing.push( ( offset, start_offset ) );
+            }
+            offset = std::cmp::max( offset, end_offset );
+        }
+        if offset < self.length {
+            missing.push( ( offset, self.length ) );
+        }
+        missing
+    }
+
+    // write contents at start_offset, and record the range once it is on
+    // disk
+    pub fn write_range( &mut self, start_offset: u64, contents: &[u8] ) -> io::Result< () > {
+        let end_offset = start_offset + contents.len() as u64;
+        self.file.seek( SeekFrom::Start( start_offset ) )?;
+        self.file.write_all( contents )?;
+        self.file.sync_data()?;
+        writeln!( self.ranges_file, "{} {}", start_offset, end_offset )?;
+        self.written.push( ( start_offset, end_offset ) );
+        Ok( () )
+    }
+
+    // the digest of everything in the partial file
+    pub fn checksum( &mut self, algorithm: Algorithm ) -> io::Result< Digest > {
+        let mut hasher = Hasher::new( algorithm );
+        let mut buffer = vec![ 0u8; CHECKSUM_BUFFER_SIZE ];
+        self.file.seek( SeekFrom::Start( 0 ) )?;
+        loop {
+            match self.file.read( &mut buffer )? {
+                0 => break,
+                read => hasher.update( &buffer[ .. read ] ),
+            }
+        }
+        Ok( hasher.finish() )
+    }
+
+    // move the finished file into place at output
+    pub fn finish( self ) -> io::Result< () > {
+        let PartialFile { output, file, ranges_file, .. } = self;
+        drop( file );
+        drop( ranges_file );
+        fs::rename( part_path( &output ), &output )?;
+        fs::remove_file( ranges_path( &output ) )
+    }
+
+    // throw the download away, so the next one starts over
+    pub fn discard( self ) {
+        let PartialFile { output, file, ranges_file, .. } = self;
+        drop( file );
+        drop( ranges_file );
+        let _ = fs::remove_file( part_path( &output ) );
+        let _ = fs::remove_file( ranges_path( &output ) );
+    }
+}
+
+// the ranges recorded in a sidecar, if it is for the file described by
+// header. Only lines ending in a newline were written completely.
+fn parse_ranges( ranges: &str, header: &str, length: u64 ) -> Option< Vec< ( u64, u64 ) > > {
+    let mut lines: Vec< &str > = ranges.split( '\n' ).collect();
+    lines.pop();
+    if lines.first() != Some( &header ) {
+        return None;
+    }
+    Some( lines[ 1 .. ].iter().filter_map( | line | {
+        let ( start_offset, end_offset ) = line.split_once( ' ' )?;
+        let start_offset: u64 = start_offset.parse().ok()?;
+        let end_offset: u64 = end_offset.parse().ok()?;
+        if start_offset < end_offset && end_offset <= length {
+            Some( ( start_offset, end_offset ) )
+        } else {
+            None
+        }
+    } ).collect() )
+}
@@ -0,0 +1,25 @@
+// Code shared between the turtleFS client and server.
+
+extern crate rand;
+extern crate yaml_rust;
+extern crate crc;
+extern crate sha2;
+extern crate blake3;
+extern crate openssl;
+
+pub mod digest;
+pub mod download;
+pub mod listing;
+pub mod manifest;
+pub mod node;
+pub mod pool;
+pub mod protocol;
+pub mod remote_reader;
+pub mod request;
+pub mod signing;
+pub mod stat;
+pub mod tls;
+pub mod turtle_client;
+
+pub use remote_reader::RemoteReader;
+pub use turtle_client::{ClientError, Downloaded, NamespaceEntry, TurtleClient};
@@ -0,0 +1,92 @@
+// Directory listings returned by the LIST action, sent as a YAML document:
+//
+//     entries:
+//       - name: dir
+//         directory: true
+//       - name: dir/hello.txt
+//         size: 12
+//     next: dir/hello.txt
+//
+// Entry names are relative to the listed directory, and next is only
+// present when there are more entries, which are listed by passing it back
+// as the after argument.
+
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+use yaml_rust::yaml::Hash;
+
+#[ derive( Clone, Debug, PartialEq, Eq ) ]
+pub struct ListEntry {
+    // path relative to the listed directory, e.g. dir/hello.txt
+    pub name: String,
+    pub is_dir: bool,
+    // length in bytes, 0 for directories
+    pub size: u64,
+}
+
+#[ derive( Clone, Debug, Default, PartialEq, Eq ) ]
+pub struct Listing {
+    pub entries: Vec< ListEntry >,
+    // name of the last entry, if the listing was cut short
+    pub next: Option< String >,
+}
+
+impl Listing {
+    // emitted with yaml_rust so that any file name is quoted as needed
+    pub fn to_yaml( &self ) -> String {
+        let entries = self.entries.iter().map( | entry | {
+            let mut entry_map = Hash::new();
+            entry_map.insert( Yaml::String( String::from( "name" ) ),
+                              Yaml::String( entry.name.clone() ) );
+            if entry.is_dir {
+                entry_map.insert( Yaml::String( String::from( "directory" ) ),
+                                  Yaml::Boolean( true ) );
+            } else {
+                entry_map.insert( Yaml::String( String::from( "size" ) ),
+                                  Yaml::Integer( entry.size as i64 ) );
+            }
+            Yaml::Hash( entry_map )
+        } ).collect();
+
+        let mut listing_map = Hash::new();
+        listing_map.insert( Yaml::String( String::from( "entries" ) ), Yaml::Array( entries ) );
+        if let Some( ref next ) = self.next {
+            listing_map.insert( Yaml::String( String::from( "next" ) ),
+                                Yaml::String( next.clone() ) );
+        }
+
+        let mut contents = String::new();
+        YamlEmitter::new( &mut contents ).dump( &Yaml::Hash( listing_map ) ).unwrap();
+        contents.push( '\n' );
+        contents
+    }
+
+    pub fn parse( contents: &str ) -> Result< Listing, String > {
+        let docs = YamlLoader::load_from_str( contents )
+            .map_err( | why | format!( "could not parse listing: {}", why ) )?;
+        let doc = docs.first().ok_or_else( || String::from( "empty listing" ) )?;
+
+        let entry_list = match doc[ "entries" ] {
+            Yaml::Array( ref entry_list ) => entry_list,
+            _ => return Err( String::from( "listing is missing its entries" ) ),
+        };
+        let mut entries = Vec::new();
+        for entry in entry_list {
+            let name = entry[ "name" ].as_str()
+                .ok_or_else( || String::from( "listing entry is missing its name" ) )?;
+            let is_dir = entry[ "directory" ].as_bool().unwrap_or( false );
+            let size = match entry[ "size" ] {
+                Yaml::Integer( size ) if size >= 0 => size as u64,
+                Yaml::BadValue if is_dir => 0,
+                _ => return Err( format!( "listing entry {} has an invalid size", name ) ),
+            };
+            entries.push( ListEntry { name: name.to_string(), is_dir, size } );
+        }
+
+        let next = match doc[ "next" ] {
+            Yaml::String( ref next ) => Some( next.clone() ),
+            Yaml::BadValue => None,
+            _ => return Err( String::from( "listing next must be a string" ) ),
+        };
+        Ok( Listing { entries, next } )
+    }
+}
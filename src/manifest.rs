@@ -0,0 +1,358 @@
+// The file manifest (store/file_store.yaml), which records the checksum of
+// each file in the store along with the nodes that provide it.
+//
+// The manifest also carries a version number, which is bumped by every
+// edit so that edits made against an out of date copy can be rejected.
+//
+// An entry's checksum is a crc32 checksum unless it names another
+// algorithm, e.g. sha256:<hex> (see digest.rs).
+//
+// An entry may also record the crc32 checksum of every fixed-size block of the
+// file, so that a bad block can be caught (and retrieved again from
+// another node) as soon as it arrives:
+//
+//     /hello.zip:
+//       checksum: B57EB130
+//       block_size: 65536
+//       block_checksums:
+//         - B57EB130
+//       nodes:
+//         - 192.168.0.155:5550
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use crc::crc32;
+use digest::Digest;
+use yaml_rust::{Yaml, YamlLoader};
+use node::Node;
+
+// request path of the manifest on the root nodes
+pub const MANIFEST_PATH: &str = "/file_store.yaml";
+
+// block size used when block checksums are computed without one being given
+pub const DEFAULT_BLOCK_SIZE: u64 = 1024 * 1024;
+
+#[ derive( Clone, Debug, PartialEq ) ]
+pub struct FileEntry {
+    // digest of the whole file, a crc32 checksum unless another algorithm
+    // is named
+    pub checksum: Digest,
+    // crc32 checksums of each block of the file, if they are recorded
+    pub blocks: Option< BlockChecksums >,
+    // nodes which provide the file
+    pub nodes: Vec< Node >,
+}
+
+#[ derive( Clone, Debug, PartialEq ) ]
+pub struct BlockChecksums {
+    pub block_size: u64,
+    // checksum of block n, which covers the bytes from n * block_size up to
+    // (n + 1) * block_size or the end of the file
+    pub checksums: Vec< u32 >,
+}
+
+impl BlockChecksums {
+    pub fn compute( contents: &[u8], block_size: u64 ) -> BlockChecksums {
+        BlockChecksums {
+            block_size,
+            checksums: contents.chunks( block_size as usize )
+                .map( crc32::checksum_ieee )
+                .collect(),
+        }
+    }
+
+    // whether these checksums can describe a file of the given length
+    pub fn fits_length( &self, file_length: u64 ) -> bool {
+        file_length.div_ceil( self.block_size ) == self.checksums.len() as u64
+    }
+
+    // check the bytes of a range starting at a block boundary, returning the
+    // start offsets of the blocks which don't match
+    pub fn bad_blocks( &self, start_offset: u64, contents: &[u8] ) -> Vec< u64 > {
+        let first_block = start_offset / self.block_size;
+        contents.chunks( self.block_size as usize ).enumerate()
+            .filter( | &( index, block ) | {
+                self.checksums.get( first_block as usize + index ) !=
+                    Some( &crc32::checksum_ieee( block ) )
+            } )
+            .map( | ( index, _ ) | start_offset + index as u64 * self.block_size )
+            .collect()
+    }
+
+    // checksums sent as a single EDIT argument, 8 hex digits per block, so
+    // the argument count doesn't grow with the file
+    fn to_arg( &self ) -> String {
+        self.checksums.iter().map( | checksum | format!( "{:08X}", checksum ) ).collect()
+    }
+
+    fn from_args( block_size_arg: &str, checksums_arg: &str ) -> Result< BlockChecksums, String > {
+        let block_size = block_size_arg.parse::< u64 >()
+            .map_err( | why | format!( "invalid block size \"{}\": {}", block_size_arg, why ) )?;
+        if block_size == 0 {
+            return Err( String::from( "block size must be greater than zero" ) );
+        }
+        if !checksums_arg.len().is_multiple_of( 8 ) || !checksums_arg.is_ascii() {
+            return Err( String::from( "block checksums must be 8 hex digits each" ) );
+        }
+        let checksums = ( 0 .. checksums_arg.len() ).step_by( 8 )
+            .map( | start | {
+                let checksum_arg = &checksums_arg[ start .. start + 8 ];
+                u32::from_str_radix( checksum_arg, 16 ).map_err( | why | format!(
+                    "invalid block checksum \"{}\": {}", checksum_arg, why ) )
+            } )
+            .collect::< Result< Vec< u32 >, String > >()?;
+        Ok( BlockChecksums { block_size, checksums } )
+    }
+}
+
+#[ derive( Clone, Debug, Default, PartialEq ) ]
+pub struct Manifest {
+    // number of edits applied to the manifest, 0 if it has never been edited
+    pub version: u64,
+    // entries keyed by request path, e.g. /hello.txt
+    pub files: BTreeMap< String, FileEntry >,
+}
+
+// a single change to the manifest entry for one file
+#[ derive( Clone, Debug, PartialEq ) ]
+pub enum ManifestEdit {
+    // add an entry for a file which is not in the manifest yet
+    AddEntry( FileEntry ),
+    // remove the entry for a file
+    RemoveEntry,
+    // replace the nodes which provide a file
+    SetNodes( Vec< Node > ),
+    // replace the checksum of a file, which drops its block checksums
+    SetChecksum( Digest ),
+    // replace or (with None) drop the block checksums of a file
+    SetBlocks( Option< BlockChecksums > ),
+}
+
+impl ManifestEdit {
+    // arguments used to send the edit in an EDIT request, after the
+    // expected manifest version
+    pub fn to_args( &self ) -> Vec< String > {
+        let mut args = Vec::new();
+        match *self {
+            ManifestEdit::AddEntry( ref entry ) => {
+                args.push( String::from( "ADD" ) );
+                args.push( entry.checksum.to_string() );
+                args.extend( entry.nodes.iter().map( | node | node.to_string() ) );
+            },
+            ManifestEdit::RemoveEntry => args.push( String::from( "REMOVE" ) ),
+            ManifestEdit::SetNodes( ref nodes ) => {
+                args.push( String::from( "NODES" ) );
+                args.extend( nodes.iter().map( | node | node.to_string() ) );
+            },
+            ManifestEdit::SetChecksum( checksum ) => {
+                args.push( String::from( "CHECKSUM" ) );
+                args.push( checksum.to_string() );
+            },
+            ManifestEdit::SetBlocks( ref blocks ) => {
+                args.push( String::from( "BLOCKS" ) );
+                if let Some( ref blocks ) = *blocks {
+                    args.push( blocks.block_size.to_string() );
+                    args.push( blocks.to_arg() );
+                }
+            },
+        }
+        args
+    }
+
+    pub fn from_args( args: &[&str] ) -> Result< ManifestEdit, String > {
+        let parse_nodes = | node_args: &[&str] | -> Result< Vec< Node >, String > {
+            if node_args.is_empty() {
+                return Err( String::from( "at least one node is required" ) );
+            }
+            node_args.iter().map( | node | node.parse() ).collect()
+        };
+        let parse_checksum = | checksum_arg: &str | checksum_arg.parse::< Digest >();
+
+        match args.split_first() {
+            Some( ( &"ADD", rest ) ) if !rest.is_empty() => Ok( ManifestEdit::AddEntry(
+                FileEntry { checksum: parse_checksum( rest[ 0 ] )?,
+                            blocks: None,
+                            nodes: parse_nodes( &rest[ 1 .. ] )? } ) ),
+            Some( ( &"REMOVE", &[] ) ) => Ok( ManifestEdit::RemoveEntry ),
+            Some( ( &"NODES", rest ) ) => Ok( ManifestEdit::SetNodes( parse_nodes( rest )? ) ),
+            Some( ( &"CHECKSUM", rest ) ) if rest.len() == 1 =>
+                Ok( ManifestEdit::SetChecksum( parse_checksum( rest[ 0 ] )? ) ),
+            Some( ( &"BLOCKS", &[] ) ) => Ok( ManifestEdit::SetBlocks( None ) ),
+            Some( ( &"BLOCKS", &[ block_size, checksums ] ) ) => Ok( ManifestEdit::SetBlocks(
+                Some( BlockChecksums::from_args( block_size, checksums )? ) ) ),
+            _ => Err( format!(
+                "invalid manifest edit ({}), expected one of: ADD,checksum,node,..., \
+                 REMOVE, NODES,node,..., CHECKSUM,checksum, BLOCKS[,block_size,checksums]",
+                args.join( "," ) ) ),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn parse( contents: &str ) -> Result< Manifest, String > {
+        let docs = YamlLoader::load_from_str( contents )
+            .map_err( | why | format!( "could not parse file_store.yaml: {}", why ) )?;
+
+        let mut manifest = Manifest::default();
+        let file_store_map = match docs.first() {
+            Some( Yaml::Hash( file_store_map ) ) => file_store_map,
+            // an empty manifest has no files in it
+            None | Some( Yaml::Null ) => return Ok( manifest ),
+            Some( _ ) => return Err( String::from(
+                "file_store.yaml must map file names to file info" ) ),
+        };
+
+        for ( key_file, key_info ) in file_store_map {
+            let file_name = key_file.as_str().ok_or_else( || format!(
+                "file_store.yaml has a non-string file name: {:?}", key_file ) )?;
+            if file_name == "version" {
+                manifest.version = match *key_info {
+                    Yaml::Integer( version ) if version >= 0 => version as u64,
+                    _ => return Err( String::from(
+                        "file_store.yaml version must be a non-negative integer" ) ),
+                };
+                continue;
+            }
+            if !file_name.starts_with( '/' ) {
+                return Err( format!(
+                    "file_store.yaml file names must start with '/': {}", file_name ) );
+            }
+            manifest.files.insert( file_name.to_string(),
+                                   parse_entry( file_name, key_info )? );
+        }
+        Ok( manifest )
+    }
+
+    // serialize the manifest in the same layout as a hand-written
+    // file_store.yaml, with the file names quoted so that any name loads
+    // back as the same key
+    pub fn to_yaml( &self ) -> String {
+        let mut contents = String::new();
+        writeln!( contents, "version: {}", self.version ).unwrap();
+        for ( file_name, entry ) in self.files.iter() {
+            writeln!( contents, "{}:", quote( file_name ) ).unwrap();
+            writeln!( contents, "  checksum: {}", entry.checksum ).unwrap();
+            if let Some( ref blocks ) = entry.blocks {
+                writeln!( contents, "  block_size: {}", blocks.block_size ).unwrap();
+                writeln!( contents, "  block_checksums:" ).unwrap();
+                for checksum in blocks.checksums.iter() {
+                    writeln!( contents, "    - {:08X}", checksum ).unwrap();
+                }
+            }
+            writeln!( contents, "  nodes:" ).unwrap();
+            for node in entry.nodes.iter() {
+                writeln!( contents, "    - {}", node ).unwrap();
+            }
+        }
+        contents
+    }
+
+    pub fn entry( &self, file_name: &str ) -> Option< &FileEntry > {
+        self.files.get( file_name )
+    }
+
+    // apply an edit to the entry for file_name, bumping the version
+    pub fn apply_edit( &mut self, file_name: &str, edit: &ManifestEdit )
+                       -> Result< (), String > {
+        if !file_name.starts_with( '/' ) {
+            return Err( format!( "file names must start with '/': {}", file_name ) );
+        }
+        match *edit {
+            ManifestEdit::AddEntry( ref entry ) => {
+                if self.files.contains_key( file_name ) {
+                    return Err( format!( "{} is already in file_store.yaml", file_name ) );
+                }
+                self.files.insert( file_name.to_string(), entry.clone() );
+            },
+            ManifestEdit::RemoveEntry => {
+                if self.files.remove( file_name ).is_none() {
+                    return Err( format!( "{} is not in file_store.yaml", file_name ) );
+                }
+            },
+            ManifestEdit::SetNodes( ref nodes ) => match self.files.get_mut( file_name ) {
+                Some( entry ) => entry.nodes = nodes.clone(),
+                None => return Err( format!( "{} is not in file_store.yaml", file_name ) ),
+            },
+            ManifestEdit::SetChecksum( checksum ) => match self.files.get_mut( file_name ) {
+                Some( entry ) => {
+                    entry.checksum = checksum;
+                    entry.blocks = None;
+                },
+                None => return Err( format!( "{} is not in file_store.yaml", file_name ) ),
+            },
+            ManifestEdit::SetBlocks( ref blocks ) => match self.files.get_mut( file_name ) {
+                Some( entry ) => entry.blocks = blocks.clone(),
+                None => return Err( format!( "{} is not in file_store.yaml", file_name ) ),
+            },
+        }
+        self.version += 1;
+        Ok( () )
+    }
+}
+
+// a YAML double-quoted string holding value
+fn quote( value: &str ) -> String {
+    let mut quoted = String::from( "\"" );
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str( "\\\"" ),
+            '\\' => quoted.push_str( "\\\\" ),
+            c if c.is_control() => write!( quoted, "\\u{:04X}", c as u32 ).unwrap(),
+            c => quoted.push( c ),
+        }
+    }
+    quoted.push( '"' );
+    quoted
+}
+
+fn parse_entry( file_name: &str, key_info: &Yaml ) -> Result< FileEntry, String > {
+    let entry_err = | why: &str | format!( "file_store.yaml entry for {}: {}",
+                                           file_name, why );
+
+    // get checksum, an all-digit checksum such as 12345678 is loaded as an
+    // integer (and one such as 1234E567 as a float), but it is still hex
+    let checksum_str = match key_info[ "checksum" ] {
+        Yaml::String( ref checksum ) | Yaml::Real( ref checksum ) => checksum.clone(),
+        Yaml::Integer( checksum ) => checksum.to_string(),
+        _ => return Err( entry_err( "missing checksum" ) ),
+    };
+    let checksum = checksum_str.parse::< Digest >().map_err( | why | entry_err( &why ) )?;
+
+    // get block checksums, which are optional
+    let blocks = match ( &key_info[ "block_size" ], &key_info[ "block_checksums" ] ) {
+        ( Yaml::BadValue, Yaml::BadValue ) => None,
+        ( &Yaml::Integer( block_size ), Yaml::Array( checksum_list ) ) if block_size > 0 => {
+            let mut checksums = Vec::new();
+            for checksum in checksum_list {
+                let checksum_str = match *checksum {
+                    Yaml::String( ref checksum ) | Yaml::Real( ref checksum ) => checksum.clone(),
+                    Yaml::Integer( checksum ) => checksum.to_string(),
+                    _ => return Err( entry_err( "block checksums must be hex strings" ) ),
+                };
+                checksums.push( u32::from_str_radix( &checksum_str, 16 ).map_err( | why | {
+                    entry_err( &format!( "invalid block checksum \"{}\": {}", checksum_str, why ) )
+                } )? );
+            }
+            Some( BlockChecksums { block_size: block_size as u64, checksums } )
+        },
+        _ => return Err( entry_err(
+            "block_size must be a positive integer, given along with block_checksums" ) ),
+    };
+
+    // get nodes
+    let node_list = key_info[ "nodes" ].as_vec()
+        .ok_or_else( || entry_err( "missing node list" ) )?;
+    let mut nodes = Vec::new();
+    for node_string in node_list {
+        let node_string = node_string.as_str()
+            .ok_or_else( || entry_err( "nodes must be strings" ) )?;
+        nodes.push( node_string.parse().map_err( | why: String | entry_err( &why ) )? );
+    }
+    // as for an edit, a file nobody provides can't be retrieved or split
+    // between nodes
+    if nodes.is_empty() {
+        return Err( entry_err( "at least one node is required" ) );
+    }
+
+    Ok( FileEntry { checksum, blocks, nodes } )
+}
@@ -0,0 +1,269 @@
+extern crate fuser;
+extern crate libc;
+extern crate turtlefs;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsStr;
+use std::time::{Duration, UNIX_EPOCH};
+use fuser::{FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData,
+            ReplyDirectory, ReplyEntry, ReplyOpen, Request};
+use libc::{EIO, EISDIR, ENOENT, ENOTDIR, EROFS, O_ACCMODE, O_RDONLY};
+use turtlefs::manifest::{FileEntry, Manifest};
+use turtlefs::pool::ConnectionPool;
+use turtlefs::request;
+use turtlefs::TurtleClient;
+
+// how long the kernel may cache attributes and lookups
+const ATTR_TTL: Duration = Duration::from_secs( 1 );
+
+// inode of the mount point itself
+const ROOT_INODE: u64 = 1;
+
+// block size reported for files, reads are spread across a file's nodes
+// in blocks of this size
+const BLOCK_SIZE: u64 = 128 * 1024;
+
+enum Inode {
+    Directory {
+        parent: u64,
+        // entry name to inode
+        children: BTreeMap< String, u64 >,
+    },
+    File {
+        // request path, e.g. /dir/hello.txt
+        file_name: String,
+        entry: FileEntry,
+        // retrieved from the file's nodes the first time it is needed
+        length: Option< u64 >,
+    },
+}
+
+// read-only view of the files in file_store.yaml
+struct TurtleFs {
+    pool: ConnectionPool,
+    // inode n is inodes[ n - 1 ]
+    inodes: Vec< Inode >,
+}
+
+impl TurtleFs {
+    // build the directory tree from the manifest
+    fn new( manifest: &Manifest, pool: ConnectionPool ) -> TurtleFs {
+        let mut turtle_fs = TurtleFs {
+            pool,
+            inodes: vec![ Inode::Directory { parent: ROOT_INODE, children: BTreeMap::new() } ],
+        };
+
+        for ( file_name, entry ) in manifest.files.iter() {
+            let path_pieces: Vec< &str > = file_name.split( '/' )
+                .filter( | piece | !piece.is_empty() && *piece != "." )
+                .collect();
+            let ( file_piece, dir_pieces ) = match path_pieces.split_last() {
+                Some( pieces ) => pieces,
+                None => continue,
+            };
+
+            let mut dir_inode = ROOT_INODE;
+            for dir_piece in dir_pieces {
+                dir_inode = match turtle_fs.child( dir_inode, dir_piece ) {
+                    Some( inode ) => inode,
+                    None => turtle_fs.add_child( dir_inode, dir_piece, Inode::Directory {
+                        parent: dir_inode,
+                        children: BTreeMap::new(),
+                    } ),
+                };
+            }
+            if turtle_fs.child( dir_inode, file_piece ).is_none() {
+                turtle_fs.add_child( dir_inode, file_piece, Inode::File {
+                    file_name: file_name.clone(),
+                    entry: entry.clone(),
+                    length: None,
+                } );
+            } else {
+                eprintln!( "skipping {}, which clashes with a directory", file_name );
+            }
+        }
+        turtle_fs
+    }
+
+    fn inode( &self, inode: u64 ) -> Option< &Inode > {
+        if inode == 0 {
+            return None;
+        }
+        self.inodes.get( inode as usize - 1 )
+    }
+
+    fn child( &self, parent: u64, name: &str ) -> Option< u64 > {
+        match self.inode( parent ) {
+            Some( Inode::Directory { children, .. } ) => children.get( name ).cloned(),
+            _ => None,
+        }
+    }
+
+    fn add_child( &mut self, parent: u64, name: &str, child: Inode ) -> u64 {
+        self.inodes.push( child );
+        let inode = self.inodes.len() as u64;
+        if let Some( Inode::Directory { children, .. } ) =
+            self.inodes.get_mut( parent as usize - 1 ) {
+            children.insert( name.to_string(), inode );
+        }
+        inode
+    }
+
+    // file length, asking the file's nodes the first time
+    fn file_length( &mut self, inode: u64 ) -> Result< u64, i32 > {
+        let pool = &self.pool;
+        match self.inodes.get_mut( inode as usize - 1 ) {
+            Some( Inode::File { file_name, entry, length } ) => {
+                if length.is_none() {
+                    *length = request::request_length( pool, entry.nodes.clone(), file_name );
+                }
+                length.ok_or_else( || {
+                    eprintln!( "Could not retrieve file length for {}", file_name );
+                    EIO
+                } )
+            },
+            _ => Err( EISDIR ),
+        }
+    }
+
+    fn attr( &mut self, req: &Request, inode: u64 ) -> Result< FileAttr, i32 > {
+        let ( kind, perm, size ) = match self.inode( inode ) {
+            Some( Inode::Directory { .. } ) => ( FileType::Directory, 0o555, 0 ),
+            Some( Inode::File { .. } ) => ( FileType::RegularFile, 0o444,
+                                            self.file_length( inode )? ),
+            None => return Err( ENOENT ),
+        };
+        Ok( FileAttr {
+            ino: inode,
+            size,
+            blocks: size.div_ceil( 512 ),
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: req.uid(),
+            gid: req.gid(),
+            rdev: 0,
+            blksize: BLOCK_SIZE as u32,
+            flags: 0,
+        } )
+    }
+}
+
+impl Filesystem for TurtleFs {
+    fn lookup( &mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry ) {
+        let inode = match name.to_str().and_then( | name | self.child( parent, name ) ) {
+            Some( inode ) => inode,
+            None => return reply.error( ENOENT ),
+        };
+        match self.attr( req, inode ) {
+            Ok( attr ) => reply.entry( &ATTR_TTL, &attr, 0 ),
+            Err( why ) => reply.error( why ),
+        }
+    }
+
+    fn getattr( &mut self, req: &Request, inode: u64, _fh: Option< u64 >, reply: ReplyAttr ) {
+        match self.attr( req, inode ) {
+            Ok( attr ) => reply.attr( &ATTR_TTL, &attr ),
+            Err( why ) => reply.error( why ),
+        }
+    }
+
+    fn open( &mut self, _req: &Request, inode: u64, flags: i32, reply: ReplyOpen ) {
+        match self.inode( inode ) {
+            Some( Inode::File { .. } ) if flags & O_ACCMODE == O_RDONLY => reply.opened( 0, 0 ),
+            Some( Inode::File { .. } ) => reply.error( EROFS ),
+            Some( Inode::Directory { .. } ) => reply.error( EISDIR ),
+            None => reply.error( ENOENT ),
+        }
+    }
+
+    fn read( &mut self, _req: &Request, inode: u64, _fh: u64, offset: i64, size: u32,
+             _flags: i32, _lock_owner: Option< u64 >, reply: ReplyData ) {
+        let file_length = match self.file_length( inode ) {
+            Ok( file_length ) => file_length,
+            Err( why ) => return reply.error( why ),
+        };
+        let start_offset = offset as u64;
+        let end_offset = std::cmp::min( start_offset + size as u64, file_length );
+        if start_offset >= end_offset {
+            return reply.data( &[] );
+        }
+
+        let ( file_name, nodes ) = match self.inode( inode ) {
+            Some( Inode::File { file_name, entry, .. } ) => ( file_name, &entry.nodes ),
+            _ => return reply.error( ENOENT ),
+        };
+        // spread reads of different blocks across the file's nodes, falling
+        // back to the others if one fails
+        let primary_node = &nodes[ ( start_offset / BLOCK_SIZE ) as usize % nodes.len() ];
+        match request::request_file_chunk( &self.pool, primary_node, nodes.clone(),
+                                           file_name, start_offset, end_offset ) {
+            Some( file_chunk ) => reply.data( &file_chunk ),
+            None => {
+                eprintln!( "Could not retrieve file contents between {} and {} for {}",
+                           start_offset, end_offset, file_name );
+                reply.error( EIO );
+            },
+        }
+    }
+
+    fn readdir( &mut self, _req: &Request, inode: u64, _fh: u64, offset: i64,
+                mut reply: ReplyDirectory ) {
+        let ( parent, children ) = match self.inode( inode ) {
+            Some( Inode::Directory { parent, children } ) => ( *parent, children ),
+            Some( Inode::File { .. } ) => return reply.error( ENOTDIR ),
+            None => return reply.error( ENOENT ),
+        };
+
+        let mut entries = vec![ ( inode, FileType::Directory, "." ),
+                                ( parent, FileType::Directory, ".." ) ];
+        for ( name, child ) in children.iter() {
+            let kind = match self.inode( *child ) {
+                Some( Inode::Directory { .. } ) => FileType::Directory,
+                _ => FileType::RegularFile,
+            };
+            entries.push( ( *child, kind, name.as_str() ) );
+        }
+
+        // offset is the position after the last entry already returned
+        for ( position, ( child, kind, name ) ) in entries.into_iter().enumerate()
+            .skip( offset as usize ) {
+            if reply.add( child, position as i64 + 1, kind, name ) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!( "usage: {} <turtlefs-root> <mount-point>", args[ 0 ] );
+        std::process::exit( 2 );
+    }
+    let turtlefs_root = &args[ 1 ];
+    let mount_point = &args[ 2 ];
+
+    // the directory tree is built once, from the manifest at mount time
+    let manifest = TurtleClient::from_root( turtlefs_root )
+        .and_then( | client | client.manifest() )
+        .unwrap_or_else( | why | panic!( "{}", why ) );
+    // file contents are read over TLS and with signed requests if
+    // turtlefs-root says to
+    let pool = ConnectionPool::from_root( turtlefs_root )
+        .unwrap_or_else( | why | panic!( "{}", why ) );
+    let turtle_fs = TurtleFs::new( &manifest, pool );
+
+    let options = [ MountOption::RO, MountOption::FSName( String::from( "turtlefs" ) ),
+                    MountOption::Subtype( String::from( "turtlefs" ) ) ];
+    println!( "Mounting {} files from {} on {}", manifest.files.len(), turtlefs_root,
+              mount_point );
+    fuser::mount2( turtle_fs, mount_point, &options )
+        .unwrap_or_else( | why | panic!( "Could not mount {}: {}", mount_point, why ) );
+}
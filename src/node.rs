@@ -0,0 +1,60 @@
+// Nodes (IP:TCP_PORT pairs) and the root node list in nodes.yaml.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::str::FromStr;
+use yaml_rust::YamlLoader;
+
+#[ derive( Clone, Debug, PartialEq, Eq, Hash ) ]
+pub struct Node {
+    pub ip: String,
+    pub port: String,
+}
+
+impl fmt::Display for Node {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        fmt.write_str( &format!( "{}:{}", self.ip, self.port ) )?;
+        Ok( () )
+    }
+}
+
+impl FromStr for Node {
+    type Err = String;
+
+    // parse a node in the form IP:TCP_PORT
+    fn from_str( node_string: &str ) -> Result< Node, String > {
+        let node_info: Vec< &str > = node_string.rsplitn( 2, ':' ).collect();
+        if node_info.len() != 2 || node_info[ 0 ].is_empty() || node_info[ 1 ].is_empty() {
+            return Err( format!( "invalid node \"{}\", expected IP:TCP_PORT",
+                                 node_string ) );
+        }
+        Ok( Node { ip: node_info[ 1 ].to_string(),
+                   port: node_info[ 0 ].to_string() } )
+    }
+}
+
+// read the root nodes (the nodes holding file_store.yaml) from
+// turtlefs-root/nodes.yaml
+pub fn retrieve_root_nodes( turtlefs_root: &str ) -> Result< Vec< Node >, String > {
+    let nodes_path: PathBuf = [ turtlefs_root, "nodes.yaml" ].iter().collect();
+    let mut contents = String::new();
+    File::open( &nodes_path )
+        .and_then( | mut f | f.read_to_string( &mut contents ) )
+        .map_err( | why | format!( "could not read {}: {}", nodes_path.display(), why ) )?;
+
+    let node_yaml = YamlLoader::load_from_str( &contents )
+        .map_err( | why | format!( "could not parse {}: {}", nodes_path.display(), why ) )?;
+    let node_list = node_yaml.first().and_then( | doc | doc.as_vec() )
+        .ok_or_else( || format!( "{} must be a list of nodes", nodes_path.display() ) )?;
+
+    let mut root_nodes = Vec::new();
+    for node_string in node_list {
+        let node_string = node_string.as_str()
+            .ok_or_else( || format!( "{} must be a list of nodes", nodes_path.display() ) )?;
+        root_nodes.push( node_string.parse()? );
+    }
+
+    Ok( root_nodes )
+}
@@ -0,0 +1,207 @@
+// Pool of open connections to nodes, reused across requests.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use openssl::ssl::{SslConnector, SslStream};
+use node::Node;
+use signing::{KeyRing, SigningKey};
+use tls::TlsSettings;
+
+// pooled connections left unused for longer than this are closed
+pub const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs( 30 );
+
+// maximum number of idle connections kept open to a single node
+pub const POOL_MAX_IDLE_PER_NODE: usize = 8;
+
+// longest wait for a node to accept a new connection
+pub const POOL_CONNECT_TIMEOUT: Duration = Duration::from_secs( 5 );
+
+// longest wait for a node to send or accept any bytes of a request or
+// response, well past the longest a node takes to answer an EDIT
+pub const POOL_IO_TIMEOUT: Duration = Duration::from_secs( 30 );
+
+// a connection to a node, encrypted if the pool connects over TLS
+pub enum Connection {
+    Plain( TcpStream ),
+    Tls( SslStream< TcpStream > ),
+}
+
+impl Connection {
+    fn tcp_stream( &self ) -> &TcpStream {
+        match *self {
+            Connection::Plain( ref stream ) => stream,
+            Connection::Tls( ref stream ) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read( &mut self, buf: &mut [u8] ) -> io::Result< usize > {
+        match *self {
+            Connection::Plain( ref mut stream ) => stream.read( buf ),
+            Connection::Tls( ref mut stream ) => stream.read( buf ),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write( &mut self, buf: &[u8] ) -> io::Result< usize > {
+        match *self {
+            Connection::Plain( ref mut stream ) => stream.write( buf ),
+            Connection::Tls( ref mut stream ) => stream.write( buf ),
+        }
+    }
+
+    fn flush( &mut self ) -> io::Result< () > {
+        match *self {
+            Connection::Plain( ref mut stream ) => stream.flush(),
+            Connection::Tls( ref mut stream ) => stream.flush(),
+        }
+    }
+}
+
+struct IdleConnection {
+    stream: Connection,
+    last_used: Instant,
+}
+
+// keeps connections to nodes open between requests so that they can be
+// reused instead of paying for a new TCP handshake every time
+pub struct ConnectionPool {
+    idle_connections: Mutex< HashMap< Node, Vec< IdleConnection > > >,
+    idle_timeout: Duration,
+    max_idle_per_node: usize,
+    connect_timeout: Duration,
+    io_timeout: Duration,
+    // TLS setup for new connections, which are plaintext without one
+    tls: Option< SslConnector >,
+    // key requests are signed with, if any
+    signing_key: Option< SigningKey >,
+}
+
+impl ConnectionPool {
+    pub fn new( idle_timeout: Duration, max_idle_per_node: usize ) -> ConnectionPool {
+        ConnectionPool {
+            idle_connections: Mutex::new( HashMap::new() ),
+            idle_timeout,
+            max_idle_per_node,
+            connect_timeout: POOL_CONNECT_TIMEOUT,
+            io_timeout: POOL_IO_TIMEOUT,
+            tls: None,
+            signing_key: None,
+        }
+    }
+
+    // a pool set up as turtlefs-root says, connecting over TLS if
+    // turtlefs-root/tls.yaml is present and signing requests if
+    // turtlefs-root/keys.yaml names a signing_key
+    pub fn from_root( turtlefs_root: &str ) -> Result< ConnectionPool, String > {
+        let mut pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE );
+        if let Some( tls_settings ) = TlsSettings::load( turtlefs_root )? {
+            pool = pool.with_tls( tls_settings.connector()? );
+        }
+        if let Some( signing_key ) = KeyRing::load( turtlefs_root )?
+            .and_then( | key_ring | key_ring.signing_key ) {
+            pool = pool.with_signing_key( signing_key );
+        }
+        Ok( pool )
+    }
+
+    // give up on a node which takes longer than connect_timeout to accept
+    // a connection, or longer than io_timeout to send or accept any bytes
+    pub fn with_timeouts( mut self, connect_timeout: Duration, io_timeout: Duration )
+                          -> ConnectionPool {
+        self.connect_timeout = connect_timeout;
+        self.io_timeout = io_timeout;
+        self
+    }
+
+    // connect to nodes over TLS
+    pub fn with_tls( mut self, connector: SslConnector ) -> ConnectionPool {
+        self.tls = Some( connector );
+        self
+    }
+
+    // sign every request sent through the pool
+    pub fn with_signing_key( mut self, signing_key: SigningKey ) -> ConnectionPool {
+        self.signing_key = Some( signing_key );
+        self
+    }
+
+    pub fn signing_key( &self ) -> Option< &SigningKey > {
+        self.signing_key.as_ref()
+    }
+
+    // open a new connection to the node, checking its certificate against
+    // the node's address when connecting over TLS
+    pub fn connect( &self, node: &Node ) -> io::Result< Connection > {
+        let stream = self.connect_tcp( node )?;
+        stream.set_read_timeout( Some( self.io_timeout ) )?;
+        stream.set_write_timeout( Some( self.io_timeout ) )?;
+        let connector = match self.tls {
+            Some( ref connector ) => connector,
+            None => return Ok( Connection::Plain( stream ) ),
+        };
+        let host = node.ip.trim_start_matches( '[' ).trim_end_matches( ']' );
+        connector.connect( host, stream )
+            .map( Connection::Tls )
+            .map_err( | why | io::Error::other( format!( "TLS handshake failed: {}", why ) ) )
+    }
+
+    // connect to the first of the node's addresses which answers in time
+    fn connect_tcp( &self, node: &Node ) -> io::Result< TcpStream > {
+        let mut last_error = io::Error::new( io::ErrorKind::InvalidInput,
+                                             format!( "{} has no addresses", node ) );
+        for address in node.to_string().to_socket_addrs()? {
+            match TcpStream::connect_timeout( &address, self.connect_timeout ) {
+                Ok( stream ) => return Ok( stream ),
+                Err( why ) => last_error = why,
+            }
+        }
+        Err( last_error )
+    }
+
+    // take a healthy idle connection to the node if there is one,
+    // returning whether or not it was reused
+    pub fn checkout( &self, node: &Node ) -> io::Result< ( Connection, bool ) > {
+        let mut idle_connections = self.idle_connections.lock().unwrap();
+        if let Some( connections ) = idle_connections.get_mut( node ) {
+            while let Some( connection ) = connections.pop() {
+                if connection.last_used.elapsed() < self.idle_timeout &&
+                    is_healthy( connection.stream.tcp_stream() ) {
+                    return Ok( ( connection.stream, true ) );
+                }
+            }
+        }
+        drop( idle_connections );
+
+        self.connect( node ).map( | stream | ( stream, false ) )
+    }
+
+    // hand a connection back to the pool once its response has been read
+    pub fn checkin( &self, node: &Node, stream: Connection ) {
+        let mut idle_connections = self.idle_connections.lock().unwrap();
+        let idle_timeout = self.idle_timeout;
+        let connections = idle_connections.entry( node.clone() ).or_default();
+        connections.retain( | connection | connection.last_used.elapsed() < idle_timeout );
+        if connections.len() < self.max_idle_per_node {
+            connections.push( IdleConnection { stream, last_used: Instant::now() } );
+        }
+    }
+}
+
+// an idle connection is healthy if the server has neither closed it nor
+// sent anything unexpected on it
+fn is_healthy( stream: &TcpStream ) -> bool {
+    if stream.set_nonblocking( true ).is_err() {
+        return false;
+    }
+    let healthy = match stream.peek( &mut [ 0u8; 1 ] ) {
+        Err( ref why ) => why.kind() == io::ErrorKind::WouldBlock,
+        Ok( _ ) => false,
+    };
+    healthy && stream.set_nonblocking( false ).is_ok()
+}
@@ -0,0 +1,381 @@
+// Framed binary wire protocol shared by the client and server.
+//
+// All integers are big-endian.
+//
+// request frame:
+//   magic "TFS" (3) | version (1) | opcode (1) | arg count (1) |
+//   path length (2) | args length (4) | payload length (8) |
+//   path | args | payload
+// where each arg is encoded as: arg length (2) | arg bytes
+//
+// response frame:
+//   magic "TFS" (3) | version (1) | status (1) | payload length (8) | payload
+//
+// Any number of requests may be sent over a single connection, each one
+// answered by exactly one response in order.
+//
+// The legacy text format (/filename:(action,arg1,arg2,...) followed by the
+// payload, terminated by closing the write half of the connection) is still
+// accepted by the server. Legacy requests always begin with '/', which can
+// never be the first byte of a frame.
+//
+// Either format may be sent inside a TLS connection, which the server tells
+// apart by the first byte of the TLS handshake.
+//
+// A signed frame has SIGNED_FLAG set in its opcode, and its last four args
+// are the signature rather than the action's own (see src/signing.rs).
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: &[u8; 3] = b"TFS";
+pub const VERSION: u8 = 1;
+
+pub const REQUEST_HEADER_LEN: usize = 20;
+pub const RESPONSE_HEADER_LEN: usize = 13;
+
+// set in the opcode of a signed request frame
+pub const SIGNED_FLAG: u8 = 0x80;
+
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum Opcode {
+    Read = 1,
+    Length = 2,
+    Write = 3,
+    Edit = 4,
+    // consensus messages exchanged between the root nodes
+    Vote = 5,
+    Append = 6,
+    Stat = 7,
+    List = 8,
+    Delete = 9,
+    Checksum = 10,
+    // sent by the consensus leader in place of log entries it has compacted
+    Snapshot = 11,
+}
+
+impl Opcode {
+    pub fn from_u8( opcode: u8 ) -> Option< Opcode > {
+        match opcode {
+            1 => Some( Opcode::Read ),
+            2 => Some( Opcode::Length ),
+            3 => Some( Opcode::Write ),
+            4 => Some( Opcode::Edit ),
+            5 => Some( Opcode::Vote ),
+            6 => Some( Opcode::Append ),
+            7 => Some( Opcode::Stat ),
+            8 => Some( Opcode::List ),
+            9 => Some( Opcode::Delete ),
+            10 => Some( Opcode::Checksum ),
+            11 => Some( Opcode::Snapshot ),
+            _ => None,
+        }
+    }
+
+    // action name used by the legacy text format and in log messages
+    pub fn from_name( name: &str ) -> Option< Opcode > {
+        match name {
+            "READ" => Some( Opcode::Read ),
+            "LENGTH" => Some( Opcode::Length ),
+            "WRITE" => Some( Opcode::Write ),
+            "EDIT" => Some( Opcode::Edit ),
+            "VOTE" => Some( Opcode::Vote ),
+            "APPEND" => Some( Opcode::Append ),
+            "STAT" => Some( Opcode::Stat ),
+            "LIST" => Some( Opcode::List ),
+            "DELETE" => Some( Opcode::Delete ),
+            "CHECKSUM" => Some( Opcode::Checksum ),
+            "SNAPSHOT" => Some( Opcode::Snapshot ),
+            _ => None,
+        }
+    }
+
+    pub fn name( self ) -> &'static str {
+        match self {
+            Opcode::Read => "READ",
+            Opcode::Length => "LENGTH",
+            Opcode::Write => "WRITE",
+            Opcode::Edit => "EDIT",
+            Opcode::Vote => "VOTE",
+            Opcode::Append => "APPEND",
+            Opcode::Stat => "STAT",
+            Opcode::List => "LIST",
+            Opcode::Delete => "DELETE",
+            Opcode::Checksum => "CHECKSUM",
+            Opcode::Snapshot => "SNAPSHOT",
+        }
+    }
+}
+
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum Status {
+    Ok = 0,
+    Error = 1,
+    // the requested path contains a ".." component
+    PathTraversal = 2,
+    // the requested path contains an absolute component or drive prefix
+    AbsolutePath = 3,
+    // the requested path resolves outside of the store through a symlink
+    SymlinkEscape = 4,
+    // the requested path contains a NUL byte
+    NulByte = 5,
+    // a manifest edit was made against an out of date version of the
+    // manifest, the payload holds the current version
+    VersionConflict = 6,
+    // the root node is not the consensus leader, the payload holds the
+    // leader's address if it is known
+    NotLeader = 7,
+    // the node only accepts requests over TLS
+    TlsRequired = 8,
+    // the node only accepts signed requests
+    Unsigned = 9,
+    // the request's nonce was already used, or its timestamp is too far
+    // from the node's clock
+    Replayed = 10,
+    // the request's signature is malformed, made with an unknown key or
+    // doesn't match the request
+    BadSignature = 11,
+    // the request's key (or an unsigned request) lacks the permission the
+    // action needs on the path
+    Forbidden = 12,
+}
+
+impl Status {
+    pub fn from_u8( status: u8 ) -> Option< Status > {
+        match status {
+            0 => Some( Status::Ok ),
+            1 => Some( Status::Error ),
+            2 => Some( Status::PathTraversal ),
+            3 => Some( Status::AbsolutePath ),
+            4 => Some( Status::SymlinkEscape ),
+            5 => Some( Status::NulByte ),
+            6 => Some( Status::VersionConflict ),
+            7 => Some( Status::NotLeader ),
+            8 => Some( Status::TlsRequired ),
+            9 => Some( Status::Unsigned ),
+            10 => Some( Status::Replayed ),
+            11 => Some( Status::BadSignature ),
+            12 => Some( Status::Forbidden ),
+            _ => None,
+        }
+    }
+}
+
+// fixed-size part of a request frame
+#[ derive( Clone, Debug ) ]
+pub struct RequestHeader {
+    pub opcode: u8,
+    pub arg_count: u8,
+    pub path_length: u16,
+    pub args_length: u32,
+    pub payload_length: u64,
+}
+
+impl RequestHeader {
+    // number of bytes taken up by the path and args after the header
+    pub fn body_length( &self ) -> usize {
+        self.path_length as usize + self.args_length as usize
+    }
+}
+
+// fixed-size part of a response frame
+#[ derive( Clone, Debug ) ]
+pub struct ResponseHeader {
+    pub status: u8,
+    pub payload_length: u64,
+}
+
+fn check_magic( buf: &[u8] ) -> Result< (), String > {
+    if &buf[ 0 .. 3 ] != MAGIC {
+        return Err( String::from( "invalid frame: bad magic bytes" ) );
+    }
+    if buf[ 3 ] != VERSION {
+        return Err( format!( "unsupported protocol version: {} (expected {})",
+                             buf[ 3 ], VERSION ) );
+    }
+    Ok( () )
+}
+
+fn read_u16( buf: &[u8] ) -> u16 {
+    let mut bytes = [ 0u8; 2 ];
+    bytes.copy_from_slice( &buf[ .. 2 ] );
+    u16::from_be_bytes( bytes )
+}
+
+fn read_u32( buf: &[u8] ) -> u32 {
+    let mut bytes = [ 0u8; 4 ];
+    bytes.copy_from_slice( &buf[ .. 4 ] );
+    u32::from_be_bytes( bytes )
+}
+
+fn read_u64( buf: &[u8] ) -> u64 {
+    let mut bytes = [ 0u8; 8 ];
+    bytes.copy_from_slice( &buf[ .. 8 ] );
+    u64::from_be_bytes( bytes )
+}
+
+// a length which doesn't fit in its field, which would otherwise be
+// truncated into a frame the other end misparses
+fn too_long( what: &str, length: usize ) -> io::Error {
+    io::Error::new( io::ErrorKind::InvalidInput,
+                    format!( "{} of {} is too long for a frame", what, length ) )
+}
+
+// encode a list of strings, each as: length (2) | bytes
+pub fn encode_args( args: &[String] ) -> io::Result< Vec< u8 > > {
+    let mut encoded_args = Vec::new();
+    for arg in args {
+        let arg_length = u16::try_from( arg.len() )
+            .map_err( | _ | too_long( "argument length", arg.len() ) )?;
+        encoded_args.extend_from_slice( &arg_length.to_be_bytes() );
+        encoded_args.extend_from_slice( arg.as_bytes() );
+    }
+    Ok( encoded_args )
+}
+
+// decode arg_count strings encoded by encode_args from the front of buf,
+// leaving buf pointing at whatever follows them
+pub fn decode_args( buf: &mut &[u8], arg_count: usize ) -> Result< Vec< String >, String > {
+    let mut args = Vec::new();
+    for _ in 0 .. arg_count {
+        if buf.len() < 2 {
+            return Err( String::from( "invalid frame: truncated argument" ) );
+        }
+        let arg_length = read_u16( buf ) as usize;
+        if buf.len() < 2 + arg_length {
+            return Err( String::from( "invalid frame: truncated argument" ) );
+        }
+        let arg = String::from_utf8( buf[ 2 .. 2 + arg_length ].to_vec() )
+            .map_err( | _ | String::from(
+                "invalid frame: argument is not valid UTF-8" ) )?;
+        args.push( arg );
+        *buf = &buf[ 2 + arg_length .. ];
+    }
+    Ok( args )
+}
+
+// encode a request frame, including its payload
+pub fn encode_request( opcode: Opcode, path: &str, args: &[String],
+                       payload: &[u8] ) -> io::Result< Vec< u8 > > {
+    let encoded_args = encode_args( args )?;
+    let arg_count = u8::try_from( args.len() )
+        .map_err( | _ | too_long( "argument count", args.len() ) )?;
+    let path_length = u16::try_from( path.len() )
+        .map_err( | _ | too_long( "path length", path.len() ) )?;
+    let args_length = u32::try_from( encoded_args.len() )
+        .map_err( | _ | too_long( "arguments length", encoded_args.len() ) )?;
+
+    let mut frame = Vec::with_capacity(
+        REQUEST_HEADER_LEN + path.len() + encoded_args.len() + payload.len() );
+    frame.extend_from_slice( MAGIC );
+    frame.push( VERSION );
+    frame.push( opcode as u8 );
+    frame.push( arg_count );
+    frame.extend_from_slice( &path_length.to_be_bytes() );
+    frame.extend_from_slice( &args_length.to_be_bytes() );
+    frame.extend_from_slice( &( payload.len() as u64 ).to_be_bytes() );
+    frame.extend_from_slice( path.as_bytes() );
+    frame.extend_from_slice( &encoded_args );
+    frame.extend_from_slice( payload );
+    Ok( frame )
+}
+
+// encode a signed request frame, with the signature args after the
+// action's own
+pub fn encode_signed_request( opcode: Opcode, path: &str, args: &[String],
+                              signature_args: &[String], payload: &[u8] )
+                              -> io::Result< Vec< u8 > > {
+    let mut signed_args = args.to_vec();
+    signed_args.extend_from_slice( signature_args );
+    let mut frame = encode_request( opcode, path, &signed_args, payload )?;
+    frame[ 4 ] |= SIGNED_FLAG;
+    Ok( frame )
+}
+
+pub fn decode_request_header( buf: &[u8] ) -> Result< RequestHeader, String > {
+    if buf.len() < REQUEST_HEADER_LEN {
+        return Err( String::from( "invalid frame: truncated request header" ) );
+    }
+    check_magic( buf )?;
+    Ok( RequestHeader {
+        opcode: buf[ 4 ],
+        arg_count: buf[ 5 ],
+        path_length: read_u16( &buf[ 6 .. ] ),
+        args_length: read_u32( &buf[ 8 .. ] ),
+        payload_length: read_u64( &buf[ 12 .. ] ),
+    } )
+}
+
+// decode the path and args which follow the request header
+pub fn decode_request_body( header: &RequestHeader, buf: &[u8] )
+                            -> Result< ( String, Vec< String > ), String > {
+    if buf.len() != header.body_length() {
+        return Err( String::from( "invalid frame: truncated request body" ) );
+    }
+    let ( path_bytes, mut args_bytes ) = buf.split_at( header.path_length as usize );
+    let path = String::from_utf8( path_bytes.to_vec() )
+        .map_err( | _ | String::from( "invalid frame: path is not valid UTF-8" ) )?;
+
+    let args = decode_args( &mut args_bytes, header.arg_count as usize )?;
+    if !args_bytes.is_empty() {
+        return Err( String::from( "invalid frame: trailing argument bytes" ) );
+    }
+    Ok( ( path, args ) )
+}
+
+pub fn encode_response_header( status: Status, payload_length: u64 ) -> Vec< u8 > {
+    let mut frame = Vec::with_capacity( RESPONSE_HEADER_LEN );
+    frame.extend_from_slice( MAGIC );
+    frame.push( VERSION );
+    frame.push( status as u8 );
+    frame.extend_from_slice( &payload_length.to_be_bytes() );
+    frame
+}
+
+// encode a response frame, including its payload
+pub fn encode_response( status: Status, payload: &[u8] ) -> Vec< u8 > {
+    let mut frame = encode_response_header( status, payload.len() as u64 );
+    frame.extend_from_slice( payload );
+    frame
+}
+
+pub fn decode_response_header( buf: &[u8] ) -> Result< ResponseHeader, String > {
+    if buf.len() < RESPONSE_HEADER_LEN {
+        return Err( String::from( "invalid frame: truncated response header" ) );
+    }
+    check_magic( buf )?;
+    Ok( ResponseHeader {
+        status: buf[ 4 ],
+        payload_length: read_u64( &buf[ 5 .. ] ),
+    } )
+}
+
+// send a request over a blocking stream, signed if signature args are given
+pub fn write_request< W: Write >( stream: &mut W, opcode: Opcode, path: &str,
+                                  args: &[String], signature_args: Option< &[String] >,
+                                  payload: &[u8] ) -> io::Result< () > {
+    let frame = match signature_args {
+        Some( signature_args ) =>
+            encode_signed_request( opcode, path, args, signature_args, payload ),
+        None => encode_request( opcode, path, args, payload ),
+    }?;
+    stream.write_all( &frame )?;
+    stream.flush()
+}
+
+// read a response from a blocking stream into response_buffer,
+// returning the response status
+pub fn read_response< R: Read >( stream: &mut R, response_buffer: &mut Vec< u8 > )
+                                 -> io::Result< u8 > {
+    let mut header_buf = [ 0u8; RESPONSE_HEADER_LEN ];
+    stream.read_exact( &mut header_buf )?;
+    let header = decode_response_header( &header_buf )
+        .map_err( | why | io::Error::new( io::ErrorKind::InvalidData, why ) )?;
+    let bytes_read = stream.take( header.payload_length )
+        .read_to_end( response_buffer )?;
+    if ( bytes_read as u64 ) < header.payload_length {
+        return Err( io::Error::new( io::ErrorKind::UnexpectedEof,
+                                    "connection closed mid-response" ) );
+    }
+    Ok( header.status )
+}
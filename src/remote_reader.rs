@@ -0,0 +1,185 @@
+// RemoteReader, a Read + Seek view of a single remote file.
+//
+// The file is split into fixed-size blocks which are retrieved on demand
+// with range READ requests. A read which misses the cache also retrieves
+// the next read_ahead blocks in the same request, and recently used blocks
+// are kept so that seeking back and forth doesn't retrieve them again.
+
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
+use node::Node;
+use pool::ConnectionPool;
+use request;
+
+// size of the blocks a file is retrieved and cached in
+pub const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024;
+
+// blocks retrieved past the one being read
+pub const DEFAULT_READ_AHEAD: u64 = 2;
+
+// most blocks held in the cache at once
+pub const DEFAULT_CACHED_BLOCKS: usize = 64;
+
+pub struct RemoteReader {
+    pool: Arc< ConnectionPool >,
+    file_name: String,
+    nodes: Vec< Node >,
+    length: u64,
+    position: u64,
+    block_size: u64,
+    read_ahead: u64,
+    max_cached_blocks: usize,
+    // cached blocks by block index, along with the indexes from least to
+    // most recently used
+    blocks: HashMap< u64, Vec< u8 > >,
+    block_order: VecDeque< u64 >,
+}
+
+impl RemoteReader {
+    // read file_name, of the given length, from nodes, of which there must
+    // be at least one
+    pub fn new( pool: Arc< ConnectionPool >, file_name: &str, nodes: Vec< Node >,
+                length: u64 ) -> io::Result< RemoteReader > {
+        if nodes.is_empty() {
+            return Err( io::Error::new( io::ErrorKind::InvalidInput,
+                                        format!( "No nodes to read {} from", file_name ) ) );
+        }
+        Ok( RemoteReader {
+            pool,
+            file_name: file_name.to_string(),
+            nodes,
+            length,
+            position: 0,
+            block_size: DEFAULT_BLOCK_SIZE,
+            read_ahead: DEFAULT_READ_AHEAD,
+            max_cached_blocks: DEFAULT_CACHED_BLOCKS,
+            blocks: HashMap::new(),
+            block_order: VecDeque::new(),
+        } )
+    }
+
+    // change the block size, which drops any cached blocks
+    pub fn with_block_size( mut self, block_size: u64 ) -> RemoteReader {
+        self.block_size = cmp::max( block_size, 1 );
+        self.blocks.clear();
+        self.block_order.clear();
+        self
+    }
+
+    pub fn with_read_ahead( mut self, read_ahead: u64 ) -> RemoteReader {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    pub fn with_cached_blocks( mut self, max_cached_blocks: usize ) -> RemoteReader {
+        self.max_cached_blocks = cmp::max( max_cached_blocks, 1 );
+        self
+    }
+
+    pub fn len( &self ) -> u64 {
+        self.length
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.length == 0
+    }
+
+    fn num_blocks( &self ) -> u64 {
+        self.length.div_ceil( self.block_size )
+    }
+
+    // mark a cached block as the most recently used
+    fn touch_block( &mut self, index: u64 ) {
+        if let Some( position ) = self.block_order.iter().position( | i | *i == index ) {
+            self.block_order.remove( position );
+        }
+        self.block_order.push_back( index );
+    }
+
+    fn cache_block( &mut self, index: u64, block: Vec< u8 > ) {
+        self.blocks.insert( index, block );
+        self.touch_block( index );
+        while self.block_order.len() > self.max_cached_blocks {
+            if let Some( oldest ) = self.block_order.pop_front() {
+                self.blocks.remove( &oldest );
+            }
+        }
+    }
+
+    // make sure block index is cached, retrieving it along with any
+    // uncached blocks up to read_ahead past it
+    fn load_block( &mut self, index: u64 ) -> io::Result< () > {
+        if self.blocks.contains_key( &index ) {
+            self.touch_block( index );
+            return Ok( () );
+        }
+
+        // never read ahead further than the cache can hold
+        let read_ahead = cmp::min( self.read_ahead, self.max_cached_blocks as u64 - 1 );
+        let mut end_index = index + 1;
+        while end_index < cmp::min( index + 1 + read_ahead, self.num_blocks() ) &&
+            !self.blocks.contains_key( &end_index ) {
+            end_index += 1;
+        }
+
+        let start_offset = index * self.block_size;
+        let end_offset = cmp::min( end_index * self.block_size, self.length );
+        // spread blocks across the file's nodes, falling back to the others
+        let primary_node = &self.nodes[ index as usize % self.nodes.len() ];
+        let contents = request::request_file_chunk( &self.pool, primary_node, self.nodes.clone(),
+                                                    &self.file_name, start_offset, end_offset )
+            .ok_or_else( || io::Error::other( format!(
+                "Could not retrieve file contents between {} and {} for {}",
+                start_offset, end_offset, self.file_name ) ) )?;
+
+        // load the requested block last so it is the most recently used
+        let blocks: Vec< Vec< u8 > > = contents.chunks( self.block_size as usize )
+            .map( | block | block.to_vec() )
+            .collect();
+        for ( offset, block ) in blocks.into_iter().enumerate().rev() {
+            self.cache_block( index + offset as u64, block );
+        }
+        Ok( () )
+    }
+}
+
+impl Read for RemoteReader {
+    fn read( &mut self, buf: &mut [u8] ) -> io::Result< usize > {
+        if buf.is_empty() || self.position >= self.length {
+            return Ok( 0 );
+        }
+
+        let index = self.position / self.block_size;
+        self.load_block( index )?;
+        let block = &self.blocks[ &index ];
+        let block_offset = ( self.position - index * self.block_size ) as usize;
+        let bytes_read = cmp::min( buf.len(), block.len() - block_offset );
+        buf[ .. bytes_read ].copy_from_slice( &block[ block_offset .. block_offset + bytes_read ] );
+        self.position += bytes_read as u64;
+        Ok( bytes_read )
+    }
+}
+
+impl Seek for RemoteReader {
+    // seeking past the end is allowed, and reads there return nothing
+    fn seek( &mut self, pos: SeekFrom ) -> io::Result< u64 > {
+        let ( base, offset ) = match pos {
+            SeekFrom::Start( offset ) => {
+                self.position = offset;
+                return Ok( offset );
+            },
+            SeekFrom::End( offset ) => ( self.length, offset ),
+            SeekFrom::Current( offset ) => ( self.position, offset ),
+        };
+        match base.checked_add_signed( offset ) {
+            Some( position ) => {
+                self.position = position;
+                Ok( position )
+            },
+            None => Err( io::Error::new( io::ErrorKind::InvalidInput,
+                                         "invalid seek to a negative or overflowing position" ) ),
+        }
+    }
+}
@@ -0,0 +1,617 @@
+// Requests to nodes, with retries across the nodes providing a file.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::str;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+use rand::{self, Rng};
+use digest::{Algorithm, Digest, Hasher};
+use listing::{ListEntry, Listing};
+use manifest::{MANIFEST_PATH, BlockChecksums, FileEntry, Manifest, ManifestEdit};
+use node::Node;
+use pool::{Connection, ConnectionPool};
+use protocol::{self, Opcode, Status};
+use stat::FileStat;
+
+// maximum number of file chunks requested at the same time
+pub const MAX_PARALLEL_CHUNKS: usize = 8;
+
+// attempts at finding the consensus leader among the root nodes, and the
+// delay between them, long enough in total to cover an election
+pub const LEADER_RETRIES: u32 = 20;
+pub const LEADER_RETRY_DELAY: Duration = Duration::from_millis( 250 );
+
+// largest file chunk requested in one go, which bounds how much of a file
+// is held in memory while it is retrieved
+pub const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+#[ derive( Clone ) ]
+pub struct Request<'a> {
+    pub node: &'a Node,
+    pub opcode: Opcode,
+    pub file_name: &'a str,
+    pub args: Vec< String >,
+    pub payload: &'a [u8],
+}
+
+#[ derive( Clone ) ]
+pub struct Response<'a> {
+    pub status: u8,
+    pub message: &'a [u8],
+}
+
+pub fn perform_request<'a>( pool: &ConnectionPool, request: Request,
+                        response_buffer: &'a mut Vec<u8> ) -> Response<'a> {
+    // each attempt is signed afresh, so that a retry isn't taken for a replay
+    let send_request = | mut stream: Connection, response_buffer: &mut Vec<u8> | {
+        let signature_args = pool.signing_key().map( | signing_key | {
+            signing_key.sign( request.opcode, request.file_name, &request.args,
+                              request.payload ).map( | signature | signature.to_args() )
+        } ).transpose()?;
+        protocol::write_request( &mut stream, request.opcode, request.file_name,
+                                 &request.args, signature_args.as_deref(),
+                                 request.payload )
+            .and_then( | _ | protocol::read_response( &mut stream, response_buffer ) )
+            .map( | status | ( stream, status ) )
+    };
+
+    let status = pool.checkout( request.node )
+        .map_err( | why | format!( "could not connect to {}: {}", request.node, why ) )
+        .and_then( | ( stream, reused ) | {
+            let mut result = send_request( stream, response_buffer );
+            // the server may have closed a reused connection just after it
+            // passed the health check, so try once more on a new connection
+            if result.is_err() && reused {
+                response_buffer.clear();
+                result = pool.connect( request.node )
+                    .and_then( | stream | send_request( stream, response_buffer ) );
+            }
+            result.map_err( | why | format!( "request to {} failed: {}",
+                                             request.node, why ) )
+        } )
+        .map( | ( stream, status ) | {
+            pool.checkin( request.node, stream );
+            status
+        } );
+
+    match status {
+        Ok( status ) => Response {
+            status,
+            message: &response_buffer[ .. ],
+        },
+        Err( error_string ) => {
+            response_buffer.clear();
+            response_buffer.extend_from_slice( error_string.as_bytes() );
+            Response {
+                status: 1,
+                message: &response_buffer[ .. ],
+            }
+        },
+    }
+}
+
+pub fn perform_request_with_retry<'a>( pool: &ConnectionPool, opcode: Opcode, file_name: &str,
+                                   args: Vec< String >,
+                                   primary_node_option: Option< &Node >,
+                                   backup_nodes: Vec< Node >,
+                                   response_buffer: &'a mut Vec< u8 > )
+                                   -> Option< Response<'a> > {
+    let mut random_backup_nodes = backup_nodes.clone();
+    let random_backup_nodes_slice = random_backup_nodes.as_mut_slice();
+    rand::thread_rng().shuffle( random_backup_nodes_slice );
+
+    let mut nodes: Vec< Node > = match primary_node_option {
+        Some( primary_node ) => vec![ primary_node.clone() ],
+        None => Vec::new(),
+    };
+    nodes.extend_from_slice( random_backup_nodes_slice );
+
+    for node in nodes.iter() {
+        let request = Request {
+            node,
+            opcode,
+            file_name,
+            args: args.clone(),
+            payload: &[],
+        };
+        let temp_response_buffer = &mut Vec::new();
+        let temp_response = perform_request( pool, request, temp_response_buffer );
+        if temp_response.status == 0 {
+            response_buffer.clear();
+            response_buffer.extend_from_slice( temp_response.message );
+            return Some( Response {
+                status: 0,
+                message: &response_buffer[ 0.. ],
+            } );
+        }
+    }
+    None
+}
+
+pub fn request_length( pool: &ConnectionPool, nodes: Vec< Node >, file_name: &str )
+                       -> Option< u64 > {
+    let response_buffer = &mut Vec::new();
+    let response_option = perform_request_with_retry(
+        pool,
+        Opcode::Length, file_name, Vec::new(), None, nodes, response_buffer );
+
+    response_option.and_then( | response | {
+        str::from_utf8( response.message ).ok()
+            .and_then( | length | length.parse::<u64>().ok() )
+    } )
+}
+
+pub fn request_whole_file<'a>( pool: &ConnectionPool, nodes: Vec< Node >, file_name: &str,
+                               response_buffer: &'a mut Vec<u8> )
+                               -> Option< &'a [u8] > {
+    let response_option = perform_request_with_retry(
+        pool,
+        Opcode::Read, file_name, Vec::new(), None, nodes, response_buffer );
+
+    response_option.map( | response | response.message )
+}
+
+// request a range of the file, checking that the whole range came back
+pub fn request_file_chunk( pool: &ConnectionPool, primary_node: &Node, backup_nodes: Vec< Node >,
+                       file_name: &str, start_offset: u64, end_offset: u64 )
+                       -> Option< Vec< u8 > > {
+    let response_buffer = &mut Vec::new();
+    let response_option = perform_request_with_retry(
+        pool,
+        Opcode::Read, file_name,
+        vec![ start_offset.to_string(), end_offset.to_string() ],
+        Some( primary_node ), backup_nodes, response_buffer );
+
+    match response_option {
+        Some( ref response ) if response.message.len() as u64 ==
+            end_offset - start_offset => {
+            Some( response.message.to_vec() )
+        },
+        _ => None,
+    }
+}
+
+// a file retrieved by request_file_distributed
+#[ derive( Clone, Debug, PartialEq ) ]
+pub struct Retrieved {
+    pub length: u64,
+    // digest of the whole file, with the algorithm that was asked for
+    pub checksum: Digest,
+    // nodes which sent blocks that didn't match their block checksums, and
+    // had those blocks retrieved again from other nodes
+    pub corrupt_nodes: Vec< Node >,
+}
+
+// retrieve a range of the file from primary_node, falling back to the other
+// nodes in a random order. With block checksums, each block is checked as
+// it arrives and a bad block is retrieved again from the next node, so the
+// range may be pieced together from several nodes. The range starts at a
+// block boundary. Returns the range, if a good copy of all of it was found,
+// along with the nodes which sent bad blocks.
+fn request_verified_chunk( pool: &ConnectionPool, primary_node: &Node, nodes: &[Node],
+                           file_name: &str, start_offset: u64, end_offset: u64,
+                           blocks: Option< &BlockChecksums > )
+                           -> ( Option< Vec< u8 > >, Vec< Node > ) {
+    let mut ordered_nodes: Vec< Node > = nodes.iter()
+        .filter( | node | *node != primary_node )
+        .cloned()
+        .collect();
+    rand::thread_rng().shuffle( &mut ordered_nodes );
+    ordered_nodes.insert( 0, primary_node.clone() );
+
+    let mut contents = vec![ 0u8; ( end_offset - start_offset ) as usize ];
+    let mut missing = vec![ ( start_offset, end_offset ) ];
+    let mut corrupt_nodes = Vec::new();
+    for node in ordered_nodes.iter() {
+        if missing.is_empty() {
+            break;
+        }
+        let mut still_missing = Vec::new();
+        for ( piece_start, piece_end ) in missing {
+            let piece = match request_file_chunk( pool, node, Vec::new(), file_name,
+                                                  piece_start, piece_end ) {
+                Some( piece ) => piece,
+                None => {
+                    still_missing.push( ( piece_start, piece_end ) );
+                    continue;
+                },
+            };
+            let offset = ( piece_start - start_offset ) as usize;
+            contents[ offset .. offset + piece.len() ].copy_from_slice( &piece );
+            if let Some( blocks ) = blocks {
+                let bad_blocks = blocks.bad_blocks( piece_start, &piece );
+                if !bad_blocks.is_empty() && !corrupt_nodes.contains( node ) {
+                    corrupt_nodes.push( node.clone() );
+                }
+                still_missing.extend( bad_blocks.into_iter().map( | block_start | {
+                    ( block_start, std::cmp::min( block_start + blocks.block_size, piece_end ) )
+                } ) );
+            }
+        }
+        missing = still_missing;
+    }
+
+    if missing.is_empty() {
+        ( Some( contents ), corrupt_nodes )
+    } else {
+        ( None, corrupt_nodes )
+    }
+}
+
+// a range of a file, and the node asked for it first
+type Chunk = ( Node, u64, u64 );
+
+// a chunk's index, along with the chunk if a good copy of it was found and
+// the nodes which sent bad blocks of it
+type ChunkResult = ( usize, ( Option< Vec< u8 > >, Vec< Node > ) );
+
+fn check_block_checksums( file_name: &str, file_length: u64, entry: &FileEntry )
+                          -> Result< (), String > {
+    match entry.blocks {
+        Some( ref blocks ) if !blocks.fits_length( file_length ) => Err( format!(
+            "{} has {} block checksums in file_store.yaml, which doesn't fit its length \
+             ({}) with {} byte blocks", file_name, blocks.checksums.len(), file_length,
+            blocks.block_size ) ),
+        _ => Ok( () ),
+    }
+}
+
+// split ranges of the file into even chunks based on how many nodes there
+// are (capped at CHUNK_SIZE, and aligned to the block size when entry has
+// block checksums), handing the chunks out to the nodes in turn
+fn split_into_chunks( nodes: &[Node], file_length: u64, entry: &FileEntry,
+                      ranges: &[( u64, u64 )] ) -> Vec< Chunk > {
+    let num_nodes = nodes.len() as u64;
+    let mut chunk_size = file_length.div_ceil( num_nodes ).clamp( 1, CHUNK_SIZE );
+    if let Some( ref blocks ) = entry.blocks {
+        chunk_size = chunk_size.div_ceil( blocks.block_size ) * blocks.block_size;
+    }
+    let mut chunks = Vec::new();
+    for &( range_start, range_end ) in ranges {
+        let mut start_offset = range_start;
+        while start_offset < range_end {
+            let end_offset = std::cmp::min( start_offset + chunk_size, range_end );
+            let node = nodes[ chunks.len() % nodes.len() ].clone();
+            chunks.push( ( node, start_offset, end_offset ) );
+            start_offset = end_offset;
+        }
+    }
+    chunks
+}
+
+// start up to max_parallel_chunks threads, each fetching whichever chunk
+// index it is handed next and sending back the result
+fn spawn_chunk_workers( pool: Arc< ConnectionPool >, nodes: &[Node], file_name: &str,
+                        entry: &FileEntry, chunks: Arc< Vec< Chunk > >,
+                        max_parallel_chunks: usize )
+                        -> ( usize, mpsc::Sender< usize >, mpsc::Receiver< ChunkResult > ) {
+    let blocks = Arc::new( entry.blocks.clone() );
+    let ( job_sender, job_receiver ) = mpsc::channel::< usize >();
+    let job_receiver = Arc::new( Mutex::new( job_receiver ) );
+    let ( result_sender, result_receiver ) = mpsc::channel();
+    let num_workers = std::cmp::min( std::cmp::max( max_parallel_chunks, 1 ),
+                                     chunks.len() );
+    for _ in 0 .. num_workers {
+        let pool = pool.clone();
+        let chunks = chunks.clone();
+        let blocks = blocks.clone();
+        let job_receiver = job_receiver.clone();
+        let result_sender = result_sender.clone();
+        let nodes = nodes.to_vec();
+        let file_name = file_name.to_string();
+        thread::spawn( move || {
+            loop {
+                let index = match job_receiver.lock().unwrap().recv() {
+                    Ok( index ) => index,
+                    Err( _ ) => break,
+                };
+                let ( ref node, start_offset, end_offset ) = chunks[ index ];
+                let file_chunk = request_verified_chunk( &pool, node, &nodes, &file_name,
+                                                         start_offset, end_offset,
+                                                         blocks.as_ref().as_ref() );
+                if result_sender.send( ( index, file_chunk ) ).is_err() {
+                    break;
+                }
+            }
+        } );
+    }
+    ( num_workers, job_sender, result_receiver )
+}
+
+fn add_corrupt_nodes( corrupt_nodes: &mut Vec< Node >, chunk_corrupt_nodes: Vec< Node > ) {
+    for node in chunk_corrupt_nodes {
+        if !corrupt_nodes.contains( &node ) {
+            corrupt_nodes.push( node );
+        }
+    }
+}
+
+fn chunk_failure( file_name: &str, chunk: &Chunk, corrupt_nodes: &[Node] ) -> String {
+    let ( _, start_offset, end_offset ) = *chunk;
+    let corrupt = if corrupt_nodes.is_empty() {
+        String::new()
+    } else {
+        let corrupt_nodes: Vec< String > = corrupt_nodes.iter()
+            .map( | node | node.to_string() )
+            .collect();
+        format!( ", bad blocks came from: {}", corrupt_nodes.join( ", " ) )
+    };
+    format!( "Could not retrieve file contents between {} and {} for {}{}",
+             start_offset, end_offset, file_name, corrupt )
+}
+
+// split the file into chunks (see split_into_chunks), then request the
+// file chunks from the nodes in parallel, with at most max_parallel_chunks
+// requests in flight at once. Chunks are verified against the block
+// checksums (if any) and written to output in order as soon as they
+// arrive, and the digest of the whole file (with the algorithm of entry's
+// checksum) is returned along with any nodes which sent bad blocks. The
+// caller compares the digest against entry.
+pub fn request_file_distributed< W: Write >( pool: Arc< ConnectionPool >, nodes: Vec< Node >,
+                                             file_name: &str, file_length: u64,
+                                             entry: &FileEntry, max_parallel_chunks: usize,
+                                             output: &mut W )
+                                             -> Result< Retrieved, String > {
+    check_block_checksums( file_name, file_length, entry )?;
+    let chunks = Arc::new( split_into_chunks( &nodes, file_length, entry,
+                                              &[ ( 0, file_length ) ] ) );
+    let ( num_workers, job_sender, result_receiver ) = spawn_chunk_workers(
+        pool, &nodes, file_name, entry, chunks.clone(), max_parallel_chunks );
+
+    // only hand out chunks up to a fixed window past the next one to be
+    // written, so one slow chunk can't make the rest of the file pile up
+    // in memory
+    let window = num_workers * 2;
+    let mut next_job = 0;
+    while next_job < std::cmp::min( window, chunks.len() ) {
+        job_sender.send( next_job ).expect( "chunk workers exited early" );
+        next_job += 1;
+    }
+
+    // chunks can arrive in any order, so hold on to them until it's their turn
+    let mut arrived_chunks = HashMap::new();
+    let mut next_write = 0;
+    let mut hasher = Hasher::new( entry.checksum.algorithm() );
+    let mut corrupt_nodes: Vec< Node > = Vec::new();
+    while next_write < chunks.len() {
+        let ( index, ( file_chunk, chunk_corrupt_nodes ) ) = result_receiver.recv()
+            .expect( "chunk workers exited early" );
+        add_corrupt_nodes( &mut corrupt_nodes, chunk_corrupt_nodes );
+        match file_chunk {
+            Some( file_chunk ) => arrived_chunks.insert( index, file_chunk ),
+            None => return Err( chunk_failure( file_name, &chunks[ index ], &corrupt_nodes ) ),
+        };
+        while let Some( file_chunk ) = arrived_chunks.remove( &next_write ) {
+            hasher.update( &file_chunk );
+            output.write_all( &file_chunk )
+                .map_err( | why | format!( "Could not write {}: {}", file_name, why ) )?;
+            next_write += 1;
+            if next_job < chunks.len() {
+                job_sender.send( next_job ).expect( "chunk workers exited early" );
+                next_job += 1;
+            }
+        }
+    }
+    Ok( Retrieved { length: file_length, checksum: hasher.finish(), corrupt_nodes } )
+}
+
+// request the given ranges of the file from the nodes entry lists in
+// parallel, chunked the same way as request_file_distributed, handing each
+// chunk to write_chunk along with its offset as soon as it arrives, in any
+// order.
+// Every chunk is attempted even after one fails, so that as much of the
+// file as possible is written. Returns the nodes which sent bad blocks.
+pub fn request_ranges_distributed< F >( pool: Arc< ConnectionPool >, file_name: &str,
+                                        file_length: u64, entry: &FileEntry,
+                                        ranges: &[( u64, u64 )], max_parallel_chunks: usize,
+                                        mut write_chunk: F ) -> Result< Vec< Node >, String >
+    where F: FnMut( u64, &[u8] ) -> Result< (), String >
+{
+    check_block_checksums( file_name, file_length, entry )?;
+    let chunks = Arc::new( split_into_chunks( &entry.nodes, file_length, entry, ranges ) );
+    let ( _, job_sender, result_receiver ) = spawn_chunk_workers(
+        pool, &entry.nodes, file_name, entry, chunks.clone(), max_parallel_chunks );
+    for index in 0 .. chunks.len() {
+        job_sender.send( index ).expect( "chunk workers exited early" );
+    }
+    drop( job_sender );
+
+    let mut failure = None;
+    let mut corrupt_nodes: Vec< Node > = Vec::new();
+    for ( index, ( file_chunk, chunk_corrupt_nodes ) ) in result_receiver.iter() {
+        add_corrupt_nodes( &mut corrupt_nodes, chunk_corrupt_nodes );
+        let written = match file_chunk {
+            Some( file_chunk ) => write_chunk( chunks[ index ].1, &file_chunk ),
+            None => Err( chunk_failure( file_name, &chunks[ index ], &corrupt_nodes ) ),
+        };
+        if let Err( why ) = written {
+            failure.get_or_insert( why );
+        }
+    }
+    match failure {
+        Some( why ) => Err( why ),
+        None => Ok( corrupt_nodes ),
+    }
+}
+
+// retrieve the metadata of file_name from a single node, rather than
+// whichever node answers first, so that replicas can be compared
+pub fn request_stat( pool: &ConnectionPool, node: &Node, file_name: &str, algorithm: Algorithm )
+                     -> Result< FileStat, String > {
+    let request = Request {
+        node,
+        opcode: Opcode::Stat,
+        file_name,
+        args: vec![ String::from( algorithm.name() ) ],
+        payload: &[],
+    };
+    let response_buffer = &mut Vec::new();
+    let response = perform_request( pool, request, response_buffer );
+    let message = String::from_utf8_lossy( response.message );
+    if response.status != Status::Ok as u8 {
+        return Err( message.trim_end().to_string() );
+    }
+    FileStat::parse( &message )
+}
+
+// have a single node compute the digest of file_name, or of the bytes from
+// start up to (but not including) end, without sending the data
+pub fn request_checksum( pool: &ConnectionPool, node: &Node, file_name: &str,
+                         algorithm: Algorithm, range: Option< ( u64, u64 ) > )
+                         -> Result< Digest, String > {
+    let mut args = vec![ String::from( algorithm.name() ) ];
+    if let Some( ( start_offset, end_offset ) ) = range {
+        args.push( start_offset.to_string() );
+        args.push( end_offset.to_string() );
+    }
+    let request = Request {
+        node,
+        opcode: Opcode::Checksum,
+        file_name,
+        args,
+        payload: &[],
+    };
+    let response_buffer = &mut Vec::new();
+    let response = perform_request( pool, request, response_buffer );
+    let message = String::from_utf8_lossy( response.message );
+    if response.status != Status::Ok as u8 {
+        return Err( message.trim_end().to_string() );
+    }
+    let checksum = message.trim().parse::< Digest >()?;
+    if checksum.algorithm() != algorithm {
+        return Err( format!( "asked for a {} checksum, but got: {}", algorithm.name(), checksum ) );
+    }
+    Ok( checksum )
+}
+
+// list the entries under dir_name on a single node, requesting further
+// pages until the whole listing has been retrieved
+pub fn request_listing( pool: &ConnectionPool, node: &Node, dir_name: &str, recursive: bool )
+                        -> Result< Vec< ListEntry >, String > {
+    let mut entries = Vec::new();
+    let mut after = String::new();
+    loop {
+        let request = Request {
+            node,
+            opcode: Opcode::List,
+            file_name: dir_name,
+            args: vec![ String::from( if recursive { "1" } else { "0" } ),
+                        String::new(), after.clone() ],
+            payload: &[],
+        };
+        let response_buffer = &mut Vec::new();
+        let response = perform_request( pool, request, response_buffer );
+        let message = String::from_utf8_lossy( response.message );
+        if response.status != Status::Ok as u8 {
+            return Err( message.trim_end().to_string() );
+        }
+        let listing = Listing::parse( &message )?;
+        entries.extend( listing.entries );
+        match listing.next {
+            Some( next ) => after = next,
+            None => return Ok( entries ),
+        }
+    }
+}
+
+// whether perform_request failed to reach the node at all, as opposed to
+// the node answering with an error
+pub fn is_connection_failure( node: &Node, message: &str ) -> bool {
+    message.starts_with( &format!( "could not connect to {}", node ) ) ||
+        message.starts_with( &format!( "request to {} failed", node ) )
+}
+
+// send a request which only the consensus leader among the root nodes can
+// answer, following redirects to the leader and retrying while a leader is
+// being elected. Returns the reply, or the status and message of the first
+// answer which isn't a redirect.
+pub fn perform_leader_request( pool: &ConnectionPool, root_nodes: &[Node], opcode: Opcode,
+                               file_name: &str, args: Vec< String > )
+                               -> Result< Vec< u8 >, ( Status, String ) > {
+    let mut leader_hint: Option< Node > = None;
+    let mut failures = Vec::new();
+    for attempt in 0 .. LEADER_RETRIES {
+        if attempt > 0 {
+            thread::sleep( LEADER_RETRY_DELAY );
+        }
+
+        // try the last known leader first, then every root node in turn
+        let mut nodes: Vec< Node > = leader_hint.take().into_iter().collect();
+        nodes.extend( root_nodes.iter().cloned() );
+        failures.clear();
+        for node in nodes.iter() {
+            let request = Request {
+                node,
+                opcode,
+                file_name,
+                args: args.clone(),
+                payload: &[],
+            };
+            let response_buffer = &mut Vec::new();
+            let response = perform_request( pool, request, response_buffer );
+            let message = String::from_utf8_lossy( response.message ).trim_end().to_string();
+            match Status::from_u8( response.status ) {
+                Some( Status::Ok ) => return Ok( response.message.to_vec() ),
+                Some( Status::NotLeader ) => {
+                    if let Ok( leader ) = message.parse::< Node >() {
+                        if leader != *node {
+                            leader_hint = Some( leader );
+                            break;
+                        }
+                    }
+                    failures.push( format!( "{} (no leader known)", node ) );
+                },
+                // the node may be down, so move on to the next one
+                Some( Status::Error ) if is_connection_failure( node, &message ) => {
+                    failures.push( format!( "{} ({})", node, message ) );
+                },
+                Some( status ) => return Err( ( status, message ) ),
+                None => return Err( ( Status::Error, format!(
+                    "unknown status {} from {}: {}", response.status, node, message ) ) ),
+            }
+        }
+    }
+    Err( ( Status::Error, format!( "no root node could answer as the leader: {}",
+                                   failures.join( ", " ) ) ) )
+}
+
+// retrieve and parse file_store.yaml from the consensus leader
+pub fn request_manifest( pool: &ConnectionPool, root_nodes: Vec< Node > )
+                         -> Result< Manifest, String > {
+    let file_store = perform_leader_request( pool, &root_nodes, Opcode::Read,
+                                             MANIFEST_PATH, Vec::new() )
+        .map_err( | ( _, why ) | format!( "Could not retrieve file contents for {}: {}",
+                                          MANIFEST_PATH, why ) )?;
+    let file_store_string = str::from_utf8( &file_store )
+        .map_err( | why | format!( "{} is not valid UTF-8: {}", MANIFEST_PATH, why ) )?;
+    Manifest::parse( file_store_string )
+}
+
+// apply an edit to the file_store.yaml entry for file_name through the
+// consensus leader, returning the new manifest version.
+//
+// The edit is made against the latest manifest version, and is rejected
+// with a version conflict rather than overwriting another edit which
+// committed in the meantime.
+pub fn edit_manifest( pool: &ConnectionPool, root_nodes: &[Node], file_name: &str,
+                      edit: &ManifestEdit ) -> Result< u64, String > {
+    let manifest = request_manifest( pool, root_nodes.to_vec() )?;
+    let mut args = vec![ manifest.version.to_string() ];
+    args.extend( edit.to_args() );
+    // only block checksums can outgrow a single argument
+    if args.iter().any( | arg | arg.len() > u16::MAX as usize ) {
+        return Err( format!( "the edit of {} has too many block checksums to send, \
+                              use a larger block size", file_name ) );
+    }
+
+    match perform_leader_request( pool, root_nodes, Opcode::Edit, file_name, args ) {
+        Ok( version ) => String::from_utf8_lossy( &version ).trim().parse::< u64 >()
+            .map_err( | why | format!( "invalid manifest version in reply: {}", why ) ),
+        Err( ( Status::VersionConflict, why ) ) => Err( format!(
+            "file_store.yaml was changed concurrently, re-read it and try again: {}", why ) ),
+        Err( ( _, why ) ) => Err( format!( "the edit of {} was rejected: {}",
+                                           file_name, why ) ),
+    }
+}
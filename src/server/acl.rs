@@ -0,0 +1,246 @@
+// Access control for the store, read from store/acl.yaml alongside
+// file_store.yaml so that it is replicated like any other file:
+//
+//     /:
+//       admin: [nodes]
+//     /photos:
+//       read: ["*"]
+//       write: [alice]
+//
+// Rules are keyed by path prefix and grant permissions to principals, the
+// names of the keys requests are signed with (see signing.rs), or to "*"
+// for anyone, including unsigned requests. A rule covers its path and
+// everything under it, and a principal holds every permission granted by
+// the rules covering a path. Admin implies write, which implies read.
+//
+// Without acl.yaml every request is allowed.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+use yaml_rust::{Yaml, YamlLoader};
+use turtlefs::manifest::MANIFEST_PATH;
+use turtlefs::protocol::{Opcode, Status};
+
+// request path of the access control lists
+pub const ACL_PATH: &str = "/acl.yaml";
+
+// principal which stands for every request, signed or not
+pub const ANYONE: &str = "*";
+
+#[ derive( Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord ) ]
+pub enum Permission {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Permission {
+    fn name( self ) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Admin => "admin",
+        }
+    }
+}
+
+#[ derive( Clone, Debug, Default, PartialEq ) ]
+pub struct Acl {
+    // path prefix and the highest permission it grants each principal
+    rules: Vec< ( PathBuf, HashMap< String, Permission > ) >,
+}
+
+impl Acl {
+    pub fn parse( contents: &str ) -> Result< Acl, String > {
+        let docs = YamlLoader::load_from_str( contents ).map_err( | why | why.to_string() )?;
+        let rule_map = match docs.first() {
+            Some( Yaml::Hash( rule_map ) ) => rule_map,
+            None | Some( &Yaml::Null ) => return Ok( Acl::default() ),
+            Some( _ ) => return Err( String::from( "expected a map of path prefixes to rules" ) ),
+        };
+
+        let mut rules = Vec::new();
+        for ( prefix, rule ) in rule_map {
+            let prefix = match prefix.as_str() {
+                Some( prefix ) if prefix.starts_with( '/' ) => prefix,
+                _ => return Err( format!( "path prefix {:?} must start with /", prefix ) ),
+            };
+            let grants = match *rule {
+                Yaml::Hash( ref grants ) => grants,
+                _ => return Err( format!(
+                    "the rule for {} must be a map of permissions to principals", prefix ) ),
+            };
+
+            let mut principals = HashMap::new();
+            for ( permission, names ) in grants {
+                let permission = match permission.as_str() {
+                    Some( "read" ) => Permission::Read,
+                    Some( "write" ) => Permission::Write,
+                    Some( "admin" ) => Permission::Admin,
+                    _ => return Err( format!(
+                        "permission {:?} for {} must be one of: read, write, admin",
+                        permission, prefix ) ),
+                };
+                let names = names.as_vec().ok_or_else( || format!(
+                    "{} for {} must be a list of principals", permission.name(), prefix ) )?;
+                for name in names {
+                    let name = name.as_str().ok_or_else( || format!(
+                        "principals given {} for {} must be key names or \"{}\"",
+                        permission.name(), prefix, ANYONE ) )?;
+                    let granted = principals.entry( String::from( name ) ).or_insert( permission );
+                    *granted = ( *granted ).max( permission );
+                }
+            }
+            rules.push( ( PathBuf::from( prefix ), principals ) );
+        }
+        Ok( Acl { rules } )
+    }
+
+    // highest permission held on a request path, by the key a request was
+    // signed with (None for unsigned requests)
+    pub fn permission( &self, principal: Option< &str >, path: &Path ) -> Option< Permission > {
+        self.rules.iter()
+            .filter( | rule | path.starts_with( &rule.0 ) )
+            .flat_map( | rule | {
+                let principals = &rule.1;
+                let named = principal.and_then( | principal | principals.get( principal ) );
+                named.into_iter().chain( principals.get( ANYONE ) )
+            } )
+            .max()
+            .cloned()
+    }
+}
+
+// permission needed for an action on a request path
+pub fn required_permission( action: Opcode, path: &Path ) -> Permission {
+    match action {
+        Opcode::Read | Opcode::Length | Opcode::Stat | Opcode::List | Opcode::Checksum =>
+            Permission::Read,
+        // rewriting the access control lists would hand out permissions
+        Opcode::Write | Opcode::Delete if path == Path::new( ACL_PATH ) => Permission::Admin,
+        Opcode::Write | Opcode::Delete => Permission::Write,
+        Opcode::Edit | Opcode::Vote | Opcode::Append | Opcode::Snapshot => Permission::Admin,
+    }
+}
+
+type CachedAcl = Option< ( u64, SystemTime, Result< Acl, String > ) >;
+
+// the store's access control lists, only read again once acl.yaml changes
+pub struct AclCache {
+    store_root: PathBuf,
+    acl: Mutex< CachedAcl >,
+}
+
+impl AclCache {
+    pub fn new( store_root: PathBuf ) -> AclCache {
+        AclCache { store_root, acl: Mutex::new( None ) }
+    }
+
+    fn lock( &self ) -> MutexGuard< '_, CachedAcl > {
+        self.acl.lock().unwrap_or_else( | poisoned | poisoned.into_inner() )
+    }
+
+    // refuse an action on a request path (such as /dir/file.txt) unless the
+    // principal holds the permission it needs. VOTE, APPEND and SNAPSHOT
+    // change file_store.yaml, whatever path they are sent with.
+    pub fn check( &self, principal: Option< &str >, action: Opcode, request_path: &Path )
+                  -> Result< (), ( Status, String ) > {
+        let path = match action {
+            Opcode::Vote | Opcode::Append | Opcode::Snapshot => Path::new( MANIFEST_PATH ),
+            _ => request_path,
+        };
+        let needed = required_permission( action, path );
+
+        let acl_path = self.store_root.join( &ACL_PATH[ 1 .. ] );
+        let metadata = match fs::metadata( &acl_path ) {
+            Ok( metadata ) => metadata,
+            Err( _ ) => return Ok( () ),
+        };
+        let modified = metadata.modified().map_err( | why | ( Status::Error, format!(
+            "could not inspect {}: {}", ACL_PATH, why ) ) )?;
+
+        // an invalid acl.yaml refuses everything rather than nothing
+        let mut cached = self.lock();
+        let stale = match *cached {
+            Some( ( length, cached_modified, _ ) ) =>
+                length != metadata.len() || cached_modified != modified,
+            None => true,
+        };
+        if stale {
+            *cached = None;
+        }
+        let acl = match cached.get_or_insert_with( || {
+            let acl = fs::read_to_string( &acl_path )
+                .map_err( | why | why.to_string() )
+                .and_then( | contents | Acl::parse( &contents ) );
+            ( metadata.len(), modified, acl )
+        } ).2 {
+            Ok( ref acl ) => acl,
+            Err( ref why ) => return Err( ( Status::Forbidden, format!(
+                "{} is invalid, so every request is refused: {}", ACL_PATH, why ) ) ),
+        };
+        if acl.permission( principal, path ).is_some_and( | held | held >= needed ) {
+            return Ok( () );
+        }
+        Err( ( Status::Forbidden, match principal {
+            Some( principal ) => format!( "{} does not have {} permission on {}",
+                                          principal, needed.name(), path.display() ),
+            None => format!( "unsigned requests do not have {} permission on {}",
+                             needed.name(), path.display() ),
+        } ) )
+    }
+}
+
+#[ cfg( test ) ]
+mod tests {
+    use super::*;
+
+    const RULES: &str = "/:\n  admin: [nodes]\n\
+                         /photos:\n  read: [\"*\"]\n  write: [alice]\n\
+                         /photos/private:\n  read: [bob]\n";
+
+    #[ test ]
+    fn grants_add_up_along_the_path() {
+        let acl = Acl::parse( RULES ).unwrap();
+        let permission = | principal, path | acl.permission( principal, Path::new( path ) );
+        assert_eq!( permission( Some( "nodes" ), "/" ), Some( Permission::Admin ) );
+        assert_eq!( permission( Some( "nodes" ), "/photos/a.jpg" ), Some( Permission::Admin ) );
+        assert_eq!( permission( Some( "alice" ), "/photos/a.jpg" ), Some( Permission::Write ) );
+        assert_eq!( permission( Some( "alice" ), "/photos/private/a.jpg" ),
+                    Some( Permission::Write ) );
+        assert_eq!( permission( Some( "bob" ), "/photos/private" ), Some( Permission::Read ) );
+        assert_eq!( permission( None, "/photos" ), Some( Permission::Read ) );
+        assert_eq!( permission( None, "/hello.txt" ), None );
+        assert_eq!( permission( Some( "alice" ), "/file_store.yaml" ), None );
+    }
+
+    #[ test ]
+    fn prefixes_match_whole_components() {
+        let acl = Acl::parse( RULES ).unwrap();
+        assert_eq!( acl.permission( Some( "alice" ), Path::new( "/photoshop" ) ), None );
+    }
+
+    #[ test ]
+    fn acl_yaml_needs_admin_to_change() {
+        assert_eq!( required_permission( Opcode::Write, Path::new( "/acl.yaml" ) ),
+                    Permission::Admin );
+        assert_eq!( required_permission( Opcode::Delete, Path::new( "/acl.yaml" ) ),
+                    Permission::Admin );
+        assert_eq!( required_permission( Opcode::Read, Path::new( "/acl.yaml" ) ),
+                    Permission::Read );
+        assert_eq!( required_permission( Opcode::Write, Path::new( "/a.txt" ) ),
+                    Permission::Write );
+    }
+
+    #[ test ]
+    fn rejects_malformed_rules() {
+        assert!( Acl::parse( "photos:\n  read: [alice]\n" ).is_err() );
+        assert!( Acl::parse( "/photos:\n  delete: [alice]\n" ).is_err() );
+        assert!( Acl::parse( "/photos:\n  read: alice\n" ).is_err() );
+        assert!( Acl::parse( "/photos: [alice]\n" ).is_err() );
+        assert_eq!( Acl::parse( "" ).unwrap(), Acl::default() );
+    }
+}
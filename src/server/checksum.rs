@@ -0,0 +1,70 @@
+// Checksums of files in the store, shared by request handling and
+// replication so that an unchanged file is only read once per algorithm.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+use turtlefs::digest::{Algorithm, Digest, Hasher};
+
+type Checksums = HashMap< ( PathBuf, Algorithm ), ( u64, SystemTime, Digest ) >;
+
+// checksums keyed by path and algorithm, each one only trusted while the
+// file's length and modification time are unchanged
+pub struct ChecksumCache {
+    checksums: Mutex< Checksums >,
+}
+
+impl ChecksumCache {
+    pub fn new() -> ChecksumCache {
+        ChecksumCache { checksums: Mutex::new( HashMap::new() ) }
+    }
+
+    fn lock( &self ) -> MutexGuard< '_, Checksums > {
+        self.checksums.lock().unwrap_or_else( | poisoned | poisoned.into_inner() )
+    }
+
+    // checksum of a local file, reusing the cached value when the file has
+    // not changed since it was last read. The cache isn't locked while the
+    // file is read, so other files can be checked at the same time.
+    pub fn checksum( &self, path: &Path, algorithm: Algorithm ) -> io::Result< Digest > {
+        let metadata = fs::metadata( path )?;
+        let modified = metadata.modified()?;
+        let key = ( path.to_path_buf(), algorithm );
+        if let Some( &( length, cached_modified, checksum ) ) = self.lock().get( &key ) {
+            if length == metadata.len() && cached_modified == modified {
+                return Ok( checksum );
+            }
+        }
+
+        let checksum = range_checksum( path, 0, metadata.len(), algorithm )?;
+        self.lock().insert( key, ( metadata.len(), modified, checksum ) );
+        Ok( checksum )
+    }
+
+    // drop the cached checksums of a file which was replaced
+    pub fn forget( &self, path: &Path ) {
+        self.lock().retain( | key, _ | key.0 != path );
+    }
+}
+
+// checksum of the bytes from start_offset up to (but not including)
+// end_offset of a local file, which is never cached
+pub fn range_checksum( path: &Path, start_offset: u64, end_offset: u64, algorithm: Algorithm )
+                       -> io::Result< Digest > {
+    let mut file = File::open( path )?;
+    file.seek( SeekFrom::Start( start_offset ) )?;
+    let mut range = file.take( end_offset - start_offset );
+    let mut hasher = Hasher::new( algorithm );
+    let mut buf = vec![ 0u8; 64 * 1024 ];
+    loop {
+        let bytes_read = range.read( &mut buf )?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update( &buf[ .. bytes_read ] );
+    }
+    Ok( hasher.finish() )
+}
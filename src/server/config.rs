@@ -0,0 +1,370 @@
+// Server settings, taken from a YAML config file given with --config and
+// from command line flags, which take precedence over the file:
+//
+//     root: /srv/turtlefs-root
+//     bind: [ "0.0.0.0:5550" ]
+//     node: 192.168.0.155:5550
+//     max_connections: 1024
+//     idle_timeout: 60
+//     log_level: info
+//
+// Every setting has a flag of the same name, e.g. max_connections is
+// --max-connections. The old "server <turtlefs-root> <address>" form is
+// still accepted.
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use yaml_rust::{Yaml, YamlLoader};
+use turtlefs::node::Node;
+use turtlefs::signing::KEYS_CONFIG_PATH;
+use turtlefs::tls::TLS_CONFIG_PATH;
+use logging::LogLevel;
+
+pub const USAGE: &str = "\
+usage: server [<turtlefs-root> <address>] [--config <file>] [--root <dir>]
+              [--bind <address>]... [--node <address>] [--store <dir>]
+              [--max-connections <n>] [--max-payload-size <bytes>]
+              [--max-args-size <bytes>] [--idle-timeout <seconds>]
+              [--handshake-timeout <seconds>] [--log-level error|warn|info|debug]
+              [--tls-config <file>] [--keys-config <file>]
+              [--require-tls] [--require-signatures]";
+
+#[ derive( Clone, Debug, PartialEq ) ]
+pub struct ServerConfig {
+    pub turtlefs_root: String,
+    // addresses to accept connections on
+    pub bind: Vec< SocketAddr >,
+    // this node's address in nodes.yaml and file_store.yaml, the first bind
+    // address unless given
+    pub node: Node,
+    // where files are kept, turtlefs-root/store unless given
+    pub store: PathBuf,
+    // connections beyond this many are closed straight away
+    pub max_connections: usize,
+    // largest payload accepted in a single request
+    pub max_payload_size: u64,
+    // largest path and args accepted in a single request
+    pub max_args_size: u64,
+    // connections with no new request for this long are closed, this should
+    // be longer than the client's pool idle timeout so the client hangs up
+    // first
+    pub idle_timeout: Duration,
+    // TLS handshakes taking longer than this are given up on
+    pub handshake_timeout: Duration,
+    pub log_level: LogLevel,
+    // TLS settings (see tls.rs) and keys (see signing.rs), whose paths are
+    // relative to turtlefs-root
+    pub tls_config: PathBuf,
+    pub keys_config: PathBuf,
+    // refuse plaintext or unsigned requests, whatever tls_config and
+    // keys_config say
+    pub require_tls: bool,
+    pub require_signatures: bool,
+}
+
+// settings as given, before defaults are filled in
+#[ derive( Default ) ]
+struct Settings {
+    root: Option< String >,
+    bind: Vec< SocketAddr >,
+    node: Option< Node >,
+    store: Option< PathBuf >,
+    max_connections: Option< usize >,
+    max_payload_size: Option< u64 >,
+    max_args_size: Option< u64 >,
+    idle_timeout: Option< Duration >,
+    handshake_timeout: Option< Duration >,
+    log_level: Option< LogLevel >,
+    tls_config: Option< PathBuf >,
+    keys_config: Option< PathBuf >,
+    require_tls: Option< bool >,
+    require_signatures: Option< bool >,
+}
+
+fn positive< T: ::std::str::FromStr + Default + PartialEq >( value: &str ) -> Result< T, String > {
+    match value.parse() {
+        Ok( number ) if number != T::default() => Ok( number ),
+        _ => Err( format!( "must be a positive whole number, not {:?}", value ) ),
+    }
+}
+
+fn seconds( value: &str ) -> Result< Duration, String > {
+    match value.parse::< f64 >() {
+        Ok( seconds ) if seconds > 0.0 && seconds.is_finite() =>
+            Ok( Duration::from_secs_f64( seconds ) ),
+        _ => Err( format!( "must be a positive number of seconds, not {:?}", value ) ),
+    }
+}
+
+fn flag( value: &str ) -> Result< bool, String > {
+    match value {
+        "true" => Ok( true ),
+        "false" => Ok( false ),
+        _ => Err( format!( "must be true or false, not {:?}", value ) ),
+    }
+}
+
+impl Settings {
+    // set one setting from its text, as given on the command line
+    fn set( &mut self, key: &str, value: &str ) -> Result< (), String > {
+        match key {
+            "root" => self.root = Some( String::from( value ) ),
+            "bind" => self.bind.push( value.parse().map_err( | _ | format!(
+                "must be an address such as 0.0.0.0:5550, not {:?}", value ) )? ),
+            "node" => self.node = Some( value.parse().map_err( | _ | format!(
+                "must be an address such as 192.168.0.155:5550, not {:?}", value ) )? ),
+            "store" => self.store = Some( PathBuf::from( value ) ),
+            "max_connections" => self.max_connections = Some( positive( value )? ),
+            "max_payload_size" => self.max_payload_size = Some( positive( value )? ),
+            "max_args_size" => self.max_args_size = Some( positive( value )? ),
+            "idle_timeout" => self.idle_timeout = Some( seconds( value )? ),
+            "handshake_timeout" => self.handshake_timeout = Some( seconds( value )? ),
+            "log_level" => self.log_level = Some( value.parse()? ),
+            "tls_config" => self.tls_config = Some( PathBuf::from( value ) ),
+            "keys_config" => self.keys_config = Some( PathBuf::from( value ) ),
+            "require_tls" => self.require_tls = Some( flag( value )? ),
+            "require_signatures" => self.require_signatures = Some( flag( value )? ),
+            _ => return Err( String::from( "is not a setting" ) ),
+        }
+        Ok( () )
+    }
+
+    // settings from a YAML config file
+    fn parse( contents: &str ) -> Result< Settings, String > {
+        let docs = YamlLoader::load_from_str( contents ).map_err( | why | why.to_string() )?;
+        let doc = match docs.first() {
+            Some( Yaml::Hash( doc ) ) => doc,
+            None | Some( &Yaml::Null ) => return Ok( Settings::default() ),
+            Some( _ ) => return Err( String::from( "expected a map of settings" ) ),
+        };
+
+        let mut settings = Settings::default();
+        for ( key, value ) in doc {
+            let key = key.as_str().ok_or_else( || format!( "setting {:?} is not a name", key ) )?;
+            // bind may be one address or a list of them
+            let values = match *value {
+                Yaml::Array( ref values ) if key == "bind" => values.iter().collect(),
+                _ => vec![ value ],
+            };
+            for value in values {
+                let text = match *value {
+                    Yaml::String( ref text ) => text.clone(),
+                    Yaml::Integer( number ) => number.to_string(),
+                    Yaml::Real( ref number ) => number.clone(),
+                    Yaml::Boolean( flag ) => flag.to_string(),
+                    _ => return Err( format!( "{} must be a single value", key ) ),
+                };
+                settings.set( key, &text ).map_err( | why | format!( "{} {}", key, why ) )?;
+            }
+        }
+        Ok( settings )
+    }
+
+    // settings given on the command line, along with the config file named
+    // by --config, if any
+    fn from_args( args: &[String] ) -> Result< ( Settings, Option< String > ), String > {
+        let mut settings = Settings::default();
+        let mut config_path = None;
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some( arg ) = args.next() {
+            if !arg.starts_with( "--" ) {
+                positional.push( arg );
+                continue;
+            }
+            let key = arg[ 2 .. ].replace( '-', "_" );
+            if key == "require_tls" || key == "require_signatures" {
+                settings.set( &key, "true" )?;
+                continue;
+            }
+            let value = args.next().ok_or_else( || format!( "{} needs a value", arg ) )?;
+            if key == "config" {
+                config_path = Some( value.clone() );
+                continue;
+            }
+            settings.set( &key, value ).map_err( | why | format!( "{} {}", arg, why ) )?;
+        }
+
+        match positional.len() {
+            0 => {},
+            2 => {
+                settings.set( "root", positional[ 0 ] )?;
+                settings.set( "bind", positional[ 1 ] )
+                    .map_err( | why | format!( "address {}", why ) )?;
+            },
+            _ => return Err( format!(
+                "expected <turtlefs-root> <address> or flags, not {:?}", positional ) ),
+        }
+        Ok( ( settings, config_path ) )
+    }
+
+    // these settings, with any left out taken from other
+    fn or( self, other: Settings ) -> Settings {
+        Settings {
+            root: self.root.or( other.root ),
+            bind: if self.bind.is_empty() { other.bind } else { self.bind },
+            node: self.node.or( other.node ),
+            store: self.store.or( other.store ),
+            max_connections: self.max_connections.or( other.max_connections ),
+            max_payload_size: self.max_payload_size.or( other.max_payload_size ),
+            max_args_size: self.max_args_size.or( other.max_args_size ),
+            idle_timeout: self.idle_timeout.or( other.idle_timeout ),
+            handshake_timeout: self.handshake_timeout.or( other.handshake_timeout ),
+            log_level: self.log_level.or( other.log_level ),
+            tls_config: self.tls_config.or( other.tls_config ),
+            keys_config: self.keys_config.or( other.keys_config ),
+            require_tls: self.require_tls.or( other.require_tls ),
+            require_signatures: self.require_signatures.or( other.require_signatures ),
+        }
+    }
+}
+
+impl ServerConfig {
+    // settings from the command line (without the program name) and the
+    // config file it names
+    pub fn from_args( args: &[String] ) -> Result< ServerConfig, String > {
+        let ( settings, config_path ) = Settings::from_args( args )?;
+        let settings = match config_path {
+            Some( config_path ) => {
+                let contents = fs::read_to_string( &config_path ).map_err( | why | format!(
+                    "could not read {}: {}", config_path, why ) )?;
+                let file_settings = Settings::parse( &contents ).map_err( | why | format!(
+                    "invalid {}: {}", config_path, why ) )?;
+                settings.or( file_settings )
+            },
+            None => settings,
+        };
+        ServerConfig::new( settings )
+    }
+
+    fn new( settings: Settings ) -> Result< ServerConfig, String > {
+        let turtlefs_root = settings.root
+            .ok_or_else( || String::from( "a turtlefs-root must be given with root" ) )?;
+        if !Path::new( &turtlefs_root ).is_dir() {
+            return Err( format!( "root {} is not a directory", turtlefs_root ) );
+        }
+        let bind = settings.bind;
+        let node = match ( settings.node, bind.first() ) {
+            ( Some( node ), _ ) => node,
+            ( None, Some( address ) ) if address.ip().is_unspecified() => return Err( format!(
+                "node must be given when binding to {}, as other nodes can't reach it there",
+                address ) ),
+            ( None, Some( address ) ) => address.to_string().parse()?,
+            ( None, None ) => return Err( String::from(
+                "an address to accept connections on must be given with bind" ) ),
+        };
+        let store = Path::new( &turtlefs_root )
+            .join( settings.store.unwrap_or_else( || PathBuf::from( "store" ) ) );
+        if !store.is_dir() {
+            return Err( format!( "store {} is not a directory", store.display() ) );
+        }
+        let idle_timeout = settings.idle_timeout.unwrap_or( Duration::from_secs( 60 ) );
+
+        Ok( ServerConfig {
+            bind,
+            node,
+            store,
+            max_connections: settings.max_connections.unwrap_or( 1024 ),
+            max_payload_size: settings.max_payload_size.unwrap_or( 1 << 30 ),
+            max_args_size: settings.max_args_size.unwrap_or( 1 << 20 ),
+            idle_timeout,
+            handshake_timeout: settings.handshake_timeout.unwrap_or( idle_timeout ),
+            log_level: settings.log_level.unwrap_or( LogLevel::Info ),
+            tls_config: Path::new( &turtlefs_root ).join( settings.tls_config
+                .unwrap_or_else( || PathBuf::from( TLS_CONFIG_PATH ) ) ),
+            keys_config: Path::new( &turtlefs_root ).join( settings.keys_config
+                .unwrap_or_else( || PathBuf::from( KEYS_CONFIG_PATH ) ) ),
+            require_tls: settings.require_tls.unwrap_or( false ),
+            require_signatures: settings.require_signatures.unwrap_or( false ),
+            turtlefs_root,
+        } )
+    }
+}
+
+#[ cfg( test ) ]
+mod tests {
+    use super::*;
+    use scratch::ScratchDir;
+
+    // build a scratch turtlefs-root with a store
+    fn scratch_root() -> ScratchDir {
+        let root = ScratchDir::new( "config" );
+        fs::create_dir_all( root.join( "store" ) ).unwrap();
+        root
+    }
+
+    fn from_args( args: &[&str] ) -> Result< ServerConfig, String > {
+        let args: Vec< String > = args.iter().map( | arg | arg.to_string() ).collect();
+        ServerConfig::from_args( &args )
+    }
+
+    #[ test ]
+    fn accepts_root_and_address() {
+        let scratch = scratch_root();
+        let root = scratch.to_str().unwrap().to_string();
+        let config = from_args( &[ &root, "127.0.0.1:5550" ] ).unwrap();
+        assert_eq!( config.bind, vec![ "127.0.0.1:5550".parse().unwrap() ] );
+        assert_eq!( config.node, "127.0.0.1:5550".parse().unwrap() );
+        assert_eq!( config.store, Path::new( &root ).join( "store" ) );
+        assert_eq!( config.tls_config, Path::new( &root ).join( "tls.yaml" ) );
+        assert_eq!( config.max_connections, 1024 );
+        assert_eq!( config.idle_timeout, Duration::from_secs( 60 ) );
+        assert_eq!( config.log_level, LogLevel::Info );
+    }
+
+    #[ test ]
+    fn flags_take_precedence_over_the_config_file() {
+        let scratch = scratch_root();
+        let root = scratch.to_str().unwrap().to_string();
+        let config_path = Path::new( &root ).join( "server.yaml" );
+        fs::write( &config_path, format!(
+            "root: {}\nbind: [ \"0.0.0.0:5550\", \"[::]:5550\" ]\nnode: 10.0.0.1:5550\n\
+             max_connections: 10\nidle_timeout: 2.5\nlog_level: warn\n", root ) ).unwrap();
+        let config = from_args( &[ "--config", config_path.to_str().unwrap(),
+                                   "--max-connections", "20", "--require-tls" ] ).unwrap();
+        assert_eq!( config.bind.len(), 2 );
+        assert_eq!( config.node, "10.0.0.1:5550".parse().unwrap() );
+        assert_eq!( config.max_connections, 20 );
+        assert_eq!( config.idle_timeout, Duration::from_millis( 2500 ) );
+        assert_eq!( config.handshake_timeout, Duration::from_millis( 2500 ) );
+        assert_eq!( config.log_level, LogLevel::Warn );
+        assert!( config.require_tls );
+
+        let config = from_args( &[ "--config", config_path.to_str().unwrap(),
+                                   "--bind", "127.0.0.1:6000", "--node", "127.0.0.1:6000" ] )
+            .unwrap();
+        assert_eq!( config.bind, vec![ "127.0.0.1:6000".parse().unwrap() ] );
+    }
+
+    #[ test ]
+    fn rejects_invalid_settings() {
+        let scratch = scratch_root();
+        let root = scratch.to_str().unwrap().to_string();
+        let error = | args: &[&str] | from_args( args ).unwrap_err();
+        assert!( error( &[ &root, "localhost" ] ).contains( "must be an address" ) );
+        assert!( error( &[ "--root", &root, "--bind", "127.0.0.1:1", "--max-connections", "0" ] )
+                 .contains( "--max-connections must be a positive whole number" ) );
+        assert!( error( &[ "--root", &root, "--bind", "127.0.0.1:1", "--idle-timeout", "-1" ] )
+                 .contains( "--idle-timeout must be a positive number of seconds" ) );
+        assert!( error( &[ "--root", &root, "--bind", "127.0.0.1:1", "--log-level", "loud" ] )
+                 .contains( "must be one of: error, warn, info, debug" ) );
+        assert!( error( &[ "--root", &root, "--bind", "127.0.0.1:1", "--colour", "on" ] )
+                 .contains( "--colour is not a setting" ) );
+        assert!( error( &[ "--root", &root, "--bind", "127.0.0.1:1", "--node" ] )
+                 .contains( "--node needs a value" ) );
+        assert!( error( &[ "--root", &root, "--bind", "0.0.0.0:5550" ] )
+                 .contains( "node must be given" ) );
+        assert!( error( &[ "--root", &root ] ).contains( "bind" ) );
+        assert!( error( &[ "--bind", "127.0.0.1:1" ] ).contains( "root" ) );
+        assert!( error( &[ "--root", &root, "--bind", "127.0.0.1:1", "--store", "missing" ] )
+                 .contains( "is not a directory" ) );
+
+        let config_path = Path::new( &root ).join( "server.yaml" );
+        fs::write( &config_path, "max_payload_size: lots\n" ).unwrap();
+        let why = error( &[ "--config", config_path.to_str().unwrap() ] );
+        assert!( why.contains( "invalid" ) && why.contains( "max_payload_size must be" ),
+                 "{}", why );
+    }
+}
@@ -0,0 +1,171 @@
+// Directory listings for the LIST action.
+
+use std::fs::{self, FileType};
+use std::io;
+use std::path::{Path, PathBuf};
+use turtlefs::listing::{ListEntry, Listing};
+use store_path;
+
+// most entries sent back in a single LIST reply
+pub const MAX_LIST_ENTRIES: usize = 1000;
+
+// list the entries under dir, sorted by name and starting after the given
+// name, with at most limit entries. Symlinks are only followed when they
+// point inside the store, and never recursed into. The tree is walked in
+// name order, so only the entries up to the end of the page are looked at,
+// and directories wholly before the start of the page are skipped.
+pub fn list( store_root: &Path, dir: &Path, recursive: bool, limit: usize,
+             after: Option< &str > ) -> io::Result< Listing > {
+    let mut walk = Walk {
+        canonical_root: store_root.canonicalize()?,
+        recursive,
+        limit,
+        after,
+        entries: Vec::new(),
+    };
+    walk.visit( dir, "" )?;
+
+    let mut listing = Listing::default();
+    // one entry past the page is only collected to tell that there is more
+    if walk.entries.len() > limit {
+        walk.entries.truncate( limit );
+        listing.next = walk.entries.last().map( | entry | entry.name.clone() );
+    }
+    listing.entries = walk.entries;
+    Ok( listing )
+}
+
+// an entry of a directory being walked, or the entries of one of its
+// subdirectories, which sort as a block right after the key ending in /
+enum Child {
+    Entry( PathBuf, FileType ),
+    Subtree( PathBuf ),
+}
+
+struct Walk<'a> {
+    canonical_root: PathBuf,
+    recursive: bool,
+    limit: usize,
+    after: Option< &'a str >,
+    entries: Vec< ListEntry >,
+}
+
+impl<'a> Walk<'a> {
+    fn is_done( &self ) -> bool {
+        self.entries.len() > self.limit
+    }
+
+    fn visit( &mut self, dir: &Path, prefix: &str ) -> io::Result< () > {
+        let mut children = Vec::new();
+        for dir_entry in fs::read_dir( dir )? {
+            let dir_entry = dir_entry?;
+            let file_name = match dir_entry.file_name().into_string() {
+                Ok( file_name ) => file_name,
+                // request paths are UTF-8, so the file couldn't be requested
+                Err( _ ) => continue,
+            };
+            // partially written files are not part of the store yet
+            if file_name.ends_with( store_path::TEMP_SUFFIX ) {
+                continue;
+            }
+
+            let name = format!( "{}{}", prefix, file_name );
+            let file_type = dir_entry.file_type()?;
+            if self.recursive && file_type.is_dir() {
+                children.push( ( format!( "{}/", name ), Child::Subtree( dir_entry.path() ) ) );
+            }
+            children.push( ( name, Child::Entry( dir_entry.path(), file_type ) ) );
+        }
+        children.sort_by( | a, b | a.0.cmp( &b.0 ) );
+
+        for ( name, child ) in children {
+            if self.is_done() {
+                break;
+            }
+            match child {
+                Child::Subtree( ref path ) => {
+                    // every name in the subtree starts with name, so unless
+                    // the cursor does too they are all on one side of it
+                    if self.after.is_none_or( | after | after < name.as_str() ||
+                                              after.starts_with( name.as_str() ) ) {
+                        self.visit( path, &name )?;
+                    }
+                },
+                Child::Entry( ref path, file_type ) => {
+                    if self.after.is_none_or( | after | name.as_str() > after ) {
+                        self.add_entry( name, path, file_type )?;
+                    }
+                },
+            }
+        }
+        Ok( () )
+    }
+
+    fn add_entry( &mut self, name: String, path: &Path, file_type: FileType )
+                  -> io::Result< () > {
+        let metadata = if file_type.is_symlink() {
+            match path.canonicalize() {
+                Ok( ref target ) if target.starts_with( &self.canonical_root ) =>
+                    fs::metadata( target )?,
+                _ => return Ok( () ),
+            }
+        } else {
+            fs::symlink_metadata( path )?
+        };
+        self.entries.push( ListEntry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+        } );
+        Ok( () )
+    }
+}
+
+#[ cfg( test ) ]
+mod tests {
+    use super::*;
+    use scratch::ScratchDir;
+
+    // every name under the store, listed limit at a time
+    fn list_in_pages( store: &Path, recursive: bool, limit: usize ) -> Vec< String > {
+        let mut names = Vec::new();
+        let mut after = None;
+        loop {
+            let listing = list( store, store, recursive, limit, after.as_deref() ).unwrap();
+            assert!( listing.entries.len() <= limit );
+            names.extend( listing.entries.into_iter().map( | entry | entry.name ) );
+            match listing.next {
+                Some( next ) => after = Some( next ),
+                None => return names,
+            }
+        }
+    }
+
+    #[ test ]
+    fn pages_follow_name_order() {
+        let root = ScratchDir::new( "directory" );
+        let store = root.join( "store" );
+        // '-' sorts before '/', so a-c comes between a and a/b
+        fs::create_dir_all( store.join( "a" ).join( "d" ) ).unwrap();
+        fs::write( store.join( "a" ).join( "b" ), "b" ).unwrap();
+        fs::write( store.join( "a" ).join( "d" ).join( "e" ), "e" ).unwrap();
+        fs::write( store.join( "a-c" ), "c" ).unwrap();
+        fs::write( store.join( "b.txt" ), "b" ).unwrap();
+        fs::write( store.join( format!( "f{}", store_path::TEMP_SUFFIX ) ), "" ).unwrap();
+
+        let all = [ "a", "a-c", "a/b", "a/d", "a/d/e", "b.txt" ];
+        for limit in 1 .. all.len() + 2 {
+            assert_eq!( list_in_pages( &store, true, limit ), all );
+            assert_eq!( list_in_pages( &store, false, limit ), [ "a", "a-c", "b.txt" ] );
+        }
+
+        // starting part way into a directory, or after one
+        let listing = list( &store, &store, true, 10, Some( "a/c" ) ).unwrap();
+        let names: Vec< &str > = listing.entries.iter().map( | entry | entry.name.as_str() )
+            .collect();
+        assert_eq!( names, [ "a/d", "a/d/e", "b.txt" ] );
+        let listing = list( &store, &store, true, 10, Some( "a/e" ) ).unwrap();
+        assert_eq!( listing.entries.len(), 1 );
+        assert_eq!( listing.next, None );
+    }
+}
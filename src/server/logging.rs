@@ -0,0 +1,85 @@
+// Log output filtered by the configured log level. Errors and warnings go
+// to stderr, everything else to stdout.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[ derive( Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord ) ]
+pub enum LogLevel {
+    // failures of the server itself, e.g. replication or consensus
+    Error,
+    // refused or failed requests
+    Warn,
+    // requests served, files replicated and consensus changes
+    Info,
+    // the settings the server started with
+    Debug,
+}
+
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new( LogLevel::Info as usize );
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str( level: &str ) -> Result< LogLevel, String > {
+        match level {
+            "error" => Ok( LogLevel::Error ),
+            "warn" => Ok( LogLevel::Warn ),
+            "info" => Ok( LogLevel::Info ),
+            "debug" => Ok( LogLevel::Debug ),
+            _ => Err( format!( "must be one of: error, warn, info, debug, not {:?}", level ) ),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        fmt.write_str( match *self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        } )
+    }
+}
+
+pub fn set_level( level: LogLevel ) {
+    LOG_LEVEL.store( level as usize, Ordering::Relaxed );
+}
+
+pub fn enabled( level: LogLevel ) -> bool {
+    level as usize <= LOG_LEVEL.load( Ordering::Relaxed )
+}
+
+macro_rules! log_error {
+    ( $( $arg:tt )* ) => {
+        if $crate::logging::enabled( $crate::logging::LogLevel::Error ) {
+            eprintln!( $( $arg )* );
+        }
+    };
+}
+
+macro_rules! log_warn {
+    ( $( $arg:tt )* ) => {
+        if $crate::logging::enabled( $crate::logging::LogLevel::Warn ) {
+            eprintln!( $( $arg )* );
+        }
+    };
+}
+
+macro_rules! log_info {
+    ( $( $arg:tt )* ) => {
+        if $crate::logging::enabled( $crate::logging::LogLevel::Info ) {
+            println!( $( $arg )* );
+        }
+    };
+}
+
+macro_rules! log_debug {
+    ( $( $arg:tt )* ) => {
+        if $crate::logging::enabled( $crate::logging::LogLevel::Debug ) {
+            println!( $( $arg )* );
+        }
+    };
+}
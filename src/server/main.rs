@@ -1,264 +1,1004 @@
 extern crate tokio;
+extern crate tokio_threadpool;
+extern crate tokio_openssl;
+extern crate openssl;
 extern crate regex;
+extern crate crc;
+extern crate rand;
+extern crate yaml_rust;
+extern crate turtlefs;
 
-use regex::Regex;
-use std::fs::File;
+#[ macro_use ]
+mod logging;
+mod acl;
+mod checksum;
+mod config;
+mod directory;
+mod manifest_edit;
+mod raft;
+mod replay;
+mod replication;
+#[ cfg( test ) ]
+mod scratch;
+mod store_path;
+
+use regex::bytes::Regex;
+use std::fs::{self, File, OpenOptions};
 use std::path::Path;
-use std::io::Seek;
+use std::io::{Seek, Write};
 use std::env;
+#[ cfg( unix ) ]
+use std::os::unix::fs::PermissionsExt;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
 use tokio::prelude::*;
+use tokio::prelude::future::{Either, Loop};
 use tokio::io;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_openssl::SslAcceptorExt;
+use openssl::ssl::SslAcceptor;
+use turtlefs::digest::Algorithm;
+use turtlefs::manifest::MANIFEST_PATH;
+use turtlefs::node;
+use turtlefs::pool::{ConnectionPool, POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE};
+use turtlefs::protocol::{self, Opcode, Status};
+use turtlefs::signing::{KeyRing, Signature, SignatureError, SIGNATURE_ARG_COUNT};
+use turtlefs::stat::FileStat;
+use turtlefs::tls::TlsSettings;
+use acl::AclCache;
+use checksum::ChecksumCache;
+use config::ServerConfig;
+use raft::Raft;
+use replay::NonceCache;
+
+// message sent back for a successful operation
+enum Reply {
+    // a message held entirely in memory
+    Bytes( Vec< u8 > ),
+    // a file positioned at the start of a range, along with the range
+    // length, which is streamed to the client in bounded chunks
+    FileRange( File, u64 ),
+}
+
+impl Reply {
+    fn len( &self ) -> u64 {
+        match *self {
+            Reply::Bytes( ref bytes ) => bytes.len() as u64,
+            Reply::FileRange( _, length ) => length,
+        }
+    }
+}
+
+// state shared by every connection
+struct ServerState {
+    // where files are kept, normally turtlefs-root/store
+    store_root: PathBuf,
+    // consensus member, if this node is a root node
+    raft: Option< Arc< Raft > >,
+    // checksums of the files in the store
+    checksum_cache: Arc< ChecksumCache >,
+    // refuse requests over plaintext connections
+    require_tls: bool,
+    // keys which requests may be signed with
+    key_ring: Option< KeyRing >,
+    nonce_cache: NonceCache,
+    // who may do what to which files
+    acl_cache: AclCache,
+    // largest payload, and path and args, accepted in a single request
+    max_payload_size: u64,
+    max_args_size: u64,
+    // connections with no new request for this long are closed
+    idle_timeout: Duration,
+    handshake_timeout: Duration,
+}
+
+// the client end of a connection
+#[ derive( Clone, Copy ) ]
+struct Peer {
+    addr: SocketAddr,
+    // whether the connection is encrypted with TLS
+    tls: bool,
+}
 
 // operation result in the form: Ok( message, info ), Err( why )
-type OperResult = Result< ( Vec< u8 >, String ), String >;
+type OperResult = Result< ( Reply, String ), String >;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let turtlefs_root = (&args[ 1 ]).clone();
-    let address = args[ 2 ].parse().unwrap();
+// request result in the form: Ok( message, info ), Err( ( status, why ) )
+type RequestResult = Result< ( Reply, String ), ( Status, String ) >;
 
-    let listener = TcpListener::bind( &address )
-        .expect( "cannot bind TCP listener" );
+// largest piece of a file held in memory at once while streaming it
+const STREAM_CHUNK_SIZE: u64 = 64 * 1024;
 
-    println!( "Running file server on {}", address );
+// first byte of a TLS handshake record, which can never start a framed or
+// legacy request
+const TLS_HANDSHAKE_BYTE: u8 = 0x16;
 
-    // handle incoming connections
-    let server = listener.incoming()
-        .map_err( | e | eprintln!( "accept failed = {:?}", e ) )
-        .for_each( move | socket | {
-            let peer_addr = socket.peer_addr().unwrap();
+// perform the requested action against a file in the store
+fn handle_request( server: &ServerState, file_name: PathBuf, action: Opcode,
+                   action_args: &[&str], payload: &[u8] ) -> OperResult {
+    /* ERROR MESSAGES */
 
-            // requests must be in the form: /filename:(action,arg1,arg2,...)
-            let request_re = Regex::new( r"^(/[^:]+):\(([^()]+)\)$" ).unwrap();
+    let too_many_args_err = | num_args, action: Opcode | {
+        Err( format!(
+            "too many arguments ({}) provided for the action: {}",
+            num_args, action.name() ) )
+    };
 
-            // allocated for the filename
-            let mut file_name = PathBuf::from( turtlefs_root.clone() );
-            file_name.push( "store" );
+    let num_arg_err = | why, n | {
+        Err( format!(
+            "could not convert \"{}\" to unsigned integer: {}",
+            n, why ) )
+    };
 
-            // split socket into reader and writer
-            let ( reader, writer ) = socket.split();
+    let checksum_algorithm = | algorithm_name: &str | {
+        Algorithm::from_name( algorithm_name ).ok_or_else( || format!(
+            "checksum algorithm ({}) must be one of: crc32, sha256, blake3",
+            algorithm_name ) )
+    };
 
-            let buf: Vec<u8> = Vec::new();
+    let no_info_err = | why, file_name | {
+        Err( format!(
+            "could not retrieve file metadata for {:?}: {}",
+            file_name, why ) )
+    };
 
-            let handle_conn = io::read_to_end( reader, buf )
-                .and_then( move | ( _, buf ) | {
-                    /* ERROR MESSAGES */
+    let unexpected_payload_err = | num_bytes, action: Opcode | {
+        Err( format!(
+            "unexpected payload ({} bytes) provided for the action: {}",
+            num_bytes, action.name() ) )
+    };
 
-                    let format_err = Err( format!(
-                        "invalid request, \
-                         correct format: /filename:(action,arg1,arg2,...)" ) );
+    macro_rules! unwrap_result {
+        ( $r:expr, $e:expr, $( $a:expr ),* ) => {
+            {
+                match $r {
+                    Ok( v ) => v,
+                    Err( why ) => {
+                        return $e( why, $( $a ),* );
+                    },
+                }
+            }
+        };
+    }
 
-                    let too_many_args_err = | num_args, action | {
-                        Err( format!(
-                            "too many arguments ({}) provided for the action: {}",
-                            num_args, action ) )
-                    };
+    /* OPERATION FUNCTIONS */
 
-                    let num_arg_err = | why, n | {
-                        Err( format!(
-                            "could not convert \"{}\" to unsigned integer: {}",
-                            n, why ) )
-                    };
+    // open file and seek to the start of the range, the range itself is
+    // read as it is streamed out
+    let file_read_func = | file_name, start_offset, end_offset | {
+        let path = Path::new( file_name );
 
-                    let unrecognized_action_err = | action | {
-                        Err( format!( "unrecognized action: {}", action ) )
+        match File::open( file_name ) {
+            Ok( mut file ) => {
+                let file_seek_oper =
+                    match file.seek( std::io::SeekFrom::Start( start_offset ) ) {
+                        Ok( _ ) => Ok( () ),
+                        Err( why ) => Err( format!(
+                            "could not read {}: {}", path.display(),
+                            why ) ),
                     };
 
-                    let no_info_err = | why, file_name | {
-                        Err( format!(
-                            "could not retrieve file metadata for {:?}: {}",
-                            file_name, why ) )
-                    };
+                match file_seek_oper {
+                    Ok( _ ) => Ok( ( Reply::FileRange( file, end_offset - start_offset ),
+                                     format!(
+                        "sending file contents of {:?}, \
+                         start offset {}, end offset {}",
+                        file_name, start_offset, end_offset ) ) ),
+                    Err( why ) => Err( why ),
+                }
+            },
+            Err( why ) => Err( format!(
+                "couldn't open {}: {}", path.display(),
+                why ) ),
+        }
+    };
 
-                    /* OPERATION FUNCTIONS */
-
-                    // write file contents (or error) into writer socket
-                    // and log info
-                    let send_and_log = | oper_result: OperResult | {
-                        match oper_result {
-                            Ok( ( result, info ) ) => {
-                                println!( "{}: {}",
-                                           peer_addr, info );
-                                let mut message: Vec<u8> = vec![ 0 ];
-                                message.extend_from_slice( &result );
-                                io::write_all( writer, message )
-                            },
-                            Err( why ) => {
-                                eprintln!( "{}: {}", peer_addr, why );
-                                let mut message: Vec<u8> = vec![ 1 ];
-                                message.extend_from_slice(
-                                    &format!( "{}\n", why ).into_bytes() );
-                                io::write_all( writer, message )
-                            },
-                        }
-                    };
+    let file_length_func = | file_name: PathBuf | {
+        match std::fs::metadata( file_name.clone() ) {
+            Ok( m ) => Ok( ( Reply::Bytes( format!( "{}", m.len() ).into_bytes() ),
+                             format!( "sending file length of {:?}: {}",
+                                       file_name, m.len() ) ) ),
+            Err( why ) => no_info_err( why, file_name ),
+        }
+    };
 
-                    macro_rules! unwrap_option {
-                        ( $o:expr, $e:expr ) => {
-                            {
-                                match $o {
-                                    Some( v ) => v,
-                                    None => {
-                                        return send_and_log( $e );
-                                    },
-                                }
-                            }
-                        };
-                    }
+    // size, modification time, permissions and checksum in one reply
+    let file_stat_func = | file_name: PathBuf, algorithm: Algorithm | {
+        let metadata = unwrap_result!(
+            std::fs::metadata( file_name.clone() ), no_info_err, file_name );
+        let checksum = unwrap_result!(
+            server.checksum_cache.checksum( &file_name, algorithm ), no_info_err, file_name );
+        let modified = metadata.modified().ok()
+            .and_then( | modified | modified.duration_since( UNIX_EPOCH ).ok() )
+            .map_or( 0, | modified | modified.as_secs() );
+        let file_stat = FileStat {
+            size: metadata.len(),
+            modified,
+            permissions: permission_bits( &metadata ),
+            checksum,
+        };
+        Ok( ( Reply::Bytes( file_stat.to_yaml().into_bytes() ),
+              format!( "sending file metadata of {:?}: size {}, checksum {}",
+                       file_name, file_stat.size, file_stat.checksum ) ) )
+    };
 
-                    macro_rules! unwrap_result {
-                        ( $r:expr, $e:expr ) => {
-                            {
-                                match $r {
-                                    Ok( v ) => v,
-                                    Err( why ) => {
-                                        return send_and_log(
-                                            $e( why ) );
-                                    },
-                                }
-                            }
-                        };
-                        ( $r:expr, $e:expr, $( $a:expr ),* ) => {
-                            {
-                                match $r {
-                                    Ok( v ) => v,
-                                    Err( why ) => {
-                                        return send_and_log(
-                                            $e( why, $( $a ),* ) );
-                                    },
-                                }
-                            }
-                        };
-                    }
+    let store_root = &server.store_root;
 
-                    // open file and read from it
-                    let file_read_func = | file_name, start_offset, end_offset | {
-                        let path = Path::new( file_name );
-                        let length: usize = end_offset as usize -
-                            start_offset as usize;
-
-                        match File::open( &file_name ) {
-                            Ok( mut file ) => {
-                                let mut file_buf = vec![ 0u8; length ];
-
-                                file.seek( std::io::SeekFrom::Start(
-                                    start_offset as u64 ) ).unwrap();
-
-                                let file_read_oper =
-                                    match file.read_exact( &mut file_buf ) {
-                                        Ok( _ ) => Ok( () ),
-                                        Err( why ) => Err( format!(
-                                            "could not read {}: {}", path.display(),
-                                            why ) ),
-                                    };
-
-                                match file_read_oper {
-                                    Ok( _ ) => Ok( ( file_buf, format!(
-                                        "sending file contents of {:?}, \
-                                         start offset {}, end offset {}",
-                                        file_name, start_offset, end_offset ) ) ),
-                                    Err( why ) => Err( why ),
-                                }
-                            },
-                            Err( why ) => Err( format!(
-                                "couldn't open {}: {}", path.display(),
-                                why ) ),
-                        }
-                    };
+    // entries under a directory, a page at a time
+    let dir_list_func = | file_name: PathBuf, recursive, limit, after: Option< &str > | {
+        match directory::list( store_root, &file_name, recursive, limit, after ) {
+            Ok( listing ) => {
+                let info = format!( "sending listing of {:?}: {} entries{}", file_name,
+                                    listing.entries.len(),
+                                    if listing.next.is_some() { ", more to come" } else { "" } );
+                Ok( ( Reply::Bytes( listing.to_yaml().into_bytes() ), info ) )
+            },
+            Err( why ) => Err( format!( "could not list {:?}: {}", file_name, why ) ),
+        }
+    };
 
-                    let file_length_func = | file_name: PathBuf | {
-                        match std::fs::metadata( file_name.clone() ) {
-                            Ok( m ) => Ok( ( format!( "{}", m.len() ).into_bytes(),
-                                             format!( "sending file length of {:?}: {}",
-                                                       file_name, m.len() ) ) ),
-                            Err( why ) => no_info_err( why, file_name ),
-                        }
-                    };
+    // checksum of the whole file, which may be cached, or of a range
+    let file_checksum_func = | file_name: PathBuf, algorithm: Algorithm,
+                               range: Option< ( u64, u64 ) > | {
+        let checksum = match range {
+            None => server.checksum_cache.checksum( &file_name, algorithm ),
+            Some( ( start_offset, end_offset ) ) =>
+                checksum::range_checksum( &file_name, start_offset, end_offset, algorithm ),
+        };
+        match checksum {
+            Ok( checksum ) => {
+                let info = match range {
+                    Some( ( start_offset, end_offset ) ) => format!( "sending checksum of {:?}, start offset {}, \
+                                           end offset {}: {}",
+                                          file_name, start_offset, end_offset, checksum ),
+                    None => format!( "sending checksum of {:?}: {}", file_name, checksum ),
+                };
+                Ok( ( Reply::Bytes( checksum.to_string().into_bytes() ), info ) )
+            },
+            Err( why ) => Err( format!(
+                "could not checksum {}: {}", file_name.display(), why ) ),
+        }
+    };
 
-                    /* REQUEST PARSING */
+    // remove a file, but never a directory or the manifest
+    let file_delete_func = | file_name: PathBuf | {
+        if file_name == store_root.join( &MANIFEST_PATH[ 1 .. ] ) {
+            return Err( format!( "{} cannot be deleted", MANIFEST_PATH ) );
+        }
+        match fs::remove_file( &file_name ) {
+            Ok( _ ) => {
+                server.checksum_cache.forget( &file_name );
+                Ok( ( Reply::Bytes( Vec::new() ), format!( "deleted {:?}", file_name ) ) )
+            },
+            Err( why ) => Err( format!(
+                "could not delete {}: {}", file_name.display(), why ) ),
+        }
+    };
 
-                    let request_contents = std::str::from_utf8( &buf[ .. ] ).unwrap();
+    // write payload into file, either replacing the whole
+    // file or starting at the given offset
+    let file_write_func = | file_name: &PathBuf, offset: Option< u64 >,
+                            payload: &[u8] | {
+        let path = Path::new( file_name );
 
-                    let captures = unwrap_option!(
-                        request_re.captures( request_contents ), format_err );
+        if let Some( parent ) = path.parent() {
+            if let Err( why ) = fs::create_dir_all( parent ) {
+                return Err( format!(
+                    "couldn't create directory {}: {}",
+                    parent.display(), why ) );
+            }
+        }
 
-                    let request_file_name = unwrap_option!( captures.get( 1 ),
-                                                            format_err ).as_str();
-                    for path_piece in request_file_name.split( "/" ) {
-                        file_name.push( path_piece );
-                    }
+        let file_write_oper = match offset {
+            // write to a temporary file and rename it into
+            // place so readers never see a partial file
+            None => store_path::replace_file( file_name,
+                                              | file | file.write_all( payload ) ),
+            Some( offset ) => {
+                OpenOptions::new().write( true ).create( true ).truncate( false )
+                    .open( file_name )
+                    .and_then( | mut file | {
+                        file.seek( std::io::SeekFrom::Start(
+                            offset ) )?;
+                        file.write_all( payload )
+                    } )
+            },
+        };
 
-                    let actions_vec = unwrap_option!(
-                        captures.get( 2 ), format_err )
-                        .as_str()
-                        .split( "," )
-                        .collect::< Vec< &str > >();
+        match file_write_oper {
+            Ok( _ ) => Ok( ( Reply::Bytes( Vec::new() ), format!(
+                "wrote {} bytes to {:?}, start offset {}",
+                payload.len(), file_name, offset.unwrap_or( 0 ) ) ) ),
+            Err( why ) => Err( format!(
+                "could not write {}: {}", path.display(),
+                why ) ),
+        }
+    };
 
-                    if actions_vec.len() == 0 {
-                        return send_and_log( format_err );
-                    }
+    /* REQUEST HANDLING */
 
-                    let action = actions_vec[ 0 ];
-                    let action_args = &actions_vec[ 1 .. ];
-
-                    // we need file length for a couple of different things,
-                    // so we go ahead and retrieve that now
-                    let file_length = unwrap_result!(
-                        std::fs::metadata( file_name.clone() ), no_info_err, file_name )
-                        .len() as u32;
-
-                    if action == "READ" {
-                        let mut start_offset = 0;
-                        let mut end_offset = file_length;
-                        if action_args.len() >= 1 {
-                            let start_offset_str = action_args[ 0 ];
-                            start_offset = unwrap_result!(
-                                start_offset_str.parse::< u32 >(), num_arg_err,
-                                String::from( start_offset_str ) );
-                            if start_offset >= file_length {
-                                return send_and_log( Err( format!(
-                                    "start offset ({}) must be: \
-                                     less than file length ({})",
-                                    start_offset, file_length ) ) );
-                            }
-                        }
-                        if action_args.len() >= 2 {
-                            let end_offset_str = action_args[ 1 ];
-                            end_offset = unwrap_result!(
-                                end_offset_str.parse::< u32 >(), num_arg_err,
-                                String::from( end_offset_str ) );
-                            if end_offset < 1 || end_offset > file_length ||
-                                end_offset <= start_offset {
-                                return send_and_log( Err( format!(
-                                    "end offset ({}) must be: \
-                                     greater than zero, \
-                                     greater than start offset ({}), \
-                                     less than or equal to file length ({})",
-                                    end_offset, start_offset, file_length ) ) );
+    // only WRITE accepts a payload
+    if action != Opcode::Write && !payload.is_empty() {
+        return unexpected_payload_err( payload.len(), action );
+    }
+
+    match action {
+        Opcode::Read => {
+            let file_length = unwrap_result!(
+                std::fs::metadata( file_name.clone() ), no_info_err, file_name )
+                .len();
+
+            let mut start_offset = 0;
+            let mut end_offset = file_length;
+            if !action_args.is_empty() {
+                let start_offset_str = action_args[ 0 ];
+                start_offset = unwrap_result!(
+                    start_offset_str.parse::< u64 >(), num_arg_err,
+                    String::from( start_offset_str ) );
+                if start_offset >= file_length {
+                    return Err( format!(
+                        "start offset ({}) must be: \
+                         less than file length ({})",
+                        start_offset, file_length ) );
+                }
+            }
+            if action_args.len() >= 2 {
+                let end_offset_str = action_args[ 1 ];
+                end_offset = unwrap_result!(
+                    end_offset_str.parse::< u64 >(), num_arg_err,
+                    String::from( end_offset_str ) );
+                if end_offset < 1 || end_offset > file_length ||
+                    end_offset <= start_offset {
+                    return Err( format!(
+                        "end offset ({}) must be: \
+                         greater than zero, \
+                         greater than start offset ({}), \
+                         less than or equal to file length ({})",
+                        end_offset, start_offset, file_length ) );
+                }
+            }
+            if action_args.len() > 2 {
+                return too_many_args_err( action_args.len(), action );
+            }
+            file_read_func( &file_name, start_offset, end_offset )
+        },
+        Opcode::Length => {
+            if action_args.is_empty() {
+                file_length_func( file_name )
+            } else {
+                too_many_args_err( action_args.len(), action )
+            }
+        },
+        Opcode::Write => {
+            let mut offset = None;
+            if !action_args.is_empty() {
+                let offset_str = action_args[ 0 ];
+                let start_offset = unwrap_result!(
+                    offset_str.parse::< u64 >(), num_arg_err,
+                    String::from( offset_str ) );
+                // the file may not exist yet
+                let file_length = match std::fs::metadata(
+                    file_name.clone() ) {
+                    Ok( m ) => m.len(),
+                    Err( _ ) => 0,
+                };
+                if start_offset > file_length {
+                    return Err( format!(
+                        "start offset ({}) must be: \
+                         less than or equal to file length ({})",
+                        start_offset, file_length ) );
+                }
+                offset = Some( start_offset );
+            }
+            if action_args.len() > 1 {
+                return too_many_args_err( action_args.len(), action );
+            }
+            file_write_func( &file_name, offset, payload )
+        },
+        Opcode::Stat => {
+            if action_args.len() > 1 {
+                return too_many_args_err( action_args.len(), action );
+            }
+            let algorithm = match action_args.first() {
+                Some( &algorithm_name ) => checksum_algorithm( algorithm_name )?,
+                None => Algorithm::Crc32,
+            };
+            file_stat_func( file_name, algorithm )
+        },
+        Opcode::List => {
+            let mut recursive = false;
+            let mut limit = directory::MAX_LIST_ENTRIES;
+            let mut after = None;
+            if !action_args.is_empty() {
+                recursive = match action_args[ 0 ] {
+                    "0" => false,
+                    "1" => true,
+                    recursive_str => return Err( format!(
+                        "recursive ({}) must be: 0 or 1", recursive_str ) ),
+                };
+            }
+            // an empty limit or after argument leaves it at its default
+            if action_args.len() >= 2 && !action_args[ 1 ].is_empty() {
+                let limit_str = action_args[ 1 ];
+                limit = unwrap_result!(
+                    limit_str.parse::< usize >(), num_arg_err,
+                    String::from( limit_str ) );
+                if !( 1 ..= directory::MAX_LIST_ENTRIES ).contains( &limit ) {
+                    return Err( format!(
+                        "limit ({}) must be: greater than zero, at most {}",
+                        limit, directory::MAX_LIST_ENTRIES ) );
+                }
+            }
+            if action_args.len() >= 3 && !action_args[ 2 ].is_empty() {
+                after = Some( action_args[ 2 ] );
+            }
+            if action_args.len() > 3 {
+                return too_many_args_err( action_args.len(), action );
+            }
+            dir_list_func( file_name, recursive, limit, after )
+        },
+        Opcode::Checksum => {
+            let algorithm = match action_args.first() {
+                Some( &algorithm_name ) => checksum_algorithm( algorithm_name )?,
+                None => Algorithm::Crc32,
+            };
+            let mut range = None;
+            if action_args.len() == 2 {
+                return Err( String::from(
+                    "a checksum range needs both a start and an end offset" ) );
+            }
+            if action_args.len() >= 3 {
+                let file_length = unwrap_result!(
+                    std::fs::metadata( file_name.clone() ), no_info_err, file_name )
+                    .len();
+                let start_offset_str = action_args[ 1 ];
+                let start_offset = unwrap_result!(
+                    start_offset_str.parse::< u64 >(), num_arg_err,
+                    String::from( start_offset_str ) );
+                let end_offset_str = action_args[ 2 ];
+                let end_offset = unwrap_result!(
+                    end_offset_str.parse::< u64 >(), num_arg_err,
+                    String::from( end_offset_str ) );
+                if end_offset > file_length || end_offset <= start_offset {
+                    return Err( format!(
+                        "end offset ({}) must be: \
+                         greater than start offset ({}), \
+                         less than or equal to file length ({})",
+                        end_offset, start_offset, file_length ) );
+                }
+                range = Some( ( start_offset, end_offset ) );
+            }
+            if action_args.len() > 3 {
+                return too_many_args_err( action_args.len(), action );
+            }
+            file_checksum_func( file_name, algorithm, range )
+        },
+        Opcode::Delete => {
+            if action_args.is_empty() {
+                file_delete_func( file_name )
+            } else {
+                too_many_args_err( action_args.len(), action )
+            }
+        },
+        Opcode::Edit | Opcode::Vote | Opcode::Append | Opcode::Snapshot =>
+            unreachable!( "{} is dispatched separately", action.name() ),
+    }
+}
+
+// permission bits of a file, which other platforms only have a read-only
+// flag for
+#[ cfg( unix ) ]
+fn permission_bits( metadata: &fs::Metadata ) -> u32 {
+    metadata.permissions().mode() & 0o7777
+}
+
+#[ cfg( not( unix ) ) ]
+fn permission_bits( metadata: &fs::Metadata ) -> u32 {
+    if metadata.permissions().readonly() { 0o444 } else { 0o644 }
+}
+
+// only root nodes take part in consensus
+fn root_raft( server: &ServerState, action: Opcode ) -> Result< &Raft, ( Status, String ) > {
+    match server.raft {
+        Some( ref raft ) => Ok( raft ),
+        None => Err( ( Status::Error, format!(
+            "{} must be sent to a root node", action.name() ) ) ),
+    }
+}
+
+// resolve the requested file name inside the store, check that whoever
+// signed the request may perform the action on it, then perform the action
+fn dispatch_request( server: &ServerState, principal: Option< &str >, action: Opcode,
+                     request_file_name: &str, action_args: &[&str], payload: &[u8] )
+                     -> RequestResult {
+    // consensus messages between the root nodes don't name a file, and are
+    // too frequent to log
+    if action == Opcode::Vote || action == Opcode::Append || action == Opcode::Snapshot {
+        server.acl_cache.check( principal, action, Path::new( MANIFEST_PATH ) )?;
+    }
+    match action {
+        Opcode::Vote => return root_raft( server, action )?.handle_vote( action_args )
+            .map( | reply | ( Reply::Bytes( reply ), String::new() ) ),
+        Opcode::Append => return root_raft( server, action )?
+            .handle_append( action_args, payload )
+            .map( | reply | ( Reply::Bytes( reply ), String::new() ) ),
+        Opcode::Snapshot => return root_raft( server, action )?
+            .handle_snapshot( action_args, payload )
+            .map( | reply | ( Reply::Bytes( reply ), String::new() ) ),
+        _ => {},
+    }
+
+    let store_root = &server.store_root;
+
+    // READ, WRITE and DELETE only ever act on a single file
+    let resolve = match action {
+        Opcode::Read | Opcode::Write | Opcode::Delete => store_path::resolve_file,
+        _ => store_path::resolve,
+    };
+    let file_name = resolve( store_root, request_file_name )
+        .map_err( | why | ( why.status(), format!(
+            "invalid path {:?}: {}", request_file_name, why ) ) )?;
+    let request_path = Path::new( "/" ).join( file_name.strip_prefix( store_root )
+        .map_err( | why | ( Status::Error, why.to_string() ) )? );
+    server.acl_cache.check( principal, action, &request_path )?;
+
+    // EDIT changes the manifest entry for the file, not the file itself. The
+    // entry is keyed by the path the ACL was checked against, so /./a and
+    // //a edit the entry for /a.
+    if action == Opcode::Edit {
+        if !payload.is_empty() {
+            return Err( ( Status::Error, format!(
+                "unexpected payload ({} bytes) provided for the action: {}",
+                payload.len(), action.name() ) ) );
+        }
+        let entry_name = request_path.to_string_lossy();
+        let version = root_raft( server, action )?
+            .propose_edit( &entry_name, action_args )?;
+        return Ok( ( Reply::Bytes( version.to_string().into_bytes() ), format!(
+            "applied {} to the file_store.yaml entry for {}, now at version {}",
+            action_args[ 1 .. ].join( "," ), entry_name, version ) ) );
+    }
+
+    // root nodes answer for file_store.yaml from the consensus log, so
+    // every root node hands out the same manifest
+    if let Some( ref raft ) = server.raft {
+        if file_name == store_root.join( &MANIFEST_PATH[ 1 .. ] ) {
+            return match action {
+                Opcode::Read if action_args.is_empty() && payload.is_empty() => {
+                    let manifest = raft.read_manifest()?;
+                    let info = format!( "sending {} ({} bytes)", MANIFEST_PATH,
+                                        manifest.len() );
+                    Ok( ( Reply::Bytes( manifest ), info ) )
+                },
+                Opcode::Length if action_args.is_empty() && payload.is_empty() => {
+                    let length = raft.read_manifest()?.len();
+                    Ok( ( Reply::Bytes( length.to_string().into_bytes() ), format!(
+                        "sending file length of {} ({})", MANIFEST_PATH, length ) ) )
+                },
+                _ => Err( ( Status::Error, format!(
+                    "{} only supports whole-file READ and LENGTH on root nodes, \
+                     use EDIT to change it", MANIFEST_PATH ) ) ),
+            };
+        }
+    }
+
+    handle_request( server, file_name, action, action_args, payload )
+        .map_err( | why | ( Status::Error, why ) )
+}
+
+// refuse requests which this node doesn't accept before anything is done
+// with them, returning the name of the key a request was signed with
+fn admit_request( server: &ServerState, peer: &Peer, action: Opcode, path: &str,
+                  args: &[String], signature: Option< Signature >, payload: &[u8] )
+                  -> Result< Option< String >, ( Status, String ) > {
+    if server.require_tls && !peer.tls {
+        return Err( ( Status::TlsRequired, String::from(
+            "this node only accepts requests over TLS" ) ) );
+    }
+
+    let key_ring = match server.key_ring {
+        Some( ref key_ring ) => key_ring,
+        None => return Ok( None ),
+    };
+    let signed_by = match signature {
+        Some( ref signature ) =>
+            key_ring.verify( action, path, args, signature, payload ).and_then( | key_name | {
+                server.nonce_cache.check( signature ).map_err( SignatureError::Replayed )?;
+                Ok( Some( String::from( key_name ) ) )
+            } ),
+        None if key_ring.require_signatures => Err( SignatureError::Unsigned ),
+        None => Ok( None ),
+    };
+    signed_by.map_err( | why | match why {
+        SignatureError::Unsigned => ( Status::Unsigned, String::from(
+            "this node only accepts signed requests" ) ),
+        SignatureError::Replayed( why ) => ( Status::Replayed, format!(
+            "refused a replayed request: {}", why ) ),
+        SignatureError::BadSignature( why ) => ( Status::BadSignature, format!(
+            "refused a badly signed request: {}", why ) ),
+    } )
+}
+
+// parse a legacy text request, which must be in the form:
+// /filename:(action,arg1,arg2,...) and may be followed by a payload. Legacy
+// requests can't be signed.
+fn handle_legacy_request( server: &ServerState, peer: &Peer, buf: &[u8] ) -> RequestResult {
+    let request_re = Regex::new( r"^(/[^:]*):\(([^()]+)\)" ).unwrap();
+
+    let format_err = || Err( ( Status::Error, String::from(
+        "invalid request, \
+         correct format: /filename:(action,arg1,arg2,...)" ) ) );
+
+    let captures = match request_re.captures( buf ) {
+        Some( captures ) => captures,
+        None => return format_err(),
+    };
+
+    // everything after the request header is the payload
+    let header_end = captures.get( 0 ).unwrap().end();
+    let payload = &buf[ header_end .. ];
+
+    let request_file_name = match std::str::from_utf8(
+        captures.get( 1 ).unwrap().as_bytes() ) {
+        Ok( name ) => name,
+        Err( _ ) => return format_err(),
+    };
+
+    let actions_vec = match std::str::from_utf8(
+        captures.get( 2 ).unwrap().as_bytes() ) {
+        Ok( actions ) => actions.split( ',' ).collect::< Vec< &str > >(),
+        Err( _ ) => return format_err(),
+    };
+
+    let action = match Opcode::from_name( actions_vec[ 0 ] ) {
+        Some( action ) => action,
+        None => return Err( ( Status::Error, format!(
+            "unrecognized action: {}", actions_vec[ 0 ] ) ) ),
+    };
+
+    let args: Vec< String > = actions_vec[ 1 .. ].iter()
+        .map( | arg | arg.to_string() )
+        .collect();
+    let principal = admit_request( server, peer, action, request_file_name, &args, None,
+                                   payload )?;
+
+    dispatch_request( server, principal.as_deref(), action, request_file_name,
+                      &actions_vec[ 1 .. ], payload )
+}
+
+// handle a framed request whose header and body have been read
+fn handle_framed_request( server: &ServerState, peer: &Peer, header: &protocol::RequestHeader,
+                          body: &[u8], payload: &[u8] ) -> RequestResult {
+    let ( path, mut args ) = protocol::decode_request_body( header, body )
+        .map_err( | why | ( Status::Error, why ) )?;
+
+    let opcode = header.opcode & !protocol::SIGNED_FLAG;
+    let action = match Opcode::from_u8( opcode ) {
+        Some( action ) => action,
+        None => return Err( ( Status::Error, format!(
+            "unrecognized opcode: {}", opcode ) ) ),
+    };
+
+    // the signature takes up the last args of a signed request
+    let signature = if header.opcode & protocol::SIGNED_FLAG != 0 {
+        if args.len() < SIGNATURE_ARG_COUNT {
+            return Err( ( Status::BadSignature, format!(
+                "refused a badly signed request: a signature takes {} arguments, not {}",
+                SIGNATURE_ARG_COUNT, args.len() ) ) );
+        }
+        let signature_args = args.split_off( args.len() - SIGNATURE_ARG_COUNT );
+        Some( Signature::from_args( &signature_args ).map_err( | why | match why {
+            SignatureError::BadSignature( why ) => ( Status::BadSignature, format!(
+                "refused a badly signed request: {}", why ) ),
+            _ => ( Status::BadSignature, String::from( "refused a badly signed request" ) ),
+        } )? )
+    } else {
+        None
+    };
+    let principal = admit_request( server, peer, action, &path, &args, signature, payload )?;
+
+    let action_args = args.iter().map( | a | a.as_str() ).collect::< Vec< &str > >();
+    dispatch_request( server, principal.as_deref(), action, &path, &action_args, payload )
+}
+
+// whether a request may wait on consensus among the root nodes (EDIT, or a
+// read of file_store.yaml), which can take up to raft::REQUEST_TIMEOUT.
+// Legacy requests (None) aren't parsed until they are handled.
+fn may_wait_on_consensus( server: &ServerState, action: Option< Opcode > ) -> bool {
+    server.raft.is_some() && match action {
+        Some( Opcode::Edit ) | Some( Opcode::Read ) | Some( Opcode::Length ) | None => true,
+        Some( _ ) => false,
+    }
+}
+
+// handle a request, in a blocking section when it may wait on consensus so
+// that it doesn't hold up the other connections served by the same worker
+// thread
+fn handle_on_worker< F >( may_block: bool, mut handle: F )
+                          -> impl Future< Item = RequestResult, Error = io::Error >
+    where F: FnMut() -> RequestResult
+{
+    if !may_block {
+        return Either::A( future::ok( handle() ) );
+    }
+    Either::B( future::poll_fn( move || tokio_threadpool::blocking( &mut handle )
+        .map_err( | why | io::Error::other( format!( "could not handle request: {}", why ) ) ) ) )
+}
+
+// log the operation result, returning the status and message to reply with
+fn log_result( peer_addr: &SocketAddr, request_result: RequestResult )
+               -> ( Status, Reply ) {
+    match request_result {
+        Ok( ( result, info ) ) => {
+            if !info.is_empty() {
+                log_info!( "{}: {}", peer_addr, info );
+            }
+            ( Status::Ok, result )
+        },
+        Err( ( status, why ) ) => {
+            log_warn!( "{}: {}", peer_addr, why );
+            ( status, Reply::Bytes( format!( "{}\n", why ).into_bytes() ) )
+        },
+    }
+}
+
+// write the response header followed by the reply, file ranges are read
+// and written a chunk at a time so they never sit in memory all at once
+fn write_reply< W >( writer: W, header: Vec< u8 >, reply: Reply )
+                     -> impl Future< Item = W, Error = io::Error >
+    where W: AsyncWrite
+{
+    match reply {
+        Reply::Bytes( bytes ) => {
+            let mut message = header;
+            message.extend_from_slice( &bytes );
+            Either::A( io::write_all( writer, message ).map( | ( writer, _ ) | writer ) )
+        },
+        Reply::FileRange( file, length ) => Either::B(
+            io::write_all( writer, header ).and_then( move | ( writer, _ ) | {
+                future::loop_fn( ( writer, file, length ),
+                                 | ( writer, mut file, remaining ) | {
+                    let mut buf = vec![
+                        0u8; std::cmp::min( remaining, STREAM_CHUNK_SIZE ) as usize ];
+                    future::result( file.read_exact( &mut buf ) )
+                        .and_then( move | _ | io::write_all( writer, buf ) )
+                        .map( move | ( writer, buf ) | {
+                            let remaining = remaining - buf.len() as u64;
+                            if remaining == 0 {
+                                Loop::Break( writer )
+                            } else {
+                                Loop::Continue( ( writer, file, remaining ) )
                             }
+                        } )
+                } )
+            } ) ),
+    }
+}
+
+// serve a single legacy text request, which is terminated by the client
+// closing its write half, and reply with a status byte and the result
+fn serve_legacy< R, W >( reader: R, writer: W, server: Arc< ServerState >, peer: Peer )
+                         -> impl Future< Item = (), Error = io::Error >
+    where R: AsyncRead, W: AsyncWrite
+{
+    // the leading '/' was already read, and anything past the largest
+    // request accepted is only read to tell that it is too large
+    let max_request_size = server.max_args_size + server.max_payload_size;
+    io::read_to_end( reader.take( max_request_size ), vec![ b'/' ] )
+        .and_then( move | ( _, buf ) | {
+            let may_block = may_wait_on_consensus( &server, None );
+            handle_on_worker( may_block, move || if buf.len() as u64 > max_request_size {
+                Err( ( Status::Error, format!(
+                    "request size must be: at most {} bytes", max_request_size ) ) )
+            } else {
+                handle_legacy_request( &server, &peer, &buf )
+            } )
+        } )
+        .and_then( move | request_result | {
+            let ( status, result ) = log_result( &peer.addr, request_result );
+            write_reply( writer, vec![ status as u8 ], result )
+        } )
+        .map( | _ | () )
+}
+
+// serve framed requests until the client closes the connection
+fn serve_framed< R, W >( reader: R, writer: W, first_byte: u8, server: Arc< ServerState >,
+                         peer: Peer )
+                         -> impl Future< Item = (), Error = io::Error >
+    where R: AsyncRead + Send + 'static, W: AsyncWrite + Send + 'static
+{
+    future::loop_fn( ( reader, writer, Some( first_byte ) ),
+                     move | ( reader, writer, first_byte ) | {
+        let server = server.clone();
+
+        // the first byte of the connection was already consumed to
+        // detect the protocol
+        let read_header = match first_byte {
+            Some( first_byte ) => Either::A(
+                io::read_exact( reader, vec![ 0u8; protocol::REQUEST_HEADER_LEN - 1 ] )
+                    .map( move | ( reader, rest ) | {
+                        let mut header_buf = vec![ first_byte ];
+                        header_buf.extend_from_slice( &rest );
+                        ( reader, header_buf )
+                    } ) ),
+            None => Either::B(
+                io::read_exact( reader, vec![ 0u8; protocol::REQUEST_HEADER_LEN ] ) ),
+        };
+
+        read_header
+            .timeout( server.idle_timeout )
+            .map_err( | e | e.into_inner().unwrap_or_else( || io::Error::new(
+                io::ErrorKind::TimedOut, "connection was idle for too long" ) ) )
+            .and_then( move | ( reader, header_buf ) | {
+                let header = protocol::decode_request_header( &header_buf )
+                    .and_then( | header | {
+                        if header.payload_length > server.max_payload_size {
+                            return Err( format!(
+                                "payload length ({}) must be: at most {}",
+                                header.payload_length, server.max_payload_size ) );
                         }
-                        if action_args.len() > 2 {
-                            return send_and_log(
-                                too_many_args_err( action_args.len(), action ) );
-                        }
-                        return send_and_log( file_read_func( &file_name,
-                                                             start_offset,
-                                                             end_offset ) );
-                    } else if action == "LENGTH" {
-                        if action_args.len() == 0 {
-                            return send_and_log( file_length_func( file_name ) );
-                        } else {
-                            return send_and_log(
-                                too_many_args_err( action_args.len(), action ) );
+                        if header.body_length() as u64 > server.max_args_size {
+                            return Err( format!(
+                                "path and args length ({}) must be: at most {}",
+                                header.body_length(), server.max_args_size ) );
                         }
-                    } else {
-                        return send_and_log( unrecognized_action_err( action ) );
-                    }
+                        Ok( header )
+                    } );
+                let header = match header {
+                    Ok( header ) => header,
+                    Err( why ) => {
+                        // the stream cannot be resynchronized, so reply and hang up
+                        let ( status, result ) = log_result(
+                            &peer.addr, Err( ( Status::Error, why ) ) );
+                        let header = protocol::encode_response_header( status, result.len() );
+                        return Either::A( write_reply( writer, header, result )
+                                          .map( | _ | Loop::Break( () ) ) );
+                    },
+                };
+
+                let body = vec![ 0u8; header.body_length() ];
+                let payload = vec![ 0u8; header.payload_length as usize ];
+                Either::B( io::read_exact( reader, body )
+                    .and_then( move | ( reader, body ) | {
+                        io::read_exact( reader, payload )
+                            .map( move | ( reader, payload ) | ( reader, body, payload ) )
+                    } )
+                    .and_then( move | ( reader, body, payload ) | {
+                        let action = Opcode::from_u8( header.opcode & !protocol::SIGNED_FLAG );
+                        let may_block = may_wait_on_consensus( &server, action );
+                        handle_on_worker( may_block, move || handle_framed_request(
+                            &server, &peer, &header, &body, &payload ) )
+                            .map( move | request_result | ( reader, request_result ) )
+                    } )
+                    .and_then( move | ( reader, request_result ) | {
+                        let ( status, result ) = log_result( &peer.addr, request_result );
+                        let header = protocol::encode_response_header( status, result.len() );
+                        write_reply( writer, header, result )
+                            .map( move | writer | Loop::Continue( ( reader, writer, None ) ) )
+                    } ) )
+            } )
+    } )
+}
+
+// serve the requests on a connection, which may be encrypted
+fn serve_connection< S >( stream: S, server: Arc< ServerState >, peer: Peer )
+                          -> impl Future< Item = (), Error = io::Error >
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    // split stream into reader and writer
+    let ( reader, writer ) = stream.split();
 
+    // framed requests start with magic bytes, while legacy
+    // requests start with the '/' of the filename
+    io::read_exact( reader, [ 0u8; 1 ] )
+        .and_then( move | ( reader, first_byte ) | {
+            if first_byte[ 0 ] == b'/' {
+                Either::A( serve_legacy( reader, writer, server, peer ) )
+            } else {
+                Either::B( serve_framed( reader, writer, first_byte[ 0 ], server, peer ) )
+            }
+        } )
+}
+
+// complete the TLS handshake on a new connection, giving up if the client
+// stalls partway through
+fn accept_tls( acceptor: &SslAcceptor, socket: TcpStream, handshake_timeout: Duration )
+               -> impl Future< Item = tokio_openssl::SslStream< TcpStream >, Error = io::Error > {
+    acceptor.accept_async( socket )
+        .map_err( | why | io::Error::other( format!( "TLS handshake failed: {}", why ) ) )
+        .timeout( handshake_timeout )
+        .map_err( | e | e.into_inner().unwrap_or_else( || io::Error::new(
+            io::ErrorKind::TimedOut, "TLS handshake took too long" ) ) )
+}
+
+// wait for the first byte of a connection without consuming it, or None if
+// the client hung up without sending anything
+fn peek_first_byte( socket: TcpStream )
+                    -> impl Future< Item = ( TcpStream, Option< u8 > ), Error = io::Error > {
+    let mut socket = Some( socket );
+    future::poll_fn( move || {
+        let mut first_byte = [ 0u8; 1 ];
+        let bytes_peeked = match socket.as_mut().unwrap().poll_peek( &mut first_byte )? {
+            Async::Ready( bytes_peeked ) => bytes_peeked,
+            Async::NotReady => return Ok( Async::NotReady ),
+        };
+        let first_byte = if bytes_peeked == 0 { None } else { Some( first_byte[ 0 ] ) };
+        Ok( Async::Ready( ( socket.take().unwrap(), first_byte ) ) )
+    } )
+}
+
+// counts a connection against max_connections until it is dropped
+struct ConnectionSlot( Arc< AtomicUsize > );
+
+impl ConnectionSlot {
+    // take a slot, unless max_connections are already open
+    fn take( open_connections: &Arc< AtomicUsize >, max_connections: usize )
+             -> Option< ConnectionSlot > {
+        let open = open_connections.fetch_add( 1, Ordering::SeqCst );
+        let slot = ConnectionSlot( open_connections.clone() );
+        if open < max_connections { Some( slot ) } else { None }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop( &mut self ) {
+        self.0.fetch_sub( 1, Ordering::SeqCst );
+    }
+}
+
+// serve the connections accepted by a listener, each on its own task
+fn serve_listener( listener: TcpListener, server: Arc< ServerState >,
+                   acceptor: Option< Arc< SslAcceptor > >,
+                   open_connections: Arc< AtomicUsize >, max_connections: usize )
+                   -> impl Future< Item = (), Error = () > {
+    listener.incoming()
+        .map_err( | e | log_error!( "accept failed = {:?}", e ) )
+        .for_each( move | socket | {
+            let peer_addr = socket.peer_addr().unwrap();
+            let server = server.clone();
+            let acceptor = acceptor.clone();
+
+            // connections past the limit are closed without a reply
+            let slot = match ConnectionSlot::take( &open_connections, max_connections ) {
+                Some( slot ) => slot,
+                None => {
+                    log_warn!( "{}: refused connection, max_connections ({}) are open",
+                               peer_addr, max_connections );
+                    return Ok( () );
+                },
+            };
+
+            // TLS connections start with a handshake, which is only
+            // answered if TLS is set up
+            let idle_timeout = server.idle_timeout;
+            let handshake_timeout = server.handshake_timeout;
+            let handle_conn = peek_first_byte( socket )
+                .timeout( idle_timeout )
+                .map_err( | e | e.into_inner().unwrap_or_else( || io::Error::new(
+                    io::ErrorKind::TimedOut, "connection was idle for too long" ) ) )
+                .and_then( move | ( socket, first_byte ) | match ( first_byte, acceptor ) {
+                    ( None, _ ) => Either::A( Either::A( future::ok( () ) ) ),
+                    ( Some( TLS_HANDSHAKE_BYTE ), Some( acceptor ) ) => Either::B(
+                        accept_tls( &acceptor, socket, handshake_timeout )
+                            .and_then( move | stream | serve_connection(
+                                stream, server, Peer { addr: peer_addr, tls: true } ) ) ),
+                    _ => Either::A( Either::B( serve_connection(
+                        socket, server, Peer { addr: peer_addr, tls: false } ) ) ),
                 } )
-                .then( | _ | {
+                .then( move | result | {
+                    drop( slot );
+                    // a client hanging up or going idle between requests
+                    // is expected
+                    if let Err( e ) = result {
+                        if e.kind() != std::io::ErrorKind::UnexpectedEof &&
+                            e.kind() != std::io::ErrorKind::TimedOut {
+                            log_error!( "{}: connection failed = {:?}", peer_addr, e );
+                        }
+                    }
                     Ok( () )
                 } );
 
@@ -266,8 +1006,112 @@ fn main() {
             tokio::spawn( handle_conn );
 
             Ok( () )
-        } );
+        } )
+}
+
+// report settings which can't be used, along with how to run the server
+fn invalid_config( why: &str ) -> ! {
+    eprintln!( "server: {}\n{}", why, config::USAGE );
+    process::exit( 2 );
+}
+
+fn main() {
+    let args: Vec< String > = env::args().skip( 1 ).collect();
+    let config = ServerConfig::from_args( &args )
+        .unwrap_or_else( | why | invalid_config( &why ) );
+    logging::set_level( config.log_level );
+    log_debug!( "starting with {:#?}", config );
+    let turtlefs_root = config.turtlefs_root.clone();
+
+    // TLS is offered alongside plaintext if there are TLS settings
+    // (turtlefs-root/tls.yaml unless configured), and used for requests to
+    // the other nodes
+    let tls_settings = TlsSettings::load_file( &turtlefs_root, &config.tls_config )
+        .unwrap_or_else( | why | panic!( "cannot set up TLS: {}", why ) );
+    if config.require_tls && tls_settings.is_none() {
+        invalid_config( &format!( "require_tls needs TLS settings in {}",
+                                  config.tls_config.display() ) );
+    }
+    let acceptor = tls_settings.as_ref().map( | tls_settings | Arc::new(
+        tls_settings.acceptor().unwrap_or_else( | why | panic!( "cannot set up TLS: {}", why ) ) ) );
+    let connector = tls_settings.as_ref().map( | tls_settings | {
+        tls_settings.connector().unwrap_or_else( | why | panic!( "cannot set up TLS: {}", why ) )
+    } );
+    let require_tls = config.require_tls || tls_settings.as_ref()
+        .is_some_and( | tls_settings | tls_settings.require_tls );
+
+    // requests are checked against the keys (turtlefs-root/keys.yaml unless
+    // configured) if there are any, and requests to the other nodes are
+    // signed with their signing_key
+    let mut key_ring = KeyRing::load_file( &config.keys_config )
+        .unwrap_or_else( | why | panic!( "cannot load keys: {}", why ) );
+    if config.require_signatures {
+        match key_ring {
+            Some( ref mut key_ring ) => key_ring.require_signatures = true,
+            None => invalid_config( &format!( "require_signatures needs keys in {}",
+                                              config.keys_config.display() ) ),
+        }
+    }
+    let signing_key = key_ring.as_ref().and_then( | key_ring | key_ring.signing_key.clone() );
+    let new_pool = || {
+        let mut pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE );
+        if let Some( ref connector ) = connector {
+            pool = pool.with_tls( connector.clone() );
+        }
+        if let Some( ref signing_key ) = signing_key {
+            pool = pool.with_signing_key( signing_key.clone() );
+        }
+        pool
+    };
+
+    let listeners: Vec< TcpListener > = config.bind.iter()
+        .map( | address | {
+            let listener = TcpListener::bind( address ).unwrap_or_else( | why | panic!(
+                "cannot bind TCP listener on {}: {}", address, why ) );
+            match tls_settings {
+                _ if require_tls =>
+                    log_info!( "Running file server on {} (TLS only)", address ),
+                Some( _ ) => log_info!( "Running file server on {} (TLS and plaintext)", address ),
+                None => log_info!( "Running file server on {}", address ),
+            }
+            listener
+        } )
+        .collect();
+
+    // root nodes replicate file_store.yaml among themselves
+    let local_node = config.node.clone();
+    let raft = match node::retrieve_root_nodes( &turtlefs_root ) {
+        Ok( ref root_nodes ) if root_nodes.contains( &local_node ) => Some(
+            Raft::start( &turtlefs_root, &config.store, local_node.clone(), root_nodes.clone(),
+                         new_pool() )
+                .unwrap_or_else( | why | panic!( "cannot start consensus: {}", why ) ) ),
+        _ => None,
+    };
+    let checksum_cache = Arc::new( ChecksumCache::new() );
+    let server = Arc::new( ServerState { store_root: config.store.clone(), raft,
+                                         checksum_cache: checksum_cache.clone(),
+                                         require_tls, key_ring,
+                                         nonce_cache: NonceCache::new(),
+                                         acl_cache: AclCache::new( config.store.clone() ),
+                                         max_payload_size: config.max_payload_size,
+                                         max_args_size: config.max_args_size,
+                                         idle_timeout: config.idle_timeout,
+                                         handshake_timeout: config.handshake_timeout } );
+
+    // keep this node's copies of its files in line with file_store.yaml
+    let replication_pool = new_pool();
+    replication::spawn( turtlefs_root, config.store.clone(), local_node, checksum_cache,
+                        replication_pool );
 
-    // start tokio runtime
-    tokio::run( server );
+    // handle incoming connections on every bind address, counting them
+    // all against max_connections
+    let open_connections = Arc::new( AtomicUsize::new( 0 ) );
+    let max_connections = config.max_connections;
+    tokio::run( future::lazy( move || {
+        for listener in listeners {
+            tokio::spawn( serve_listener( listener, server.clone(), acceptor.clone(),
+                                          open_connections.clone(), max_connections ) );
+        }
+        Ok( () )
+    } ) );
 }
@@ -0,0 +1,62 @@
+// Helpers for EDIT requests and for this node's copy of file_store.yaml.
+//
+// Every edit names the manifest version it was made against, and is
+// rejected with Status::VersionConflict if the manifest has changed since,
+// so concurrent edits are never silently overwritten.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use turtlefs::manifest::{Manifest, ManifestEdit};
+use turtlefs::protocol::Status;
+use store_path;
+
+// parse the EDIT arguments: expected version, operation, operation arguments
+pub fn parse_args( action_args: &[&str] ) -> Result< ( u64, ManifestEdit ), ( Status, String ) > {
+    let expected_version_str = match action_args.first() {
+        Some( version ) => version,
+        None => return Err( ( Status::Error, String::from(
+            "EDIT requires the expected file_store.yaml version" ) ) ),
+    };
+    let expected_version = expected_version_str.parse::< u64 >()
+        .map_err( | why | ( Status::Error, format!(
+            "could not convert \"{}\" to unsigned integer: {}",
+            expected_version_str, why ) ) )?;
+    let edit = ManifestEdit::from_args( &action_args[ 1 .. ] )
+        .map_err( | why | ( Status::Error, why ) )?;
+    Ok( ( expected_version, edit ) )
+}
+
+// apply an edit to the entry for file_name if the manifest is still at the
+// expected version, returning the new manifest version
+pub fn apply( manifest: &mut Manifest, file_name: &str, expected_version: u64,
+              edit: &ManifestEdit ) -> Result< u64, ( Status, String ) > {
+    if manifest.version != expected_version {
+        return Err( ( Status::VersionConflict, format!(
+            "file_store.yaml is at version {}, edit expected version {}",
+            manifest.version, expected_version ) ) );
+    }
+    manifest.apply_edit( file_name, edit )
+        .map_err( | why | ( Status::Error, why ) )?;
+    Ok( manifest.version )
+}
+
+// a missing manifest is treated as an empty one
+pub fn read_manifest( manifest_path: &Path ) -> Result< Manifest, String > {
+    let mut contents = String::new();
+    match File::open( manifest_path ) {
+        Ok( mut file ) => file.read_to_string( &mut contents ).map_err( | why | format!(
+            "could not read {}: {}", manifest_path.display(), why ) )?,
+        Err( ref why ) if why.kind() == io::ErrorKind::NotFound => 0,
+        Err( why ) => return Err( format!(
+            "could not read {}: {}", manifest_path.display(), why ) ),
+    };
+    Manifest::parse( &contents )
+}
+
+// write to a temporary file and rename it into place so readers never see
+// a partial manifest
+pub fn write_manifest( manifest_path: &Path, manifest: &Manifest ) -> io::Result< () > {
+    store_path::replace_file( manifest_path,
+                              | file | file.write_all( manifest.to_yaml().as_bytes() ) )
+}
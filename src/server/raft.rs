@@ -0,0 +1,968 @@
+// Raft consensus among the root nodes, which replicates file_store.yaml.
+//
+// Every root node listed in nodes.yaml runs a member of the cluster. Manifest
+// edits are appended to a replicated log by the elected leader and applied,
+// in log order, to each member's in-memory manifest once a majority of the
+// members hold them. Reads of file_store.yaml are answered by the leader
+// only, after it has confirmed with a majority that it is still the leader,
+// so every read sees every edit which completed before it (linearizable).
+//
+// Members exchange three requests over the framed protocol:
+//   VOTE   args: term, candidate, last log index, last log term
+//          reply: term,granted (0 or 1)
+//   APPEND args: term, leader, previous log index, previous log term,
+//                leader commit index, entry count
+//          payload: entries, each as: term (8) | arg count (2) | args
+//          reply: term,success (0 or 1),match index or next index to try
+//   SNAPSHOT args: term, leader, last included index, last included term
+//            payload: the manifest with every entry up to that index applied
+//            reply: term,success (0 or 1),match index
+//
+// The current term, vote and log are kept in turtlefs-root/raft/<node>/ so a
+// restarted member rejoins without forgetting them. The manifest each member
+// starts from is saved there as a snapshot the first time it runs, and the
+// log is replayed on top of it, so every root node must start from the same
+// file_store.yaml. Once enough entries have been applied, the snapshot is
+// replaced by the applied manifest and the log is truncated up to it. The
+// leader sends its manifest with SNAPSHOT to members which are missing
+// entries that are no longer in its log.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use rand::{self, Rng};
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+use yaml_rust::yaml;
+use turtlefs::manifest::{MANIFEST_PATH, Manifest};
+use turtlefs::node::Node;
+use turtlefs::pool::ConnectionPool;
+use turtlefs::protocol::{self, Opcode, Status};
+use turtlefs::request::{self, Request};
+use manifest_edit;
+use store_path;
+
+// followers start an election after hearing nothing from a leader for a
+// random time in this range (milliseconds)
+const ELECTION_TIMEOUT_MIN: u64 = 300;
+const ELECTION_TIMEOUT_MAX: u64 = 600;
+
+// time between heartbeats sent by the leader
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis( 100 );
+
+// how often the election timer is checked
+const TICK_INTERVAL: Duration = Duration::from_millis( 20 );
+
+// longest a client request waits for an edit to commit or a read to be
+// confirmed before giving up
+const REQUEST_TIMEOUT: Duration = Duration::from_secs( 5 );
+
+// most log entries sent in a single APPEND
+const MAX_APPEND_ENTRIES: usize = 64;
+
+// applied entries kept in the log before it is compacted into a snapshot
+const SNAPSHOT_INTERVAL: u64 = 256;
+
+// new manifest version, or the status and reason the edit was rejected
+type EditResult = Result< u64, ( Status, String ) >;
+
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[ derive( Clone, Debug, PartialEq ) ]
+struct LogEntry {
+    term: u64,
+    // file name, expected version and edit arguments of a manifest edit,
+    // or empty for the no-op entry each leader starts its term with
+    command: Vec< String >,
+}
+
+struct RaftState {
+    // persistent state
+    current_term: u64,
+    voted_for: Option< Node >,
+    // index and term of the last entry in the snapshot
+    snapshot_index: u64,
+    snapshot_term: u64,
+    // log[ 0 ] is the entry at snapshot_index + 1
+    log: Vec< LogEntry >,
+
+    // volatile state
+    role: Role,
+    leader: Option< Node >,
+    commit_index: u64,
+    last_applied: u64,
+    election_deadline: Instant,
+    votes: HashSet< Node >,
+    manifest: Manifest,
+
+    // leader state
+    next_index: HashMap< Node, u64 >,
+    match_index: HashMap< Node, u64 >,
+    // when the latest APPEND which a peer accepted in this term was sent
+    acked_at: HashMap< Node, Instant >,
+    // when a read last asked for leadership to be confirmed
+    read_requested_at: Option< Instant >,
+    // term and result of the entries applied for edits proposed through
+    // this node, keyed by log index
+    pending: HashMap< u64, Option< ( u64, EditResult ) > >,
+}
+
+impl RaftState {
+    fn last_log_index( &self ) -> u64 {
+        self.snapshot_index + self.log.len() as u64
+    }
+
+    // the term of an entry, which is only known for the last entry in the
+    // snapshot and those after it
+    fn log_term( &self, index: u64 ) -> u64 {
+        if index == self.snapshot_index {
+            self.snapshot_term
+        } else if index < self.snapshot_index || index > self.last_log_index() {
+            0
+        } else {
+            self.entry( index ).term
+        }
+    }
+
+    fn entry( &self, index: u64 ) -> &LogEntry {
+        &self.log[ ( index - self.snapshot_index - 1 ) as usize ]
+    }
+}
+
+pub struct Raft {
+    local_node: Node,
+    peers: Vec< Node >,
+    state_path: PathBuf,
+    snapshot_path: PathBuf,
+    manifest_path: PathBuf,
+    pool: ConnectionPool,
+    state: Mutex< RaftState >,
+    changed: Condvar,
+}
+
+fn random_election_deadline() -> Instant {
+    let timeout = rand::thread_rng().gen_range( ELECTION_TIMEOUT_MIN, ELECTION_TIMEOUT_MAX );
+    Instant::now() + Duration::from_millis( timeout )
+}
+
+fn parse_u64( arg: &str ) -> Result< u64, ( Status, String ) > {
+    arg.parse::< u64 >().map_err( | why | ( Status::Error, format!(
+        "could not convert \"{}\" to unsigned integer: {}", arg, why ) ) )
+}
+
+fn parse_node( arg: &str ) -> Result< Node, ( Status, String ) > {
+    arg.parse().map_err( | why | ( Status::Error, why ) )
+}
+
+impl Raft {
+    // start the consensus member for local_node, which must be one of
+    // root_nodes and keeps its files in store_root
+    pub fn start( turtlefs_root: &str, store_root: &Path, local_node: Node,
+                  root_nodes: Vec< Node >, pool: ConnectionPool )
+                  -> Result< Arc< Raft >, String > {
+        let state_dir: PathBuf = [ turtlefs_root, "raft",
+                                   &local_node.to_string().replace( ':', "_" ) ]
+            .iter().collect();
+        fs::create_dir_all( &state_dir ).map_err( | why | format!(
+            "could not create {}: {}", state_dir.display(), why ) )?;
+        let manifest_path = store_root.join( &MANIFEST_PATH[ 1 .. ] );
+
+        // the manifest the log is replayed on top of, which members that
+        // ran before snapshots were taken kept in base_file_store.yaml
+        let snapshot_path = state_dir.join( "snapshot.yaml" );
+        let ( snapshot_index, snapshot_term, manifest ) = match load_snapshot( &snapshot_path )? {
+            Some( snapshot ) => snapshot,
+            None => {
+                let base_path = state_dir.join( "base_file_store.yaml" );
+                let manifest = if base_path.exists() {
+                    manifest_edit::read_manifest( &base_path )?
+                } else {
+                    manifest_edit::read_manifest( &manifest_path )?
+                };
+                save_snapshot( &snapshot_path, 0, 0, &manifest )?;
+                ( 0, 0, manifest )
+            },
+        };
+
+        // the snapshot is saved before the log is truncated, so the log may
+        // still start with entries which are already in it
+        let state_path = state_dir.join( "state.yaml" );
+        let ( current_term, voted_for, log_start, mut log ) = load_state( &state_path )?;
+        if log_start > snapshot_index {
+            return Err( format!( "invalid {}: the log starts after the snapshot in {}",
+                                 state_path.display(), snapshot_path.display() ) );
+        }
+        let compacted = cmp::min( snapshot_index - log_start, log.len() as u64 );
+        log.drain( .. compacted as usize );
+
+        let peers: Vec< Node > = root_nodes.into_iter()
+            .filter( | node | *node != local_node )
+            .collect();
+        let raft = Arc::new( Raft {
+            local_node,
+            peers,
+            state_path,
+            snapshot_path,
+            manifest_path,
+            pool,
+            state: Mutex::new( RaftState {
+                current_term,
+                voted_for,
+                snapshot_index,
+                snapshot_term,
+                log,
+                role: Role::Follower,
+                leader: None,
+                commit_index: snapshot_index,
+                last_applied: snapshot_index,
+                election_deadline: random_election_deadline(),
+                votes: HashSet::new(),
+                manifest,
+                next_index: HashMap::new(),
+                match_index: HashMap::new(),
+                acked_at: HashMap::new(),
+                read_requested_at: None,
+                pending: HashMap::new(),
+            } ),
+            changed: Condvar::new(),
+        } );
+
+        let ticker = raft.clone();
+        thread::spawn( move || ticker.run_election_timer() );
+        for peer in raft.peers.iter() {
+            let replicator = raft.clone();
+            let peer = peer.clone();
+            thread::spawn( move || replicator.run_replicator( peer ) );
+        }
+        Ok( raft )
+    }
+
+    fn lock( &self ) -> MutexGuard< '_, RaftState > {
+        self.state.lock().unwrap_or_else( | poisoned | poisoned.into_inner() )
+    }
+
+    fn majority( &self ) -> usize {
+        let members = self.peers.len() + 1;
+        members / 2 + 1
+    }
+
+    fn not_leader( &self, state: &RaftState ) -> ( Status, String ) {
+        ( Status::NotLeader, state.leader.as_ref()
+          .map( | leader | leader.to_string() ).unwrap_or_default() )
+    }
+
+    // save the term, vote and log, which must happen before replying to
+    // any request that depends on them
+    fn persist( &self, state: &RaftState ) {
+        if let Err( why ) = save_state( &self.state_path, state ) {
+            // carrying on could break the guarantees other members rely on
+            panic!( "raft: could not save {}: {}", self.state_path.display(), why );
+        }
+    }
+
+    fn become_follower( &self, state: &mut RaftState, term: u64 ) {
+        if term > state.current_term {
+            state.current_term = term;
+            state.voted_for = None;
+            state.leader = None;
+            self.persist( state );
+        }
+        if state.role != Role::Follower {
+            log_info!( "raft: following in term {}", state.current_term );
+        }
+        state.role = Role::Follower;
+        state.read_requested_at = None;
+        self.changed.notify_all();
+    }
+
+    fn become_leader( &self, state: &mut RaftState ) {
+        log_info!( "raft: elected leader in term {}", state.current_term );
+        state.role = Role::Leader;
+        state.leader = Some( self.local_node.clone() );
+        let next_index = state.last_log_index() + 1;
+        for peer in self.peers.iter() {
+            state.next_index.insert( peer.clone(), next_index );
+            state.match_index.insert( peer.clone(), 0 );
+        }
+        state.acked_at.clear();
+
+        // committing an entry from the new term also commits everything
+        // before it
+        let term = state.current_term;
+        state.log.push( LogEntry { term, command: Vec::new() } );
+        self.persist( state );
+        self.advance_commit_index( state );
+        self.changed.notify_all();
+    }
+
+    /* ELECTIONS */
+
+    fn run_election_timer( self: Arc< Self > ) {
+        loop {
+            thread::sleep( TICK_INTERVAL );
+            let mut state = self.lock();
+            if state.role != Role::Leader && Instant::now() >= state.election_deadline {
+                self.start_election( &mut state );
+            }
+        }
+    }
+
+    fn start_election( self: &Arc< Self >, state: &mut RaftState ) {
+        state.current_term += 1;
+        state.role = Role::Candidate;
+        state.leader = None;
+        state.voted_for = Some( self.local_node.clone() );
+        state.votes.clear();
+        state.votes.insert( self.local_node.clone() );
+        state.election_deadline = random_election_deadline();
+        self.persist( state );
+        log_info!( "raft: starting election for term {}", state.current_term );
+
+        if state.votes.len() >= self.majority() {
+            self.become_leader( state );
+            return;
+        }
+
+        let args = vec![ state.current_term.to_string(), self.local_node.to_string(),
+                         state.last_log_index().to_string(),
+                         state.log_term( state.last_log_index() ).to_string() ];
+        for peer in self.peers.iter() {
+            let raft = self.clone();
+            let peer = peer.clone();
+            let args = args.clone();
+            let term = state.current_term;
+            thread::spawn( move || {
+                let reply = match raft.send_rpc( &peer, Opcode::Vote, args, &[] ) {
+                    Some( reply ) => reply,
+                    None => return,
+                };
+                let ( reply_term, granted ) = match ( reply.first(), reply.get( 1 ) ) {
+                    ( Some( &reply_term ), Some( &granted ) ) => ( reply_term, granted ),
+                    _ => return,
+                };
+
+                let mut state = raft.lock();
+                if reply_term > state.current_term {
+                    raft.become_follower( &mut state, reply_term );
+                } else if state.role == Role::Candidate && state.current_term == term &&
+                    granted == 1 {
+                    state.votes.insert( peer );
+                    if state.votes.len() >= raft.majority() {
+                        raft.become_leader( &mut state );
+                    }
+                }
+            } );
+        }
+    }
+
+    // handle a VOTE request from a candidate
+    pub fn handle_vote( &self, args: &[&str] ) -> Result< Vec< u8 >, ( Status, String ) > {
+        if args.len() != 4 {
+            return Err( ( Status::Error, format!(
+                "VOTE requires 4 arguments, got {}", args.len() ) ) );
+        }
+        let term = parse_u64( args[ 0 ] )?;
+        let candidate = parse_node( args[ 1 ] )?;
+        let last_log_index = parse_u64( args[ 2 ] )?;
+        let last_log_term = parse_u64( args[ 3 ] )?;
+
+        let mut state = self.lock();
+        if term > state.current_term {
+            self.become_follower( &mut state, term );
+        }
+
+        // only vote for candidates whose log is at least as up to date as ours
+        let our_last_term = state.log_term( state.last_log_index() );
+        let log_ok = last_log_term > our_last_term ||
+            ( last_log_term == our_last_term && last_log_index >= state.last_log_index() );
+        let can_vote = state.voted_for.is_none() || state.voted_for == Some( candidate.clone() );
+        let granted = term == state.current_term && can_vote && log_ok;
+        if granted {
+            state.voted_for = Some( candidate );
+            state.election_deadline = random_election_deadline();
+            self.persist( &state );
+        }
+        Ok( format!( "{},{}", state.current_term, granted as u8 ).into_bytes() )
+    }
+
+    /* LOG REPLICATION */
+
+    // send APPEND requests to peer whenever this node is the leader
+    fn run_replicator( self: Arc< Self >, peer: Node ) {
+        let mut last_sent: Option< ( u64, Instant ) > = None;
+        let mut caught_up = true;
+        loop {
+            let mut state = self.lock();
+            loop {
+                if state.role == Role::Leader {
+                    let term = state.current_term;
+                    let due = match last_sent {
+                        Some( ( sent_term, sent_at ) ) => sent_term != term ||
+                            sent_at.elapsed() >= HEARTBEAT_INTERVAL ||
+                            ( caught_up && state.next_index[ &peer ] <= state.last_log_index() ) ||
+                            state.read_requested_at.is_some_and( | at | at > sent_at ),
+                        None => true,
+                    };
+                    if due {
+                        break;
+                    }
+                }
+                state = self.changed.wait_timeout( state, HEARTBEAT_INTERVAL / 2 )
+                    .unwrap_or_else( | poisoned | poisoned.into_inner() ).0;
+            }
+
+            let term = state.current_term;
+            let next_index = state.next_index[ &peer ];
+            let ( opcode, args, payload ) = if next_index <= state.snapshot_index {
+                // the entries the peer is missing have been compacted, so it
+                // is sent everything that has been applied instead
+                let args = vec![ term.to_string(), self.local_node.to_string(),
+                                 state.last_applied.to_string(),
+                                 state.log_term( state.last_applied ).to_string() ];
+                ( Opcode::Snapshot, args, state.manifest.to_yaml().into_bytes() )
+            } else {
+                let prev_log_index = next_index - 1;
+                let entries: Vec< LogEntry > = state.log.iter()
+                    .skip( ( prev_log_index - state.snapshot_index ) as usize )
+                    .take( MAX_APPEND_ENTRIES )
+                    .cloned()
+                    .collect();
+                let args = vec![ term.to_string(), self.local_node.to_string(),
+                                 prev_log_index.to_string(),
+                                 state.log_term( prev_log_index ).to_string(),
+                                 state.commit_index.to_string(), entries.len().to_string() ];
+                match encode_entries( &entries ) {
+                    Ok( payload ) => ( Opcode::Append, args, payload ),
+                    // every entry came in as a frame, so it always fits
+                    // in one, but if not treat it like an unanswered RPC
+                    Err( _ ) => {
+                        last_sent = Some( ( term, Instant::now() ) );
+                        caught_up = false;
+                        continue;
+                    },
+                }
+            };
+            let sent_at = Instant::now();
+            last_sent = Some( ( term, sent_at ) );
+            drop( state );
+
+            let reply = self.send_rpc( &peer, opcode, args, &payload );
+            let ( reply_term, success, index ) = match reply {
+                Some( ref reply ) if reply.len() == 3 => ( reply[ 0 ], reply[ 1 ], reply[ 2 ] ),
+                _ => {
+                    // wait for the next heartbeat before trying again
+                    caught_up = false;
+                    continue;
+                },
+            };
+
+            let mut state = self.lock();
+            if reply_term > state.current_term {
+                self.become_follower( &mut state, reply_term );
+                continue;
+            }
+            if state.role != Role::Leader || state.current_term != term {
+                continue;
+            }
+            if success == 1 {
+                state.match_index.insert( peer.clone(), index );
+                state.next_index.insert( peer.clone(), index + 1 );
+                let acked_at = state.acked_at.entry( peer.clone() ).or_insert( sent_at );
+                *acked_at = cmp::max( *acked_at, sent_at );
+                caught_up = true;
+                self.advance_commit_index( &mut state );
+                self.changed.notify_all();
+            } else {
+                // back up to where the peer's log may match ours
+                let next_index = cmp::max( 1, cmp::min( next_index - 1, index ) );
+                state.next_index.insert( peer.clone(), next_index );
+                caught_up = true;
+            }
+        }
+    }
+
+    // handle an APPEND request from the leader
+    pub fn handle_append( &self, args: &[&str], payload: &[u8] )
+                          -> Result< Vec< u8 >, ( Status, String ) > {
+        if args.len() != 6 {
+            return Err( ( Status::Error, format!(
+                "APPEND requires 6 arguments, got {}", args.len() ) ) );
+        }
+        let term = parse_u64( args[ 0 ] )?;
+        let leader = parse_node( args[ 1 ] )?;
+        let prev_log_index = parse_u64( args[ 2 ] )?;
+        let prev_log_term = parse_u64( args[ 3 ] )?;
+        let leader_commit = parse_u64( args[ 4 ] )?;
+        let entry_count = parse_u64( args[ 5 ] )? as usize;
+        let entries = decode_entries( payload, entry_count )
+            .map_err( | why | ( Status::Error, why ) )?;
+
+        let mut state = self.lock();
+        if term < state.current_term {
+            return Ok( format!( "{},0,0", state.current_term ).into_bytes() );
+        }
+        self.follow_leader( &mut state, term, leader );
+
+        // the entries must follow on from a matching entry in our log, and
+        // every entry in the snapshot has been committed so matches
+        if prev_log_index > state.last_log_index() {
+            return Ok( format!( "{},0,{}", state.current_term,
+                                state.last_log_index() + 1 ).into_bytes() );
+        }
+        if prev_log_index > state.snapshot_index &&
+            state.log_term( prev_log_index ) != prev_log_term {
+            // skip back over the whole conflicting term at once
+            let conflict_term = state.log_term( prev_log_index );
+            let mut first_index = prev_log_index;
+            while first_index > state.snapshot_index + 1 &&
+                state.log_term( first_index - 1 ) == conflict_term {
+                first_index -= 1;
+            }
+            return Ok( format!( "{},0,{}", state.current_term, first_index ).into_bytes() );
+        }
+
+        let mut log_changed = false;
+        for ( offset, entry ) in entries.into_iter().enumerate() {
+            let index = prev_log_index + 1 + offset as u64;
+            if index <= state.snapshot_index {
+                continue;
+            }
+            if index <= state.last_log_index() {
+                if state.log_term( index ) == entry.term {
+                    continue;
+                }
+                // an entry which was never committed, replaced by the leader's
+                let kept = index - state.snapshot_index - 1;
+                state.log.truncate( kept as usize );
+            }
+            state.log.push( entry );
+            log_changed = true;
+        }
+        if log_changed {
+            self.persist( &state );
+        }
+
+        let match_index = prev_log_index + entry_count as u64;
+        let commit_index = cmp::min( leader_commit, match_index );
+        if commit_index > state.commit_index {
+            state.commit_index = commit_index;
+            self.apply_committed( &mut state );
+        }
+        Ok( format!( "{},1,{}", state.current_term, match_index ).into_bytes() )
+    }
+
+    // handle a SNAPSHOT request from the leader, replacing the log up to
+    // the snapshot with the manifest it carries
+    pub fn handle_snapshot( &self, args: &[&str], payload: &[u8] )
+                            -> Result< Vec< u8 >, ( Status, String ) > {
+        if args.len() != 4 {
+            return Err( ( Status::Error, format!(
+                "SNAPSHOT requires 4 arguments, got {}", args.len() ) ) );
+        }
+        let term = parse_u64( args[ 0 ] )?;
+        let leader = parse_node( args[ 1 ] )?;
+        let snapshot_index = parse_u64( args[ 2 ] )?;
+        let snapshot_term = parse_u64( args[ 3 ] )?;
+        let manifest = Manifest::parse( &String::from_utf8_lossy( payload ) )
+            .map_err( | why | ( Status::Error, format!( "invalid SNAPSHOT payload: {}", why ) ) )?;
+
+        let mut state = self.lock();
+        if term < state.current_term {
+            return Ok( format!( "{},0,0", state.current_term ).into_bytes() );
+        }
+        self.follow_leader( &mut state, term, leader );
+
+        // everything in it has already been committed here
+        if snapshot_index <= state.commit_index {
+            return Ok( format!( "{},1,{}", state.current_term, snapshot_index ).into_bytes() );
+        }
+
+        save_snapshot( &self.snapshot_path, snapshot_index, snapshot_term, &manifest )
+            .map_err( | why | ( Status::Error, why ) )?;
+
+        // keep the entries after the snapshot if our log agrees with it up
+        // to there
+        if snapshot_index <= state.last_log_index() &&
+            state.log_term( snapshot_index ) == snapshot_term {
+            let compacted = snapshot_index - state.snapshot_index;
+            state.log.drain( .. compacted as usize );
+        } else {
+            state.log.clear();
+        }
+        state.snapshot_index = snapshot_index;
+        state.snapshot_term = snapshot_term;
+        state.commit_index = snapshot_index;
+        state.last_applied = snapshot_index;
+        state.manifest = manifest;
+        self.persist( &state );
+        self.store_manifest( &state );
+        self.changed.notify_all();
+        Ok( format!( "{},1,{}", state.current_term, snapshot_index ).into_bytes() )
+    }
+
+    // recognize leader as the leader for term, which is at least our own
+    fn follow_leader( &self, state: &mut RaftState, term: u64, leader: Node ) {
+        if term > state.current_term || state.role != Role::Follower {
+            self.become_follower( state, term );
+        }
+        if state.leader.as_ref() != Some( &leader ) {
+            log_info!( "raft: {} is the leader for term {}", leader, term );
+            state.leader = Some( leader );
+        }
+        state.election_deadline = random_election_deadline();
+    }
+
+    // commit entries from the current term which a majority of members hold
+    fn advance_commit_index( &self, state: &mut RaftState ) {
+        let mut match_indexes: Vec< u64 > = self.peers.iter()
+            .map( | peer | state.match_index.get( peer ).cloned().unwrap_or( 0 ) )
+            .collect();
+        match_indexes.push( state.last_log_index() );
+        match_indexes.sort_unstable_by( | a, b | b.cmp( a ) );
+        let majority_index = match_indexes[ self.majority() - 1 ];
+        if majority_index > state.commit_index &&
+            state.log_term( majority_index ) == state.current_term {
+            state.commit_index = majority_index;
+            self.apply_committed( state );
+        }
+    }
+
+    // apply committed entries to the manifest, in log order
+    fn apply_committed( &self, state: &mut RaftState ) {
+        let old_version = state.manifest.version;
+        while state.last_applied < state.commit_index {
+            state.last_applied += 1;
+            let index = state.last_applied;
+            let LogEntry { term, command } = state.entry( index ).clone();
+            let result = apply_command( &mut state.manifest, &command );
+            if let Some( pending ) = state.pending.get_mut( &index ) {
+                *pending = Some( ( term, result ) );
+            }
+        }
+        if state.manifest.version != old_version {
+            self.store_manifest( state );
+        }
+        if state.last_applied - state.snapshot_index >= SNAPSHOT_INTERVAL {
+            self.compact_log( state );
+        }
+        self.changed.notify_all();
+    }
+
+    // keep the copy in the store up to date, without ever going back to an
+    // older version while the log is replayed after a restart (the store
+    // may be shared with other members running on the same host)
+    fn store_manifest( &self, state: &RaftState ) {
+        let stored_version = manifest_edit::read_manifest( &self.manifest_path )
+            .map( | manifest | manifest.version ).unwrap_or( 0 );
+        if state.manifest.version > stored_version {
+            if let Err( why ) = manifest_edit::write_manifest( &self.manifest_path,
+                                                               &state.manifest ) {
+                log_error!( "raft: could not write {}: {}",
+                           self.manifest_path.display(), why );
+            }
+        }
+    }
+
+    // replace the snapshot with the applied manifest and drop the entries
+    // it covers from the log
+    fn compact_log( &self, state: &mut RaftState ) {
+        let snapshot_index = state.last_applied;
+        let snapshot_term = state.log_term( snapshot_index );
+        if let Err( why ) = save_snapshot( &self.snapshot_path, snapshot_index, snapshot_term,
+                                           &state.manifest ) {
+            // the log is kept whole, and compacting is tried again later
+            log_error!( "raft: {}", why );
+            return;
+        }
+        let compacted = snapshot_index - state.snapshot_index;
+        state.log.drain( .. compacted as usize );
+        state.snapshot_index = snapshot_index;
+        state.snapshot_term = snapshot_term;
+        self.persist( state );
+    }
+
+    /* CLIENT REQUESTS */
+
+    // append a manifest edit to the log and wait for it to be applied,
+    // returning the new manifest version
+    pub fn propose_edit( &self, file_name: &str, action_args: &[&str] ) -> EditResult {
+        // reject malformed edits before they reach the log
+        manifest_edit::parse_args( action_args )?;
+
+        let mut state = self.lock();
+        if state.role != Role::Leader {
+            return Err( self.not_leader( &state ) );
+        }
+        let term = state.current_term;
+        let mut command = vec![ file_name.to_string() ];
+        command.extend( action_args.iter().map( | arg | arg.to_string() ) );
+        state.log.push( LogEntry { term, command } );
+        self.persist( &state );
+        let index = state.last_log_index();
+        state.pending.insert( index, None );
+        self.advance_commit_index( &mut state );
+        self.changed.notify_all();
+
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        loop {
+            // another leader may have replaced the entry before it was
+            // committed, in which case the result is for someone else's edit
+            if let Some( &Some( _ ) ) = state.pending.get( &index ) {
+                let ( applied_term, result ) = state.pending.remove( &index ).unwrap().unwrap();
+                if applied_term == term {
+                    return result;
+                }
+            }
+            if state.current_term != term || state.role != Role::Leader {
+                state.pending.remove( &index );
+                return Err( ( Status::Error, String::from(
+                    "leadership changed before the edit was committed, \
+                     it may or may not have been applied" ) ) );
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                state.pending.remove( &index );
+                return Err( ( Status::Error, String::from(
+                    "timed out waiting for a majority of root nodes to commit the edit, \
+                     it may or may not have been applied" ) ) );
+            }
+            state = self.changed.wait_timeout( state, deadline - now )
+                .unwrap_or_else( | poisoned | poisoned.into_inner() ).0;
+        }
+    }
+
+    // serialize the manifest once a majority of members have confirmed that
+    // this node is still the leader
+    pub fn read_manifest( &self ) -> Result< Vec< u8 >, ( Status, String ) > {
+        let mut state = self.lock();
+        if state.role != Role::Leader {
+            return Err( self.not_leader( &state ) );
+        }
+        let term = state.current_term;
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        let mut requested_at = None;
+        loop {
+            if state.current_term != term || state.role != Role::Leader {
+                return Err( self.not_leader( &state ) );
+            }
+
+            // the leader only knows everything that has been committed once
+            // an entry from its own term is committed
+            let committed_in_term = state.log_term( state.commit_index ) == term;
+            if committed_in_term {
+                let requested_at = *requested_at.get_or_insert_with( || {
+                    let now = Instant::now();
+                    state.read_requested_at = Some( now );
+                    self.changed.notify_all();
+                    now
+                } );
+                let confirmations = 1 + self.peers.iter()
+                    .filter( | peer | state.acked_at.get( *peer )
+                             .is_some_and( | at | *at >= requested_at ) )
+                    .count();
+                if confirmations >= self.majority() {
+                    return Ok( state.manifest.to_yaml().into_bytes() );
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err( ( Status::Error, String::from(
+                    "timed out confirming leadership with a majority of root nodes" ) ) );
+            }
+            state = self.changed.wait_timeout( state, deadline - now )
+                .unwrap_or_else( | poisoned | poisoned.into_inner() ).0;
+        }
+    }
+
+    /* NETWORKING */
+
+    // send a consensus request to a peer, returning the numbers in its reply
+    fn send_rpc( &self, peer: &Node, opcode: Opcode, args: Vec< String >, payload: &[u8] )
+                 -> Option< Vec< u64 > > {
+        let request = Request {
+            node: peer,
+            opcode,
+            file_name: "/",
+            args,
+            payload,
+        };
+        let response_buffer = &mut Vec::new();
+        let response = request::perform_request( &self.pool, request, response_buffer );
+        if response.status != Status::Ok as u8 {
+            return None;
+        }
+        String::from_utf8_lossy( response.message ).split( ',' )
+            .map( | number | number.trim().parse::< u64 >().ok() )
+            .collect()
+    }
+}
+
+// apply a log entry's command to the manifest
+fn apply_command( manifest: &mut Manifest, command: &[String] ) -> EditResult {
+    let ( file_name, action_args ) = match command.split_first() {
+        Some( ( file_name, action_args ) ) => ( file_name, action_args ),
+        None => return Ok( manifest.version ),
+    };
+    let action_args: Vec< &str > = action_args.iter().map( | arg | arg.as_str() ).collect();
+    let ( expected_version, edit ) = manifest_edit::parse_args( &action_args )?;
+    manifest_edit::apply( manifest, file_name, expected_version, &edit )
+}
+
+fn encode_entries( entries: &[LogEntry] ) -> io::Result< Vec< u8 > > {
+    let mut payload = Vec::new();
+    for entry in entries {
+        let arg_count = u16::try_from( entry.command.len() ).map_err( | _ | io::Error::new(
+            io::ErrorKind::InvalidInput, "log entry has too many arguments" ) )?;
+        payload.extend_from_slice( &entry.term.to_be_bytes() );
+        payload.extend_from_slice( &arg_count.to_be_bytes() );
+        payload.extend_from_slice( &protocol::encode_args( &entry.command )? );
+    }
+    Ok( payload )
+}
+
+fn decode_entries( mut payload: &[u8], entry_count: usize ) -> Result< Vec< LogEntry >, String > {
+    let mut entries = Vec::new();
+    for _ in 0 .. entry_count {
+        if payload.len() < 10 {
+            return Err( String::from( "invalid APPEND payload: truncated entry" ) );
+        }
+        let mut term = [ 0u8; 8 ];
+        term.copy_from_slice( &payload[ .. 8 ] );
+        let mut arg_count = [ 0u8; 2 ];
+        arg_count.copy_from_slice( &payload[ 8 .. 10 ] );
+        payload = &payload[ 10 .. ];
+        let command = protocol::decode_args( &mut payload,
+                                             u16::from_be_bytes( arg_count ) as usize )?;
+        entries.push( LogEntry { term: u64::from_be_bytes( term ), command } );
+    }
+    if !payload.is_empty() {
+        return Err( String::from( "invalid APPEND payload: trailing bytes" ) );
+    }
+    Ok( entries )
+}
+
+// the term, vote, index of the entry before the log, and log
+type PersistentState = ( u64, Option< Node >, u64, Vec< LogEntry > );
+
+// read the persistent state, which is empty for a new member
+fn load_state( state_path: &Path ) -> Result< PersistentState, String > {
+    let state_err = | why: &str | format!( "invalid {}: {}", state_path.display(), why );
+
+    let mut contents = String::new();
+    match File::open( state_path ) {
+        Ok( mut file ) => file.read_to_string( &mut contents )
+            .map_err( | why | state_err( &why.to_string() ) )?,
+        Err( _ ) => return Ok( ( 0, None, 0, Vec::new() ) ),
+    };
+    let docs = YamlLoader::load_from_str( &contents )
+        .map_err( | why | state_err( &why.to_string() ) )?;
+    let doc = match docs.first() {
+        Some( doc ) => doc,
+        None => return Ok( ( 0, None, 0, Vec::new() ) ),
+    };
+
+    let current_term = doc[ "term" ].as_i64().ok_or_else( || state_err( "missing term" ) )?;
+    let voted_for = match doc[ "voted_for" ].as_str() {
+        Some( node ) => Some( node.parse().map_err( | why: String | state_err( &why ) )? ),
+        None => None,
+    };
+    // missing from members which ran before the log was compacted
+    let log_start = doc[ "log_start" ].as_i64().unwrap_or( 0 );
+    let mut log = Vec::new();
+    for entry in doc[ "log" ].as_vec().ok_or_else( || state_err( "missing log" ) )? {
+        let term = entry[ "term" ].as_i64().ok_or_else( || state_err( "missing entry term" ) )?;
+        let command = entry[ "command" ].as_vec()
+            .ok_or_else( || state_err( "missing entry command" ) )?
+            .iter()
+            .map( | arg | arg.as_str().map( | arg | arg.to_string() )
+                  .ok_or_else( || state_err( "entry command must be strings" ) ) )
+            .collect::< Result< Vec< String >, String > >()?;
+        log.push( LogEntry { term: term as u64, command } );
+    }
+    Ok( ( current_term as u64, voted_for, log_start as u64, log ) )
+}
+
+// write the persistent state, with the log that follows the snapshot
+fn save_state( state_path: &Path, state: &RaftState ) -> Result< (), String > {
+    let mut doc = yaml::Hash::new();
+    doc.insert( Yaml::String( String::from( "term" ) ),
+                Yaml::Integer( state.current_term as i64 ) );
+    doc.insert( Yaml::String( String::from( "voted_for" ) ),
+                match state.voted_for {
+                    Some( ref node ) => Yaml::String( node.to_string() ),
+                    None => Yaml::Null,
+                } );
+    doc.insert( Yaml::String( String::from( "log_start" ) ),
+                Yaml::Integer( state.snapshot_index as i64 ) );
+    let log = state.log.iter().map( | entry | {
+        let mut yaml_entry = yaml::Hash::new();
+        yaml_entry.insert( Yaml::String( String::from( "term" ) ),
+                           Yaml::Integer( entry.term as i64 ) );
+        yaml_entry.insert( Yaml::String( String::from( "command" ) ),
+                           Yaml::Array( entry.command.iter()
+                                        .map( | arg | Yaml::String( arg.clone() ) )
+                                        .collect() ) );
+        Yaml::Hash( yaml_entry )
+    } ).collect();
+    doc.insert( Yaml::String( String::from( "log" ) ), Yaml::Array( log ) );
+
+    write_yaml( state_path, doc )
+}
+
+// read the snapshot: the index and term of the last entry in it, and the
+// manifest with every entry up to there applied
+fn load_snapshot( snapshot_path: &Path ) -> Result< Option< ( u64, u64, Manifest ) >, String > {
+    let snapshot_err = | why: &str | format!( "invalid {}: {}", snapshot_path.display(), why );
+
+    let mut contents = String::new();
+    match File::open( snapshot_path ) {
+        Ok( mut file ) => file.read_to_string( &mut contents )
+            .map_err( | why | snapshot_err( &why.to_string() ) )?,
+        Err( _ ) => return Ok( None ),
+    };
+    let docs = YamlLoader::load_from_str( &contents )
+        .map_err( | why | snapshot_err( &why.to_string() ) )?;
+    let doc = docs.first().ok_or_else( || snapshot_err( "empty file" ) )?;
+
+    let index = doc[ "index" ].as_i64().ok_or_else( || snapshot_err( "missing index" ) )?;
+    let term = doc[ "term" ].as_i64().ok_or_else( || snapshot_err( "missing term" ) )?;
+    let manifest = doc[ "manifest" ].as_str()
+        .ok_or_else( || snapshot_err( "missing manifest" ) )?;
+    let manifest = Manifest::parse( manifest ).map_err( | why | snapshot_err( &why ) )?;
+    Ok( Some( ( index as u64, term as u64, manifest ) ) )
+}
+
+fn save_snapshot( snapshot_path: &Path, index: u64, term: u64, manifest: &Manifest )
+                  -> Result< (), String > {
+    let mut doc = yaml::Hash::new();
+    doc.insert( Yaml::String( String::from( "index" ) ), Yaml::Integer( index as i64 ) );
+    doc.insert( Yaml::String( String::from( "term" ) ), Yaml::Integer( term as i64 ) );
+    doc.insert( Yaml::String( String::from( "manifest" ) ),
+                Yaml::String( manifest.to_yaml() ) );
+    write_yaml( snapshot_path, doc ).map_err( | why | format!(
+        "could not write {}: {}", snapshot_path.display(), why ) )
+}
+
+// write a document to a temporary file, sync it and rename it into place
+fn write_yaml( path: &Path, doc: yaml::Hash ) -> Result< (), String > {
+    let mut contents = String::new();
+    YamlEmitter::new( &mut contents ).dump( &Yaml::Hash( doc ) )
+        .map_err( | why | format!( "{:?}", why ) )?;
+    contents.push( '\n' );
+
+    store_path::replace_file( path, | file | {
+        file.write_all( contents.as_bytes() )?;
+        file.sync_all()
+    } ).map_err( | why | why.to_string() )
+}
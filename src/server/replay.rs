@@ -0,0 +1,59 @@
+// Replay protection for signed requests, which are only accepted once and
+// only while their timestamp is close to the node's clock.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use turtlefs::signing::{Signature, MAX_CLOCK_SKEW};
+
+// key name and nonce of a signed request
+type NonceKey = ( String, String );
+
+// the nonces of recently accepted requests, and when each was accepted,
+// oldest first
+struct Seen {
+    nonces: HashSet< NonceKey >,
+    accepted: VecDeque< ( u64, NonceKey ) >,
+}
+
+pub struct NonceCache {
+    seen: Mutex< Seen >,
+}
+
+impl NonceCache {
+    pub fn new() -> NonceCache {
+        NonceCache { seen: Mutex::new( Seen { nonces: HashSet::new(),
+                                              accepted: VecDeque::new() } ) }
+    }
+
+    // accept a correctly signed request once, if its timestamp is within
+    // MAX_CLOCK_SKEW of now. A request is accepted at most MAX_CLOCK_SKEW
+    // before its timestamp, so its nonce is forgotten twice that long after
+    // it was accepted, when the request is refused for being too old anyway.
+    pub fn check( &self, signature: &Signature ) -> Result< (), String > {
+        let now = SystemTime::now().duration_since( UNIX_EPOCH )
+            .map_or( 0, | now | now.as_secs() );
+        let max_skew = MAX_CLOCK_SKEW.as_secs();
+        if signature.timestamp.abs_diff( now ) > max_skew {
+            return Err( format!(
+                "the request was signed at {}, more than {} seconds from this node's clock ({})",
+                signature.timestamp, max_skew, now ) );
+        }
+
+        let mut seen = self.seen.lock().unwrap_or_else( | poisoned | poisoned.into_inner() );
+        while let Some( &( accepted_at, _ ) ) = seen.accepted.front() {
+            if now.saturating_sub( accepted_at ) <= 2 * max_skew {
+                break;
+            }
+            let ( _, key ) = seen.accepted.pop_front().unwrap();
+            seen.nonces.remove( &key );
+        }
+        let key = ( signature.key_name.clone(), signature.nonce.clone() );
+        if !seen.nonces.insert( key.clone() ) {
+            return Err( format!( "nonce {} was already used with key \"{}\"",
+                                 signature.nonce, signature.key_name ) );
+        }
+        seen.accepted.push_back( ( now, key ) );
+        Ok( () )
+    }
+}
@@ -0,0 +1,110 @@
+// Background replication: periodically reads file_store.yaml from the root
+// nodes and pulls any file this node is supposed to provide, but is missing
+// or has with the wrong checksum, from the other nodes providing it.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use turtlefs::manifest::FileEntry;
+use turtlefs::node::{self, Node};
+use turtlefs::pool::ConnectionPool;
+use turtlefs::request::{self, MAX_PARALLEL_CHUNKS};
+use checksum::ChecksumCache;
+use store_path;
+
+// time between replication passes
+pub const REPLICATION_INTERVAL: Duration = Duration::from_secs( 30 );
+
+// start the replication service for the node at local_node, which keeps
+// its files in store_root
+pub fn spawn( turtlefs_root: String, store_root: PathBuf, local_node: Node,
+              checksum_cache: Arc< ChecksumCache >, pool: ConnectionPool )
+              -> thread::JoinHandle< () > {
+    thread::spawn( move || {
+        let pool = Arc::new( pool );
+        loop {
+            if let Err( why ) = replicate( &turtlefs_root, &store_root, &local_node, &pool,
+                                           &checksum_cache ) {
+                log_error!( "replication: {}", why );
+            }
+            thread::sleep( REPLICATION_INTERVAL );
+        }
+    } )
+}
+
+// run a single replication pass
+fn replicate( turtlefs_root: &str, store_root: &Path, local_node: &Node,
+              pool: &Arc< ConnectionPool >, checksum_cache: &ChecksumCache )
+              -> Result< (), String > {
+    let root_nodes = node::retrieve_root_nodes( turtlefs_root )?;
+    let manifest = request::request_manifest( pool, root_nodes )?;
+
+    for ( file_name, entry ) in manifest.files.iter() {
+        if !entry.nodes.contains( local_node ) {
+            continue;
+        }
+        if let Err( why ) = replicate_file( store_root, local_node, pool,
+                                            checksum_cache, file_name, entry ) {
+            log_error!( "replication: {}", why );
+        }
+    }
+    Ok( () )
+}
+
+// make sure the local copy of file_name matches its manifest entry
+fn replicate_file( store_root: &Path, local_node: &Node, pool: &Arc< ConnectionPool >,
+                   checksum_cache: &ChecksumCache, file_name: &str,
+                   entry: &FileEntry ) -> Result< (), String > {
+    let path = store_path::resolve_file( store_root, file_name )
+        .map_err( | why | format!( "invalid path {:?}: {}", file_name, why ) )?;
+
+    match checksum_cache.checksum( &path, entry.checksum.algorithm() ) {
+        Ok( checksum ) if checksum == entry.checksum => return Ok( () ),
+        Ok( checksum ) => log_info!(
+            "replication: {} has checksum {}, expected {}",
+            file_name, checksum, entry.checksum ),
+        Err( ref why ) if why.kind() == io::ErrorKind::NotFound => log_info!(
+            "replication: {} is missing", file_name ),
+        Err( why ) => return Err( format!( "could not read {:?}: {}", path, why ) ),
+    }
+
+    let peers: Vec< Node > = entry.nodes.iter()
+        .filter( | node | *node != local_node )
+        .cloned()
+        .collect();
+    if peers.is_empty() {
+        return Err( format!( "no other nodes provide {}", file_name ) );
+    }
+
+    let file_length = request::request_length( pool, peers.clone(), file_name )
+        .ok_or_else( || format!( "could not retrieve file length for {}", file_name ) )?;
+
+    // pull into a temporary file and only rename it into place once the
+    // checksum is known to be right
+    if let Some( parent ) = path.parent() {
+        fs::create_dir_all( parent )
+            .map_err( | why | format!( "could not create {:?}: {}", parent, why ) )?;
+    }
+    store_path::replace_file( &path, | temp_file | {
+        let retrieved = request::request_file_distributed(
+            pool.clone(), peers, file_name, file_length, entry,
+            MAX_PARALLEL_CHUNKS, temp_file ).map_err( io::Error::other )?;
+        temp_file.flush()?;
+        for node in retrieved.corrupt_nodes.iter() {
+            log_warn!( "replication: {} sent bad blocks of {}", node, file_name );
+        }
+        if retrieved.checksum != entry.checksum {
+            return Err( io::Error::other( format!(
+                "pulled {} with checksum {}, expected {}",
+                file_name, retrieved.checksum, entry.checksum ) ) );
+        }
+        Ok( () )
+    } ).map_err( | why | format!( "could not pull {}: {}", file_name, why ) )?;
+
+    checksum_cache.forget( &path );
+    log_info!( "replication: pulled {} ({} bytes)", file_name, file_length );
+    Ok( () )
+}
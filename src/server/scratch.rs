@@ -0,0 +1,50 @@
+// Scratch directories for the unit tests, removed once the test is over,
+// whether it passed or not.
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new( 0 );
+
+// an empty directory in the temporary directory, which is removed when
+// dropped
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    // name tells the tests apart, and each one gets a directory of its own
+    // since they run concurrently
+    pub fn new( name: &str ) -> ScratchDir {
+        let mut path = env::temp_dir();
+        path.push( format!( "turtlefs-{}-{}-{}", name, process::id(),
+                            NEXT_DIR.fetch_add( 1, Ordering::SeqCst ) ) );
+        let _ = fs::remove_dir_all( &path );
+        fs::create_dir_all( &path ).unwrap();
+        ScratchDir { path }
+    }
+}
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref( &self ) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef< Path > for ScratchDir {
+    fn as_ref( &self ) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop( &mut self ) {
+        let _ = fs::remove_dir_all( &self.path );
+    }
+}
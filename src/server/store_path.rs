@@ -0,0 +1,324 @@
+// Resolves requested file names to paths inside turtlefs-root/store,
+// refusing anything that would reach outside of it.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use turtlefs::protocol::Status;
+
+// suffix of the temporary files whole files are written to before being
+// renamed into place, which listings leave out
+pub const TEMP_SUFFIX: &str = ".turtlefs-tmp";
+
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new( 0 );
+
+#[ derive( Debug ) ]
+pub enum PathError {
+    // a ".." component
+    ParentTraversal,
+    // a component which is absolute or carries a drive prefix
+    AbsoluteComponent( String ),
+    // the path resolves outside of the store through a symlink
+    SymlinkEscape,
+    // a NUL byte anywhere in the path
+    NulByte,
+    // the path is the store itself or a directory, where a file is needed
+    NotAFile,
+    // the store (or part of the path) could not be inspected
+    Io( io::Error ),
+}
+
+impl PathError {
+    // status code sent back to the client for this error
+    pub fn status( &self ) -> Status {
+        match *self {
+            PathError::ParentTraversal => Status::PathTraversal,
+            PathError::AbsoluteComponent( _ ) => Status::AbsolutePath,
+            PathError::SymlinkEscape => Status::SymlinkEscape,
+            PathError::NulByte => Status::NulByte,
+            PathError::NotAFile | PathError::Io( _ ) => Status::Error,
+        }
+    }
+}
+
+impl fmt::Display for PathError {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            PathError::ParentTraversal =>
+                write!( fmt, "path must not contain \"..\" components" ),
+            PathError::AbsoluteComponent( ref component ) =>
+                write!( fmt, "path must not contain absolute components: {:?}",
+                        component ),
+            PathError::SymlinkEscape =>
+                write!( fmt, "path must not resolve outside of the store" ),
+            PathError::NulByte =>
+                write!( fmt, "path must not contain NUL bytes" ),
+            PathError::NotAFile =>
+                write!( fmt, "path must name a file, not the store or a directory" ),
+            PathError::Io( ref why ) =>
+                write!( fmt, "could not resolve path: {}", why ),
+        }
+    }
+}
+
+// resolve a request path such as /dir/file.txt against the store root,
+// the resolved path does not need to exist yet (e.g. for WRITE)
+pub fn resolve( store_root: &Path, request_file_name: &str ) -> Result< PathBuf, PathError > {
+    if request_file_name.contains( '\0' ) {
+        return Err( PathError::NulByte );
+    }
+
+    let mut file_name = store_root.to_path_buf();
+    for path_piece in request_file_name.split( '/' ) {
+        match path_piece {
+            "" | "." => continue,
+            ".." => return Err( PathError::ParentTraversal ),
+            _ => {},
+        }
+
+        // each piece must be exactly one plain component on this platform,
+        // which rules out things like "C:" or "\dir" on Windows
+        let mut components = Path::new( path_piece ).components();
+        match ( components.next(), components.next() ) {
+            ( Some( Component::Normal( _ ) ), None ) => file_name.push( path_piece ),
+            ( Some( Component::ParentDir ), None ) =>
+                return Err( PathError::ParentTraversal ),
+            _ => return Err( PathError::AbsoluteComponent( path_piece.to_string() ) ),
+        }
+    }
+
+    check_symlinks( store_root, &file_name )?;
+    Ok( file_name )
+}
+
+// resolve a request path which has to name a file (e.g. for READ, WRITE
+// and DELETE), refusing the store itself and existing directories
+pub fn resolve_file( store_root: &Path, request_file_name: &str )
+                     -> Result< PathBuf, PathError > {
+    let file_name = resolve( store_root, request_file_name )?;
+    if file_name == store_root || file_name.is_dir() {
+        return Err( PathError::NotAFile );
+    }
+    Ok( file_name )
+}
+
+// a temporary path in the same directory as file_name, which no other
+// write (in this process or another server sharing the store) will use
+pub fn temp_path( file_name: &Path ) -> PathBuf {
+    let base_name = file_name.file_name()
+        .map( | base_name | base_name.to_string_lossy().into_owned() )
+        .unwrap_or_default();
+    file_name.with_file_name( format!( ".{}.{}-{}{}", base_name, process::id(),
+                                       NEXT_TEMP.fetch_add( 1, Ordering::SeqCst ),
+                                       TEMP_SUFFIX ) )
+}
+
+// replace file_name with whatever write puts in a temporary file, renaming
+// it into place so readers never see a partial file. The temporary file is
+// removed again if writing or renaming it fails.
+pub fn replace_file< F >( file_name: &Path, write: F ) -> io::Result< () >
+    where F: FnOnce( &mut File ) -> io::Result< () >
+{
+    let temp_name = temp_path( file_name );
+    let replaced = File::create( &temp_name )
+        .and_then( | mut file | write( &mut file ) )
+        .and_then( | _ | fs::rename( &temp_name, file_name ) );
+    if replaced.is_err() {
+        let _ = fs::remove_file( &temp_name );
+    }
+    replaced
+}
+
+// make sure the deepest existing ancestor of the path (including the path
+// itself) is still inside the store once symlinks are followed
+fn check_symlinks( store_root: &Path, file_name: &Path ) -> Result< (), PathError > {
+    let canonical_root = store_root.canonicalize().map_err( PathError::Io )?;
+
+    let mut existing = file_name;
+    loop {
+        match existing.canonicalize() {
+            Ok( canonical ) => {
+                if canonical.starts_with( &canonical_root ) {
+                    return Ok( () );
+                }
+                return Err( PathError::SymlinkEscape );
+            },
+            Err( why ) => {
+                // a dangling symlink cannot be trusted to stay inside the store
+                if existing.symlink_metadata().is_ok() {
+                    return Err( PathError::Io( why ) );
+                }
+                existing = match existing.parent() {
+                    Some( parent ) => parent,
+                    None => return Err( PathError::Io( why ) ),
+                };
+            },
+        }
+    }
+}
+
+#[ cfg( test ) ]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use scratch::ScratchDir;
+
+    // build a scratch turtlefs-root with a store and a secret outside of it
+    fn scratch_root() -> ScratchDir {
+        let root = ScratchDir::new( "store-path" );
+        fs::create_dir_all( root.join( "store" ).join( "dir" ) ).unwrap();
+        fs::write( root.join( "store" ).join( "dir" ).join( "file.txt" ), "data" ).unwrap();
+        fs::write( root.join( "nodes.yaml" ), "secret" ).unwrap();
+        root
+    }
+
+    #[ test ]
+    fn resolves_paths_inside_store() {
+        let root = scratch_root();
+        let store = root.join( "store" );
+        assert_eq!( resolve( &store, "/dir/file.txt" ).unwrap(),
+                    store.join( "dir" ).join( "file.txt" ) );
+        assert_eq!( resolve( &store, "//dir/./file.txt" ).unwrap(),
+                    store.join( "dir" ).join( "file.txt" ) );
+        assert_eq!( resolve( &store, "/new/dir/file.txt" ).unwrap(),
+                    store.join( "new" ).join( "dir" ).join( "file.txt" ) );
+        assert_eq!( resolve( &store, "/" ).unwrap(), store );
+    }
+
+    #[ test ]
+    fn files_must_not_be_directories() {
+        let root = scratch_root();
+        let store = root.join( "store" );
+        for request in &[ "/", "", "/.", "//", "/dir", "/dir/" ] {
+            match resolve_file( &store, request ) {
+                Err( PathError::NotAFile ) => {},
+                other => panic!( "{:?}: expected not a file error, got {:?}", request, other ),
+            }
+        }
+        assert_eq!( resolve_file( &store, "/dir/file.txt" ).unwrap(),
+                    store.join( "dir" ).join( "file.txt" ) );
+        assert_eq!( resolve_file( &store, "/dir/new.txt" ).unwrap(),
+                    store.join( "dir" ).join( "new.txt" ) );
+    }
+
+    #[ test ]
+    fn temp_paths_are_unique_and_next_to_the_file() {
+        let root = scratch_root();
+        let store = root.join( "store" );
+        let a_txt = temp_path( &store.join( "dir" ).join( "a.txt" ) );
+        let a_zip = temp_path( &store.join( "dir" ).join( "a.zip" ) );
+        assert_ne!( a_txt, a_zip );
+        assert_ne!( a_txt, temp_path( &store.join( "dir" ).join( "a.txt" ) ) );
+        assert_eq!( a_txt.parent(), Some( store.join( "dir" ).as_path() ) );
+        assert!( a_txt.to_str().unwrap().ends_with( TEMP_SUFFIX ) );
+
+        // a failed write leaves nothing behind
+        let file_name = store.join( "dir" ).join( "b.txt" );
+        assert!( replace_file( &file_name, | _ | Err( io::Error::other( "failed" ) ) )
+                 .is_err() );
+        assert_eq!( fs::read_dir( store.join( "dir" ) ).unwrap().count(), 1 );
+        replace_file( &file_name, | file | file.write_all( b"data" ) ).unwrap();
+        assert_eq!( fs::read( &file_name ).unwrap(), b"data" );
+        assert_eq!( fs::read_dir( store.join( "dir" ) ).unwrap().count(), 2 );
+    }
+
+    #[ test ]
+    fn rejects_parent_traversal() {
+        let root = scratch_root();
+        let store = root.join( "store" );
+        for request in &[ "/..", "/../nodes.yaml", "/dir/../../nodes.yaml",
+                          "/dir/../file.txt", "/./../nodes.yaml",
+                          "/../../../../../../etc/passwd", "/dir/.." ] {
+            match resolve( &store, request ) {
+                Err( PathError::ParentTraversal ) => {},
+                other => panic!( "{}: expected parent traversal error, got {:?}",
+                                 request, other ),
+            }
+        }
+    }
+
+    #[ test ]
+    fn rejects_nul_bytes() {
+        let root = scratch_root();
+        let store = root.join( "store" );
+        for request in &[ "/file.txt\0", "/\0/../nodes.yaml", "/dir/\0file.txt" ] {
+            match resolve( &store, request ) {
+                Err( PathError::NulByte ) => {},
+                other => panic!( "{:?}: expected NUL byte error, got {:?}",
+                                 request, other ),
+            }
+        }
+    }
+
+    #[ cfg( windows ) ]
+    #[ test ]
+    fn rejects_absolute_components() {
+        let root = scratch_root();
+        let store = root.join( "store" );
+        for request in &[ "/C:", "/C:/Windows", "/dir/\\\\server\\share",
+                          "/\\..\\nodes.yaml" ] {
+            match resolve( &store, request ) {
+                Err( PathError::AbsoluteComponent( _ ) ) => {},
+                other => panic!( "{}: expected absolute component error, got {:?}",
+                                 request, other ),
+            }
+        }
+    }
+
+    #[ cfg( unix ) ]
+    #[ test ]
+    fn rejects_symlink_escapes() {
+        use std::os::unix::fs::symlink;
+
+        let root = scratch_root();
+        let store = root.join( "store" );
+        symlink( &root, store.join( "escape" ) ).unwrap();
+        symlink( root.join( "nodes.yaml" ), store.join( "nodes.yaml" ) ).unwrap();
+        symlink( root.join( "missing" ), store.join( "dangling" ) ).unwrap();
+        symlink( store.join( "dir" ), store.join( "inside" ) ).unwrap();
+
+        for request in &[ "/escape/nodes.yaml", "/escape", "/nodes.yaml",
+                          "/escape/new.txt" ] {
+            match resolve( &store, request ) {
+                Err( PathError::SymlinkEscape ) => {},
+                other => panic!( "{}: expected symlink escape error, got {:?}",
+                                 request, other ),
+            }
+        }
+        assert!( resolve( &store, "/dangling" ).is_err() );
+        assert!( resolve( &store, "/dangling/new.txt" ).is_err() );
+
+        // symlinks which stay inside the store are fine
+        assert!( resolve( &store, "/inside/file.txt" ).is_ok() );
+    }
+
+    #[ test ]
+    fn nothing_resolves_outside_store() {
+        let root = scratch_root();
+        let store = root.join( "store" );
+        let canonical_store = store.canonicalize().unwrap();
+        let pieces = [ "", ".", "..", "dir", "file.txt", "nodes.yaml", "\0",
+                       "...", ".. ", "%2e%2e", "store" ];
+
+        // every combination of up to three pieces either fails or stays put
+        for a in &pieces {
+            for b in &pieces {
+                for c in &pieces {
+                    let request = format!( "/{}/{}/{}", a, b, c );
+                    if let Ok( file_name ) = resolve( &store, &request ) {
+                        assert!( file_name.starts_with( &store ), "{:?}", request );
+                        let mut existing = file_name.as_path();
+                        while !existing.exists() {
+                            existing = existing.parent().unwrap();
+                        }
+                        assert!( existing.canonicalize().unwrap()
+                                 .starts_with( &canonical_store ), "{:?}", request );
+                    }
+                }
+            }
+        }
+    }
+}
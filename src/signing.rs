@@ -0,0 +1,240 @@
+// Request signing with shared secrets, read from turtlefs-root/keys.yaml:
+//
+//     signing_key: alice
+//     require_signatures: true
+//     keys:
+//       alice: 6f1c0e5a9b3d2f4e8a7c6b5d4e3f2a1b
+//       nodes: 0d9e8f7a6b5c4d3e2f1a0b9c8d7e6f5a
+//
+// Secrets are hex, at least 16 bytes long. Requests are signed with the key
+// named by signing_key, using HMAC-SHA256 over the action, path and args,
+// the key name, a timestamp and a random nonce, and the payload. Servers
+// accept signatures made with any of the keys, and the key name is who the
+// request was made by. With require_signatures set, servers reject
+// unsigned requests.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use yaml_rust::{Yaml, YamlLoader};
+use protocol::{self, Opcode};
+
+// location of the keys, relative to turtlefs-root
+pub const KEYS_CONFIG_PATH: &str = "keys.yaml";
+
+// signed requests whose timestamp is further than this from the node's
+// clock are rejected, so their nonces only need remembering for this long
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs( 300 );
+
+// shortest secret accepted, in bytes
+pub const MIN_SECRET_LENGTH: usize = 16;
+
+// number of args a signature takes up at the end of a signed request
+pub const SIGNATURE_ARG_COUNT: usize = 4;
+
+#[ derive( Clone ) ]
+pub struct SigningKey {
+    pub name: String,
+    secret: Vec< u8 >,
+}
+
+// the signature sent along with a request
+#[ derive( Clone, Debug, PartialEq, Eq ) ]
+pub struct Signature {
+    pub key_name: String,
+    // seconds since the Unix epoch
+    pub timestamp: u64,
+    pub nonce: String,
+    // HMAC-SHA256, as hex
+    pub mac: String,
+}
+
+// why a request was refused
+#[ derive( Clone, Debug, PartialEq, Eq ) ]
+pub enum SignatureError {
+    Unsigned,
+    // the nonce was already used, or the timestamp is too far off
+    Replayed( String ),
+    // unknown key, malformed signature or wrong MAC
+    BadSignature( String ),
+}
+
+#[ derive( Clone, Default ) ]
+pub struct KeyRing {
+    pub signing_key: Option< SigningKey >,
+    pub require_signatures: bool,
+    keys: HashMap< String, Vec< u8 > >,
+}
+
+fn to_hex( bytes: &[u8] ) -> String {
+    let mut hex = String::with_capacity( bytes.len() * 2 );
+    for byte in bytes {
+        write!( hex, "{:02x}", byte ).unwrap();
+    }
+    hex
+}
+
+fn from_hex( hex: &str ) -> Option< Vec< u8 > > {
+    if !hex.len().is_multiple_of( 2 ) || !hex.is_ascii() {
+        return None;
+    }
+    ( 0 .. hex.len() / 2 )
+        .map( | index | u8::from_str_radix( &hex[ index * 2 .. index * 2 + 2 ], 16 ).ok() )
+        .collect()
+}
+
+// HMAC-SHA256 of a request, covering the signature's key name, timestamp
+// and nonce, with every piece length-prefixed so that no two different
+// requests are signed over the same bytes. Fails if a piece is too long to
+// be length-prefixed, in which case it can't be sent in a frame either.
+fn request_mac( secret: &[u8], opcode: Opcode, path: &str, args: &[String],
+                signature: &Signature, payload: &[u8] ) -> io::Result< Vec< u8 > > {
+    let mut signed_fields = vec![ String::from( opcode.name() ), String::from( path ),
+                                  args.len().to_string() ];
+    signed_fields.extend_from_slice( args );
+    signed_fields.push( signature.key_name.clone() );
+    signed_fields.push( signature.timestamp.to_string() );
+    signed_fields.push( signature.nonce.clone() );
+
+    let key = PKey::hmac( secret ).unwrap();
+    let mut signer = Signer::new( MessageDigest::sha256(), &key ).unwrap();
+    signer.update( &protocol::encode_args( &signed_fields )? ).unwrap();
+    signer.update( payload ).unwrap();
+    Ok( signer.sign_to_vec().unwrap() )
+}
+
+impl SigningKey {
+    pub fn new( name: &str, secret: &[u8] ) -> SigningKey {
+        SigningKey { name: String::from( name ), secret: secret.to_vec() }
+    }
+
+    // sign a request as of now, with a fresh nonce
+    pub fn sign( &self, opcode: Opcode, path: &str, args: &[String], payload: &[u8] )
+                 -> io::Result< Signature > {
+        let timestamp = SystemTime::now().duration_since( UNIX_EPOCH )
+            .map_or( 0, | now | now.as_secs() );
+        let mut nonce = [ 0u8; 16 ];
+        rand_bytes( &mut nonce ).unwrap();
+        let mut signature = Signature { key_name: self.name.clone(), timestamp,
+                                        nonce: to_hex( &nonce ), mac: String::new() };
+        signature.mac = to_hex( &request_mac( &self.secret, opcode, path, args, &signature,
+                                              payload )? );
+        Ok( signature )
+    }
+}
+
+impl Signature {
+    pub fn to_args( &self ) -> Vec< String > {
+        vec![ self.key_name.clone(), self.timestamp.to_string(), self.nonce.clone(),
+              self.mac.clone() ]
+    }
+
+    pub fn from_args( args: &[String] ) -> Result< Signature, SignatureError > {
+        if args.len() != SIGNATURE_ARG_COUNT {
+            return Err( SignatureError::BadSignature( format!(
+                "a signature takes {} arguments, not {}", SIGNATURE_ARG_COUNT, args.len() ) ) );
+        }
+        let timestamp = args[ 1 ].parse().map_err( | _ | SignatureError::BadSignature(
+            format!( "invalid signature timestamp \"{}\"", args[ 1 ] ) ) )?;
+        Ok( Signature { key_name: args[ 0 ].clone(), timestamp, nonce: args[ 2 ].clone(),
+                        mac: args[ 3 ].clone() } )
+    }
+}
+
+impl KeyRing {
+    // read turtlefs-root/keys.yaml, or None if there isn't one
+    pub fn load( turtlefs_root: &str ) -> Result< Option< KeyRing >, String > {
+        KeyRing::load_file( &Path::new( turtlefs_root ).join( KEYS_CONFIG_PATH ) )
+    }
+
+    // read keys kept somewhere else, or None if there aren't any
+    pub fn load_file( config_path: &Path ) -> Result< Option< KeyRing >, String > {
+        if !config_path.exists() {
+            return Ok( None );
+        }
+        let mut contents = String::new();
+        File::open( config_path )
+            .and_then( | mut f | f.read_to_string( &mut contents ) )
+            .map_err( | why | format!( "could not read {}: {}", config_path.display(), why ) )?;
+        KeyRing::parse( &contents )
+            .map( Some )
+            .map_err( | why | format!( "invalid {}: {}", config_path.display(), why ) )
+    }
+
+    pub fn parse( contents: &str ) -> Result< KeyRing, String > {
+        let docs = YamlLoader::load_from_str( contents ).map_err( | why | why.to_string() )?;
+        let doc = match docs.first() {
+            Some( doc @ &Yaml::Hash( _ ) ) => doc,
+            _ => return Err( String::from( "expected a map of settings" ) ),
+        };
+
+        let mut keys = HashMap::new();
+        match doc[ "keys" ] {
+            Yaml::Hash( ref key_map ) => for ( name, secret ) in key_map {
+                let name = match *name {
+                    Yaml::String( ref name ) => name.clone(),
+                    Yaml::Integer( name ) => name.to_string(),
+                    _ => return Err( String::from( "key names must be strings" ) ),
+                };
+                // an all-digit secret would be loaded as an integer, losing
+                // any leading zeros, so it has to be quoted
+                let secret = secret.as_str().and_then( from_hex ).ok_or_else( || format!(
+                    "key {} must be a hex secret (quoted if it is all digits)", name ) )?;
+                if secret.len() < MIN_SECRET_LENGTH {
+                    return Err( format!( "key {} must be at least {} bytes ({} hex digits)",
+                                         name, MIN_SECRET_LENGTH, MIN_SECRET_LENGTH * 2 ) );
+                }
+                keys.insert( name, secret );
+            },
+            Yaml::BadValue => {},
+            _ => return Err( String::from( "keys must be a map of key names to secrets" ) ),
+        }
+
+        let signing_key = match doc[ "signing_key" ] {
+            Yaml::String( ref name ) => {
+                let secret = keys.get( name ).ok_or_else( || format!(
+                    "signing_key {} is not one of the keys", name ) )?;
+                Some( SigningKey::new( name, secret ) )
+            },
+            Yaml::BadValue => None,
+            _ => return Err( String::from( "signing_key must be a key name" ) ),
+        };
+        let require_signatures = match doc[ "require_signatures" ] {
+            Yaml::Boolean( require_signatures ) => require_signatures,
+            Yaml::BadValue => false,
+            _ => return Err( String::from( "require_signatures must be true or false" ) ),
+        };
+        if require_signatures && keys.is_empty() {
+            return Err( String::from( "require_signatures needs at least one key" ) );
+        }
+
+        Ok( KeyRing { signing_key, require_signatures, keys } )
+    }
+
+    // check that the signature was made over this request with one of the
+    // keys, returning the key's name. Whether it is fresh is up to the
+    // caller.
+    pub fn verify( &self, opcode: Opcode, path: &str, args: &[String], signature: &Signature,
+                   payload: &[u8] ) -> Result< &str, SignatureError > {
+        let ( key_name, secret ) = self.keys.get_key_value( &signature.key_name )
+            .ok_or_else( || SignatureError::BadSignature( format!(
+                "unknown key \"{}\"", signature.key_name ) ) )?;
+        let mac = from_hex( &signature.mac ).ok_or_else( || SignatureError::BadSignature(
+            String::from( "the signature is not hex" ) ) )?;
+        let expected_mac = request_mac( secret, opcode, path, args, signature, payload )
+            .map_err( | why | SignatureError::BadSignature( why.to_string() ) )?;
+        if mac.len() != expected_mac.len() || !memcmp::eq( &mac, &expected_mac ) {
+            return Err( SignatureError::BadSignature( format!(
+                "the signature does not match the request for key \"{}\"", key_name ) ) );
+        }
+        Ok( key_name )
+    }
+}
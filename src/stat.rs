@@ -0,0 +1,65 @@
+// File metadata returned by the STAT action, sent as a small YAML document:
+//
+//     size: 13
+//     modified: 1541376000
+//     permissions: 644
+//     checksum: "DB588331"
+//
+// The checksum is crc32 unless another algorithm was asked for, in which
+// case it is typed, e.g. sha256:<64 hex digits>. It is quoted so that it
+// always loads as a string.
+
+use std::fmt::Write;
+use yaml_rust::{Yaml, YamlLoader};
+use digest::Digest;
+
+#[ derive( Clone, Debug, PartialEq, Eq ) ]
+pub struct FileStat {
+    // length in bytes
+    pub size: u64,
+    // modification time in seconds since the Unix epoch
+    pub modified: u64,
+    // permission bits, e.g. 0o644
+    pub permissions: u32,
+    // checksum of the file as stored on the node
+    pub checksum: Digest,
+}
+
+impl FileStat {
+    pub fn to_yaml( &self ) -> String {
+        let mut contents = String::new();
+        writeln!( contents, "size: {}", self.size ).unwrap();
+        writeln!( contents, "modified: {}", self.modified ).unwrap();
+        writeln!( contents, "permissions: {:o}", self.permissions ).unwrap();
+        writeln!( contents, "checksum: \"{}\"", self.checksum ).unwrap();
+        contents
+    }
+
+    pub fn parse( contents: &str ) -> Result< FileStat, String > {
+        let docs = YamlLoader::load_from_str( contents )
+            .map_err( | why | format!( "could not parse file metadata: {}", why ) )?;
+        let doc = docs.first().ok_or_else( || String::from( "empty file metadata" ) )?;
+
+        let integer = | key: &str | match doc[ key ] {
+            Yaml::Integer( value ) if value >= 0 => Ok( value as u64 ),
+            _ => Err( format!( "file metadata {} must be a non-negative integer", key ) ),
+        };
+        // permissions are octal and a crc32 checksum is hex, but either may
+        // be all digits and so loaded as an integer (and an unquoted
+        // checksum such as 1234E567 as a float)
+        let number_str = | key: &str | match doc[ key ] {
+            Yaml::String( ref value ) | Yaml::Real( ref value ) => Ok( value.clone() ),
+            Yaml::Integer( value ) => Ok( value.to_string() ),
+            _ => Err( format!( "file metadata is missing {}", key ) ),
+        };
+        let permissions = number_str( "permissions" )?;
+
+        Ok( FileStat {
+            size: integer( "size" )?,
+            modified: integer( "modified" )?,
+            permissions: u32::from_str_radix( &permissions, 8 ).map_err( | why | format!(
+                "invalid file metadata permissions \"{}\": {}", permissions, why ) )?,
+            checksum: number_str( "checksum" )?.parse()?,
+        } )
+    }
+}
@@ -0,0 +1,147 @@
+// TLS settings for connections between nodes and clients, read from
+// turtlefs-root/tls.yaml:
+//
+//     certificate: tls/node.crt
+//     private_key: tls/node.key
+//     ca: tls/ca.crt
+//     require_tls: true
+//     require_client_certificate: true
+//
+// Paths are relative to turtlefs-root. While tls.yaml is present, requests
+// are sent over TLS, and node certificates are checked against the CA
+// certificates in ca (or the system's when ca is left out). The certificate
+// and private key are what a server presents, and what a client presents
+// when the server asks for one.
+//
+// Servers accept TLS connections alongside plaintext ones, unless
+// require_tls is set. With require_client_certificate set, they only accept
+// clients presenting a certificate signed by ca (mutual TLS).
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use openssl::ssl::{SslAcceptor, SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use yaml_rust::{Yaml, YamlLoader};
+
+// location of the TLS settings, relative to turtlefs-root
+pub const TLS_CONFIG_PATH: &str = "tls.yaml";
+
+#[ derive( Clone, Debug, Default, PartialEq, Eq ) ]
+pub struct TlsSettings {
+    // certificate chain and private key of this end of the connection
+    pub certificate: Option< PathBuf >,
+    pub private_key: Option< PathBuf >,
+    // CA certificates trusted to sign the other end's certificate
+    pub ca: Option< PathBuf >,
+    // servers refuse plaintext connections
+    pub require_tls: bool,
+    // servers refuse clients without a certificate signed by ca
+    pub require_client_certificate: bool,
+}
+
+impl TlsSettings {
+    // read turtlefs-root/tls.yaml, or None if there isn't one
+    pub fn load( turtlefs_root: &str ) -> Result< Option< TlsSettings >, String > {
+        TlsSettings::load_file( turtlefs_root, &Path::new( turtlefs_root ).join( TLS_CONFIG_PATH ) )
+    }
+
+    // read TLS settings kept somewhere else, or None if there aren't any
+    pub fn load_file( turtlefs_root: &str, config_path: &Path )
+                      -> Result< Option< TlsSettings >, String > {
+        if !config_path.exists() {
+            return Ok( None );
+        }
+        let mut contents = String::new();
+        File::open( config_path )
+            .and_then( | mut f | f.read_to_string( &mut contents ) )
+            .map_err( | why | format!( "could not read {}: {}", config_path.display(), why ) )?;
+        TlsSettings::parse( turtlefs_root, &contents )
+            .map( Some )
+            .map_err( | why | format!( "invalid {}: {}", config_path.display(), why ) )
+    }
+
+    // parse the contents of tls.yaml, resolving paths against turtlefs_root
+    pub fn parse( turtlefs_root: &str, contents: &str ) -> Result< TlsSettings, String > {
+        let docs = YamlLoader::load_from_str( contents ).map_err( | why | why.to_string() )?;
+        let doc = match docs.first() {
+            Some( doc @ &Yaml::Hash( _ ) ) => doc,
+            // an empty file turns on TLS with the system's CA certificates
+            None | Some( &Yaml::Null ) => return Ok( TlsSettings::default() ),
+            Some( _ ) => return Err( String::from( "expected a map of settings" ) ),
+        };
+
+        let path = | key: &str | match doc[ key ] {
+            Yaml::String( ref path ) => Ok( Some( Path::new( turtlefs_root ).join( path ) ) ),
+            Yaml::BadValue => Ok( None ),
+            _ => Err( format!( "{} must be a path", key ) ),
+        };
+        let flag = | key: &str | match doc[ key ] {
+            Yaml::Boolean( flag ) => Ok( flag ),
+            Yaml::BadValue => Ok( false ),
+            _ => Err( format!( "{} must be true or false", key ) ),
+        };
+
+        let settings = TlsSettings {
+            certificate: path( "certificate" )?,
+            private_key: path( "private_key" )?,
+            ca: path( "ca" )?,
+            require_tls: flag( "require_tls" )?,
+            require_client_certificate: flag( "require_client_certificate" )?,
+        };
+        if settings.certificate.is_some() != settings.private_key.is_some() {
+            return Err( String::from( "certificate and private_key must be given together" ) );
+        }
+        if settings.require_client_certificate && settings.ca.is_none() {
+            return Err( String::from(
+                "require_client_certificate needs the ca that signs client certificates" ) );
+        }
+        Ok( settings )
+    }
+
+    // TLS setup for connecting to nodes
+    pub fn connector( &self ) -> Result< SslConnector, String > {
+        let mut builder = SslConnector::builder( SslMethod::tls() )
+            .map_err( | why | format!( "could not set up TLS: {}", why ) )?;
+        if let Some( ref ca ) = self.ca {
+            builder.set_ca_file( ca )
+                .map_err( | why | format!( "could not load {}: {}", ca.display(), why ) )?;
+        }
+        if let ( Some( certificate ), Some( private_key ) ) =
+            ( self.certificate.as_ref(), self.private_key.as_ref() ) {
+            builder.set_certificate_chain_file( certificate ).map_err( | why | format!(
+                "could not load {}: {}", certificate.display(), why ) )?;
+            builder.set_private_key_file( private_key, SslFiletype::PEM ).map_err( | why | format!(
+                "could not load {}: {}", private_key.display(), why ) )?;
+            builder.check_private_key().map_err( | why | format!(
+                "{} does not match {}: {}", private_key.display(), certificate.display(),
+                why ) )?;
+        }
+        Ok( builder.build() )
+    }
+
+    // TLS setup for accepting connections, which needs a certificate
+    pub fn acceptor( &self ) -> Result< SslAcceptor, String > {
+        let ( certificate, private_key ) = match ( &self.certificate, &self.private_key ) {
+            ( Some( certificate ), Some( private_key ) ) => ( certificate, private_key ),
+            _ => return Err( format!(
+                "{} needs a certificate and private_key to accept TLS connections",
+                TLS_CONFIG_PATH ) ),
+        };
+        let mut builder = SslAcceptor::mozilla_intermediate_v5( SslMethod::tls() )
+            .map_err( | why | format!( "could not set up TLS: {}", why ) )?;
+        builder.set_certificate_chain_file( certificate ).map_err( | why | format!(
+            "could not load {}: {}", certificate.display(), why ) )?;
+        builder.set_private_key_file( private_key, SslFiletype::PEM ).map_err( | why | format!(
+            "could not load {}: {}", private_key.display(), why ) )?;
+        builder.check_private_key().map_err( | why | format!(
+            "{} does not match {}: {}", private_key.display(), certificate.display(), why ) )?;
+        if let Some( ref ca ) = self.ca {
+            builder.set_ca_file( ca )
+                .map_err( | why | format!( "could not load {}: {}", ca.display(), why ) )?;
+        }
+        if self.require_client_certificate {
+            builder.set_verify( SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT );
+        }
+        Ok( builder.build() )
+    }
+}
@@ -0,0 +1,469 @@
+// TurtleClient, the entry point for reading and writing turtleFS files from
+// other programs.
+//
+// Example:
+//
+//     let client = TurtleClient::from_root( "/path/to/turtlefs-root" )?;
+//     let contents = client.get( "/hello.txt" )?;
+//     client.download( "/hello.zip", "hello.zip" )?;
+//     let header = client.get_range( "/hello.zip", 0, 4 )?;
+//     let mut reader = client.open( "/hello.zip" )?;
+//     reader.seek( SeekFrom::End( -22 ) )?;
+
+use std::collections::{BTreeMap, HashSet};
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use digest::{Algorithm, Digest};
+use download::{self, PartialFile};
+use listing::ListEntry;
+use manifest::{MANIFEST_PATH, FileEntry, Manifest, ManifestEdit};
+use node::{self, Node};
+use pool::{ConnectionPool, POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE};
+use protocol::Opcode;
+use remote_reader::RemoteReader;
+use request::{self, Request, Retrieved, MAX_PARALLEL_CHUNKS};
+use stat::FileStat;
+
+#[ derive( Debug ) ]
+pub enum ClientError {
+    // nodes.yaml or file_store.yaml could not be read or understood
+    Config( String ),
+    // file_store.yaml has no entry, or no nodes, for the file
+    NotFound( String ),
+    // none of the nodes asked could answer the request
+    Unavailable( String ),
+    // a node answered, but refused the request
+    Rejected( String ),
+    // the retrieved file does not match its checksum in file_store.yaml
+    ChecksumMismatch { file_name: String, expected: Digest, actual: Digest },
+    // the retrieved file could not be written out
+    Io( io::Error ),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            ClientError::Config( ref why ) |
+            ClientError::Unavailable( ref why ) |
+            ClientError::Rejected( ref why ) => write!( fmt, "{}", why ),
+            ClientError::NotFound( ref file_name ) =>
+                write!( fmt, "no active nodes for file {}", file_name ),
+            ClientError::ChecksumMismatch { ref file_name, expected, actual } =>
+                write!( fmt, "{} has checksum {}, but file_store.yaml expects {}",
+                        file_name, actual, expected ),
+            ClientError::Io( ref why ) => write!( fmt, "{}", why ),
+        }
+    }
+}
+
+impl error::Error for ClientError {}
+
+// the metadata of a file on each of the nodes which provide it
+pub type NodeStats = Vec< ( Node, Result< FileStat, ClientError > ) >;
+
+// the checksum of a file on each of the nodes which provide it
+pub type NodeChecksums = Vec< ( Node, Result< Digest, ClientError > ) >;
+
+// an entry in the namespace described by file_store.yaml
+#[ derive( Clone, Debug, PartialEq ) ]
+pub enum NamespaceEntry {
+    // a directory holding at least one file, by request path
+    Directory( String ),
+    File {
+        // request path, e.g. /dir/hello.txt
+        file_name: String,
+        entry: FileEntry,
+        // nodes listed for the file which could not be connected to
+        unreachable_nodes: Vec< Node >,
+    },
+}
+
+// a file retrieved by TurtleClient::download
+#[ derive( Clone, Debug, PartialEq ) ]
+pub struct Downloaded {
+    pub retrieved: Retrieved,
+    // whether an earlier, interrupted download had already written part of
+    // the file
+    pub resumed: bool,
+}
+
+// connects to a turtleFS deployment through its root nodes, reusing
+// connections to every node across requests
+pub struct TurtleClient {
+    pool: Arc< ConnectionPool >,
+    root_nodes: Vec< Node >,
+    max_parallel_chunks: usize,
+}
+
+impl TurtleClient {
+    // use the root nodes listed in turtlefs-root/nodes.yaml, connecting and
+    // signing requests as turtlefs-root says (see ConnectionPool::from_root)
+    pub fn from_root< P: AsRef< Path > >( turtlefs_root: P ) -> Result< TurtleClient, ClientError > {
+        let turtlefs_root = turtlefs_root.as_ref().to_str().ok_or_else( || ClientError::Config(
+            format!( "{:?} is not valid UTF-8", turtlefs_root.as_ref() ) ) )?;
+        let root_nodes = node::retrieve_root_nodes( turtlefs_root )
+            .map_err( ClientError::Config )?;
+        let pool = ConnectionPool::from_root( turtlefs_root ).map_err( ClientError::Config )?;
+        Ok( TurtleClient::from_nodes( root_nodes ).with_pool( pool ) )
+    }
+
+    // use the given root nodes
+    pub fn from_nodes( root_nodes: Vec< Node > ) -> TurtleClient {
+        TurtleClient {
+            pool: Arc::new( ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE ) ),
+            root_nodes,
+            max_parallel_chunks: MAX_PARALLEL_CHUNKS,
+        }
+    }
+
+    // make requests through the given pool, e.g. one set up for TLS or
+    // signing requests
+    pub fn with_pool( mut self, pool: ConnectionPool ) -> TurtleClient {
+        self.pool = Arc::new( pool );
+        self
+    }
+
+    // change how many file chunks get requests at the same time
+    pub fn with_max_parallel_chunks( mut self, max_parallel_chunks: usize ) -> TurtleClient {
+        self.max_parallel_chunks = max_parallel_chunks;
+        self
+    }
+
+    pub fn root_nodes( &self ) -> &[Node] {
+        &self.root_nodes
+    }
+
+    // the connection pool shared by every request this client makes
+    pub fn pool( &self ) -> &Arc< ConnectionPool > {
+        &self.pool
+    }
+
+    // retrieve the current file_store.yaml
+    pub fn manifest( &self ) -> Result< Manifest, ClientError > {
+        request::request_manifest( &self.pool, self.root_nodes.clone() )
+            .map_err( ClientError::Unavailable )
+    }
+
+    // the file_store.yaml entry for a file, which must list at least one node
+    pub fn entry( &self, file_name: &str ) -> Result< FileEntry, ClientError > {
+        match self.manifest()?.entry( file_name ) {
+            Some( entry ) if !entry.nodes.is_empty() => Ok( entry.clone() ),
+            _ => Err( ClientError::NotFound( file_name.to_string() ) ),
+        }
+    }
+
+    pub fn length( &self, file_name: &str ) -> Result< u64, ClientError > {
+        let entry = self.entry( file_name )?;
+        self.entry_length( file_name, &entry )
+    }
+
+    // the length of a file, from the nodes listed in its entry
+    pub fn entry_length( &self, file_name: &str, entry: &FileEntry )
+                         -> Result< u64, ClientError > {
+        request::request_length( &self.pool, entry.nodes.clone(), file_name )
+            .ok_or_else( || ClientError::Unavailable(
+                format!( "Could not retrieve file length for {}", file_name ) ) )
+    }
+
+    // list the files and directories directly under dir_name in
+    // file_store.yaml, checking which of the files' nodes can be reached.
+    // Listing a file's own name lists just that file, and a directory
+    // with no files under it (or / when file_store.yaml is empty) has
+    // nothing to list.
+    pub fn ls( &self, dir_name: &str ) -> Result< Vec< NamespaceEntry >, ClientError > {
+        let manifest = self.manifest()?;
+        let prefix = format!( "{}/", dir_name.trim_end_matches( '/' ) );
+
+        let mut listed = BTreeMap::new();
+        for ( file_name, entry ) in manifest.files.iter() {
+            if file_name == dir_name {
+                listed.insert( file_name.clone(), Some( entry ) );
+            } else if let Some( rest ) = file_name.strip_prefix( &prefix ) {
+                match rest.find( '/' ) {
+                    Some( end ) => listed.insert( format!( "{}{}", prefix, &rest[ .. end ] ), None ),
+                    None => listed.insert( file_name.clone(), Some( entry ) ),
+                };
+            }
+        }
+        let nodes: HashSet< Node > = listed.values()
+            .flat_map( | entry | entry.iter().flat_map( | entry | entry.nodes.iter().cloned() ) )
+            .collect();
+        let unreachable = self.unreachable_nodes( nodes );
+        Ok( listed.into_iter().map( | ( name, entry ) | match entry {
+            Some( entry ) => NamespaceEntry::File {
+                file_name: name,
+                entry: entry.clone(),
+                unreachable_nodes: entry.nodes.iter()
+                    .filter( | node | unreachable.contains( *node ) )
+                    .cloned()
+                    .collect(),
+            },
+            None => NamespaceEntry::Directory( name ),
+        } ).collect() )
+    }
+
+    // the nodes which can't be connected to, trying them all at once
+    fn unreachable_nodes( &self, nodes: HashSet< Node > ) -> HashSet< Node > {
+        let probes: Vec< _ > = nodes.into_iter().map( | node | {
+            let pool = self.pool.clone();
+            thread::spawn( move || match pool.checkout( &node ) {
+                Ok( ( stream, _ ) ) => {
+                    pool.checkin( &node, stream );
+                    None
+                },
+                Err( _ ) => Some( node ),
+            } )
+        } ).collect();
+        probes.into_iter().filter_map( | probe | probe.join().ok().and_then( | node | node ) )
+            .collect()
+    }
+
+    // list what a single node holds under dir_name in its store, which may
+    // include files file_store.yaml doesn't know about
+    pub fn list_node( &self, node: &Node, dir_name: &str, recursive: bool )
+                      -> Result< Vec< ListEntry >, ClientError > {
+        request::request_listing( &self.pool, node, dir_name, recursive ).map_err( | why | {
+            if request::is_connection_failure( node, &why ) {
+                ClientError::Unavailable( why )
+            } else {
+                ClientError::Rejected( format!( "{} could not list {}: {}", node, dir_name, why ) )
+            }
+        } )
+    }
+
+    // retrieve the metadata of a file from every node that file_store.yaml
+    // lists for it, so that replicas which have diverged stand out. The
+    // checksums use the same algorithm as file_store.yaml.
+    pub fn stat( &self, file_name: &str ) -> Result< NodeStats, ClientError > {
+        let entry = self.entry( file_name )?;
+        let algorithm = entry.checksum.algorithm();
+        Ok( entry.nodes.into_iter().map( | node | {
+            let file_stat = self.stat_node( file_name, &node, algorithm );
+            ( node, file_stat )
+        } ).collect() )
+    }
+
+    // retrieve the metadata of a file from a single node, with its checksum
+    // computed using the given algorithm
+    pub fn stat_node( &self, file_name: &str, node: &Node, algorithm: Algorithm )
+                      -> Result< FileStat, ClientError > {
+        request::request_stat( &self.pool, node, file_name, algorithm ).map_err( | why | {
+            if request::is_connection_failure( node, &why ) {
+                ClientError::Unavailable( why )
+            } else {
+                ClientError::Rejected( format!( "{} could not stat {}: {}", node, file_name, why ) )
+            }
+        } )
+    }
+
+    // have a single node compute the checksum of a file, or of the bytes
+    // from start_offset up to (but not including) end_offset, without
+    // sending the data back
+    pub fn checksum_node( &self, file_name: &str, node: &Node, algorithm: Algorithm,
+                          range: Option< ( u64, u64 ) > ) -> Result< Digest, ClientError > {
+        request::request_checksum( &self.pool, node, file_name, algorithm, range )
+            .map_err( | why | {
+            if request::is_connection_failure( node, &why ) {
+                ClientError::Unavailable( why )
+            } else {
+                ClientError::Rejected( format!( "{} could not checksum {}: {}", node,
+                                                file_name, why ) )
+            }
+        } )
+    }
+
+    // have every node that file_store.yaml lists for a file compute its
+    // checksum, with the same algorithm as the checksum in file_store.yaml,
+    // so that a bad replica can be found without retrieving it
+    pub fn checksum_replicas( &self, file_name: &str, range: Option< ( u64, u64 ) > )
+                              -> Result< NodeChecksums, ClientError > {
+        let entry = self.entry( file_name )?;
+        let algorithm = entry.checksum.algorithm();
+        Ok( entry.nodes.into_iter().map( | node | {
+            let checksum = self.checksum_node( file_name, &node, algorithm, range );
+            ( node, checksum )
+        } ).collect() )
+    }
+
+    // retrieve a whole file, checking it against file_store.yaml
+    pub fn get( &self, file_name: &str ) -> Result< Vec< u8 >, ClientError > {
+        let mut contents = Vec::new();
+        self.get_to( file_name, &mut contents )?;
+        Ok( contents )
+    }
+
+    // retrieve a whole file, writing it to output as it arrives. The
+    // checksum can only be checked once the whole file has been written, so
+    // output may hold a bad file when this fails with
+    // ClientError::ChecksumMismatch. If file_store.yaml has block checksums
+    // for the file, bad blocks are retrieved again from other nodes, and the
+    // nodes which sent them are returned in Retrieved::corrupt_nodes.
+    pub fn get_to< W: Write >( &self, file_name: &str, output: &mut W )
+                               -> Result< Retrieved, ClientError > {
+        let entry = self.entry( file_name )?;
+        let file_length = self.entry_length( file_name, &entry )?;
+        let retrieved = request::request_file_distributed(
+            self.pool.clone(), entry.nodes.clone(), file_name, file_length, &entry,
+            self.max_parallel_chunks, output )
+            .map_err( ClientError::Unavailable )?;
+        output.flush().map_err( ClientError::Io )?;
+        if retrieved.checksum != entry.checksum {
+            return Err( ClientError::ChecksumMismatch { file_name: file_name.to_string(),
+                                                        expected: entry.checksum,
+                                                        actual: retrieved.checksum } );
+        }
+        Ok( retrieved )
+    }
+
+    // retrieve a whole file into the local file output, picking up where an
+    // earlier download to output left off (see download::PartialFile). The
+    // file is only moved into place once it matches its checksum, and is
+    // thrown away if it doesn't. Anything written before a failure to
+    // retrieve part of the file is kept for the next attempt.
+    pub fn download< P: AsRef< Path > >( &self, file_name: &str, output: P )
+                                         -> Result< Downloaded, ClientError > {
+        let entry = self.entry( file_name )?;
+        let file_length = self.entry_length( file_name, &entry )?;
+        let block_size = entry.blocks.as_ref().map_or( 0, | blocks | blocks.block_size );
+        let mut partial = PartialFile::open( output.as_ref(), &entry.checksum, file_length,
+                                             block_size ).map_err( ClientError::Io )?;
+        let resumed = partial.is_resumed();
+
+        let part_path = download::part_path( output.as_ref() );
+        let missing = partial.missing();
+        let corrupt_nodes = request::request_ranges_distributed(
+            self.pool.clone(), file_name, file_length, &entry, &missing,
+            self.max_parallel_chunks, | start_offset, contents | {
+                partial.write_range( start_offset, contents ).map_err( | why | format!(
+                    "Could not write {}: {}", part_path.display(), why ) )
+            } )
+            .map_err( ClientError::Unavailable )?;
+
+        let checksum = partial.checksum( entry.checksum.algorithm() ).map_err( ClientError::Io )?;
+        if checksum != entry.checksum {
+            partial.discard();
+            return Err( ClientError::ChecksumMismatch { file_name: file_name.to_string(),
+                                                        expected: entry.checksum,
+                                                        actual: checksum } );
+        }
+        partial.finish().map_err( ClientError::Io )?;
+        Ok( Downloaded {
+            retrieved: Retrieved { length: file_length, checksum, corrupt_nodes },
+            resumed,
+        } )
+    }
+
+    // retrieve the bytes from start_offset up to (but not including)
+    // end_offset
+    pub fn get_range( &self, file_name: &str, start_offset: u64, end_offset: u64 )
+                      -> Result< Vec< u8 >, ClientError > {
+        let entry = self.entry( file_name )?;
+        self.entry_range( file_name, &entry, start_offset, end_offset )
+    }
+
+    // get_range from the nodes listed in an entry already retrieved with
+    // entry, so reading a file in several ranges only asks for
+    // file_store.yaml once and always reads the same file
+    pub fn entry_range( &self, file_name: &str, entry: &FileEntry, start_offset: u64,
+                        end_offset: u64 ) -> Result< Vec< u8 >, ClientError > {
+        let primary_node = entry.nodes.first()
+            .ok_or_else( || ClientError::NotFound( file_name.to_string() ) )?;
+        if start_offset == end_offset {
+            return Ok( Vec::new() );
+        }
+        request::request_file_chunk( &self.pool, primary_node, entry.nodes.clone(),
+                                     file_name, start_offset, end_offset )
+            .ok_or_else( || ClientError::Unavailable( format!(
+                "Could not retrieve file contents between {} and {} for {}",
+                start_offset, end_offset, file_name ) ) )
+    }
+
+    // a Read + Seek view of a file which retrieves it in blocks as it is
+    // read, see RemoteReader for changing the block size, read-ahead and cache
+    pub fn open( &self, file_name: &str ) -> Result< RemoteReader, ClientError > {
+        let entry = self.entry( file_name )?;
+        let file_length = self.entry_length( file_name, &entry )?;
+        RemoteReader::new( self.pool.clone(), file_name, entry.nodes, file_length )
+            .map_err( | _ | ClientError::NotFound( file_name.to_string() ) )
+    }
+
+    // push a file to every node that file_store.yaml lists for it, the
+    // contents must match the checksum in file_store.yaml
+    pub fn put( &self, file_name: &str, contents: &[u8] ) -> Result< (), ClientError > {
+        let entry = self.entry( file_name )?;
+        let checksum = Digest::compute( entry.checksum.algorithm(), contents );
+        if checksum != entry.checksum {
+            return Err( ClientError::ChecksumMismatch { file_name: file_name.to_string(),
+                                                        expected: entry.checksum,
+                                                        actual: checksum } );
+        }
+
+        let mut failed_nodes = Vec::new();
+        for node in entry.nodes.iter() {
+            let request = Request {
+                node,
+                opcode: Opcode::Write,
+                file_name,
+                args: Vec::new(),
+                payload: contents,
+            };
+            let response_buffer = &mut Vec::new();
+            let response = request::perform_request( &self.pool, request, response_buffer );
+            if response.status != 0 {
+                failed_nodes.push( format!( "{} ({})", node,
+                                            String::from_utf8_lossy( response.message ).trim_end() ) );
+            }
+        }
+        if !failed_nodes.is_empty() {
+            return Err( ClientError::Rejected( format!(
+                "Could not upload {} to: {}", file_name, failed_nodes.join( ", " ) ) ) );
+        }
+        Ok( () )
+    }
+
+    // remove a file: its entry comes out of file_store.yaml first, so that
+    // no node replicates it again, then its data is deleted from every node
+    // the entry listed. Nodes which could not delete it are reported with
+    // ClientError::Rejected, once the entry is already gone.
+    pub fn remove( &self, file_name: &str ) -> Result< (), ClientError > {
+        let entry = self.manifest()?.entry( file_name ).cloned()
+            .ok_or_else( || ClientError::NotFound( file_name.to_string() ) )?;
+        self.edit_manifest( file_name, &ManifestEdit::RemoveEntry )?;
+
+        let mut failed_nodes = Vec::new();
+        for node in entry.nodes.iter() {
+            let request = Request {
+                node,
+                opcode: Opcode::Delete,
+                file_name,
+                args: Vec::new(),
+                payload: &[],
+            };
+            let response_buffer = &mut Vec::new();
+            let response = request::perform_request( &self.pool, request, response_buffer );
+            if response.status != 0 {
+                failed_nodes.push( format!( "{} ({})", node,
+                                            String::from_utf8_lossy( response.message ).trim_end() ) );
+            }
+        }
+        if !failed_nodes.is_empty() {
+            return Err( ClientError::Rejected( format!(
+                "Removed {} from {}, but could not delete it from: {}", file_name,
+                MANIFEST_PATH, failed_nodes.join( ", " ) ) ) );
+        }
+        Ok( () )
+    }
+
+    // change the file_store.yaml entry for a file, returning the new
+    // manifest version
+    pub fn edit_manifest( &self, file_name: &str, edit: &ManifestEdit )
+                          -> Result< u64, ClientError > {
+        request::edit_manifest( &self.pool, &self.root_nodes, file_name, edit )
+            .map_err( | why | ClientError::Rejected(
+                format!( "Could not edit {}: {}", MANIFEST_PATH, why ) ) )
+    }
+}
@@ -0,0 +1,84 @@
+// Runs a server on localhost with access control lists in its store, and
+// checks that requests are only allowed with the permissions they need.
+
+extern crate turtlefs;
+
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+use turtlefs::node::Node;
+use turtlefs::pool::{ConnectionPool, POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE};
+use turtlefs::protocol::{Opcode, Status};
+use turtlefs::request::{self, Request};
+use turtlefs::signing::SigningKey;
+use common::{Server, TempRoot};
+
+const ACL: &str = "/:\n  admin: [nodes]\n\
+                   /public:\n  read: [\"*\"]\n\
+                   /alice:\n  write: [alice]\n";
+
+fn signed_pool( key_name: &str ) -> ConnectionPool {
+    ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE )
+        .with_signing_key( SigningKey::new( key_name, &[ 7u8; 16 ] ) )
+}
+
+fn status( pool: &ConnectionPool, node: &Node, opcode: Opcode, file_name: &str,
+           payload: &[u8] ) -> u8 {
+    let request = Request { node, opcode, file_name, args: Vec::new(), payload };
+    request::perform_request( pool, request, &mut Vec::new() ).status
+}
+
+#[ test ]
+fn enforces_access_control_lists() {
+    let root = TempRoot::new( "acl" );
+    fs::create_dir_all( root.join( "store" ).join( "public" ) ).unwrap();
+    fs::write( root.join( "store" ).join( "public" ).join( "a.txt" ), "public" ).unwrap();
+    fs::write( root.join( "store" ).join( "hello.txt" ), "Hello\nWorld\n" ).unwrap();
+    fs::write( root.join( "store" ).join( "file_store.yaml" ), "version: 1\n" ).unwrap();
+    fs::write( root.join( "store" ).join( "acl.yaml" ), ACL ).unwrap();
+    let secret = "07".repeat( 16 );
+    fs::write( root.join( "keys.yaml" ),
+               format!( "keys:\n  alice: \"{0}\"\n  nodes: \"{0}\"\n", secret ) ).unwrap();
+
+    let node = common::free_node();
+    let _server = Server::start( &root, &node );
+
+    // anyone may read under /public
+    let anonymous = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE );
+    let deadline = Instant::now() + Duration::from_secs( 10 );
+    while status( &anonymous, &node, Opcode::Length, "/public/a.txt", &[] ) != Status::Ok as u8 {
+        assert!( Instant::now() < deadline, "the server did not start" );
+        thread::sleep( Duration::from_millis( 100 ) );
+    }
+    let forbidden = Status::Forbidden as u8;
+    assert_eq!( status( &anonymous, &node, Opcode::Length, "/hello.txt", &[] ), forbidden );
+    assert_eq!( status( &anonymous, &node, Opcode::Read, "/file_store.yaml", &[] ), forbidden );
+    assert_eq!( status( &anonymous, &node, Opcode::Write, "/public/a.txt", b"x" ), forbidden );
+
+    // alice may only write under /alice, and may not hand out permissions
+    let alice = signed_pool( "alice" );
+    assert_eq!( status( &alice, &node, Opcode::Write, "/alice/new.txt", b"new" ),
+                Status::Ok as u8 );
+    assert_eq!( status( &alice, &node, Opcode::Length, "/alice/new.txt", &[] ),
+                Status::Ok as u8 );
+    assert_eq!( status( &alice, &node, Opcode::Read, "/hello.txt", &[] ), forbidden );
+    assert_eq!( status( &alice, &node, Opcode::Write, "/acl.yaml", b"/:\n  admin: [alice]\n" ),
+                forbidden );
+
+    // admins may change the rules, which apply straight away
+    let nodes = signed_pool( "nodes" );
+    assert_eq!( status( &nodes, &node, Opcode::Read, "/file_store.yaml", &[] ),
+                Status::Ok as u8 );
+    let acl = format!( "{}/hello.txt:\n  read: [\"*\"]\n", ACL );
+    assert_eq!( status( &nodes, &node, Opcode::Write, "/acl.yaml", acl.as_bytes() ),
+                Status::Ok as u8 );
+    assert_eq!( status( &anonymous, &node, Opcode::Length, "/hello.txt", &[] ),
+                Status::Ok as u8 );
+
+    // and an invalid acl.yaml refuses everything
+    assert_eq!( status( &nodes, &node, Opcode::Write, "/acl.yaml", b"/: [nodes]\n" ),
+                Status::Ok as u8 );
+    assert_eq!( status( &anonymous, &node, Opcode::Length, "/public/a.txt", &[] ), forbidden );
+}
@@ -0,0 +1,122 @@
+// Runs a single root node on localhost, and checks the client's commands,
+// their --json output and their exit status.
+
+extern crate turtlefs;
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::thread;
+use std::time::{Duration, Instant};
+use common::{Server, TempRoot};
+
+fn client( root: &Path, args: &[&str] ) -> Output {
+    Command::new( env!( "CARGO_BIN_EXE_client" ) )
+        .arg( root )
+        .args( args )
+        .output()
+        .unwrap()
+}
+
+fn stdout( output: &Output ) -> String {
+    String::from_utf8_lossy( &output.stdout ).into_owned()
+}
+
+fn stderr( output: &Output ) -> String {
+    String::from_utf8_lossy( &output.stderr ).into_owned()
+}
+
+// the server is stopped before its root is removed, as long as they are
+// bound in this order
+fn start_server() -> ( TempRoot, Server ) {
+    let root = TempRoot::new( "client" );
+    let node = common::free_node();
+    fs::write( root.join( "nodes.yaml" ), format!( "- {}\n", node ) ).unwrap();
+    fs::write( root.join( "store" ).join( "hello.txt" ), "Hello\nWorld\n" ).unwrap();
+    fs::write( root.join( "store" ).join( "file_store.yaml" ),
+               format!( "/hello.txt:\n  checksum: DB588331\n  nodes:\n    - {}\n", node ) )
+        .unwrap();
+
+    let server = Server::start( &root, &node );
+
+    // the root node has to elect itself before it serves file_store.yaml
+    let deadline = Instant::now() + Duration::from_secs( 10 );
+    while !client( &root, &[ "stat", "/hello.txt" ] ).status.success() {
+        assert!( Instant::now() < deadline, "the server did not start" );
+        thread::sleep( Duration::from_millis( 100 ) );
+    }
+    ( root, server )
+}
+
+#[ test ]
+fn commands_report_results_and_exit_status() {
+    let ( root, _server ) = start_server();
+
+    let get = client( &root, &[ "get", "/hello.txt" ] );
+    assert!( get.status.success() );
+    assert_eq!( stdout( &get ), "Hello\nWorld\n" );
+
+    let cat = client( &root, &[ "cat", "/hello.txt", "--range", "2:9" ] );
+    assert!( cat.status.success() );
+    assert_eq!( stdout( &cat ), "llo\nWor" );
+    assert_eq!( stdout( &client( &root, &[ "cat", "/hello.txt", "--range", "6:" ] ) ),
+                "World\n" );
+    assert_eq!( stdout( &client( &root, &[ "cat", "/hello.txt", "--range", "12:12" ] ) ), "" );
+
+    // the older form still works
+    assert_eq!( stdout( &client( &root, &[ "/hello.txt" ] ) ), "Hello\nWorld\n" );
+
+    let output = root.join( "hello.copy" );
+    let get = client( &root, &[ "--json", "get", "/hello.txt", "-o", output.to_str().unwrap() ] );
+    assert!( get.status.success() );
+    assert!( stdout( &get ).contains( "\"length\":12" ) );
+    assert_eq!( fs::read( &output ).unwrap(), b"Hello\nWorld\n" );
+
+    let stat = client( &root, &[ "stat", "/hello.txt", "--json" ] );
+    assert!( stat.status.success() );
+    assert!( stdout( &stat ).starts_with( "{\"file\":\"/hello.txt\"" ) );
+    assert!( stdout( &stat ).contains( "\"size\":12" ) );
+    assert!( stdout( &stat ).contains( "\"matches\":true" ) );
+
+    let ls = client( &root, &[ "ls", "--json" ] );
+    assert!( ls.status.success() );
+    assert!( stdout( &ls ).contains( "{\"type\":\"file\",\"path\":\"/hello.txt\"" ) );
+    assert_eq!( client( &root, &[ "ls", "/nothing" ] ).status.code(), Some( 4 ) );
+
+    assert!( client( &root, &[ "verify", "/hello.txt" ] ).status.success() );
+
+    // failures exit with a status saying why
+    let missing_output = root.join( "missing.copy" );
+    let missing = client( &root, &[ "--json", "get", "/missing.txt", "-o",
+                                    missing_output.to_str().unwrap() ] );
+    assert_eq!( missing.status.code(), Some( 4 ) );
+    assert!( stderr( &missing ).contains( "\"kind\":\"not_found\"" ) );
+    assert!( !missing_output.exists() );
+
+    assert_eq!( client( &root, &[] ).status.code(), Some( 2 ) );
+    assert_eq!( client( &root, &[ "frobnicate" ] ).status.code(), Some( 2 ) );
+    assert_eq!( client( &root, &[ "cat", "/hello.txt", "--range", "5:2" ] ).status.code(),
+                Some( 2 ) );
+    assert_eq!( client( &root, &[ "cat", "/hello.txt", "--range", "13:" ] ).status.code(),
+                Some( 2 ) );
+    assert_eq!( client( &root, &[ "cat", "/hello.txt", "--range", "6:13" ] ).status.code(),
+                Some( 2 ) );
+    assert_eq!( client( &root, &[ "--json", "get", "/hello.txt" ] ).status.code(), Some( 2 ) );
+    assert_eq!( client( &root.join( "nowhere" ), &[ "ls" ] ).status.code(), Some( 3 ) );
+
+    fs::write( root.join( "store" ).join( "hello.txt" ), "Hello\nThere\n" ).unwrap();
+    let verify = client( &root, &[ "verify", "/hello.txt", "--json" ] );
+    assert_eq!( verify.status.code(), Some( 7 ) );
+    assert!( stdout( &verify ).contains( "\"ok\":false" ) );
+    assert_eq!( client( &root, &[ "get", "/hello.txt" ] ).status.code(), Some( 7 ) );
+
+    // an empty namespace lists nothing under /, which isn't an error
+    assert!( client( &root, &[ "edit", "/hello.txt", "--remove" ] ).status.success() );
+    let ls = client( &root, &[ "ls", "--json" ] );
+    assert!( ls.status.success() );
+    assert_eq!( stdout( &ls ), "[]\n" );
+    assert!( client( &root, &[ "ls", "/" ] ).status.success() );
+    assert_eq!( client( &root, &[ "ls", "/nothing" ] ).status.code(), Some( 4 ) );
+}
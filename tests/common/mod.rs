@@ -0,0 +1,81 @@
+// Shared by the tests which run servers on localhost: a turtlefs-root of
+// their own in the temporary directory, and servers started from it, which
+// are stopped and removed once the test is over, whether it passed or not.
+
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use turtlefs::node::Node;
+
+// an empty directory in the temporary directory, with a store/ in it,
+// which is removed when dropped
+pub struct TempRoot {
+    path: PathBuf,
+}
+
+impl TempRoot {
+    // name tells the tests apart, and the process ID test runs
+    pub fn new( name: &str ) -> TempRoot {
+        let mut path = env::temp_dir();
+        path.push( format!( "turtlefs-{}-{}", name, std::process::id() ) );
+        let _ = fs::remove_dir_all( &path );
+        fs::create_dir_all( path.join( "store" ) ).unwrap();
+        TempRoot { path }
+    }
+}
+
+impl Deref for TempRoot {
+    type Target = Path;
+
+    fn deref( &self ) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef< Path > for TempRoot {
+    fn as_ref( &self ) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempRoot {
+    fn drop( &mut self ) {
+        let _ = fs::remove_dir_all( &self.path );
+    }
+}
+
+// a localhost address which nothing is listening on
+pub fn free_node() -> Node {
+    let port = TcpListener::bind( "127.0.0.1:0" ).unwrap().local_addr().unwrap().port();
+    format!( "127.0.0.1:{}", port ).parse().unwrap()
+}
+
+// a server running as node from a turtlefs-root, which is stopped when
+// dropped. Declare it after the TempRoot it runs from, so it is stopped
+// before its root is removed.
+pub struct Server {
+    child: Child,
+}
+
+impl Server {
+    pub fn start( root: &Path, node: &Node ) -> Server {
+        let child = Command::new( env!( "CARGO_BIN_EXE_server" ) )
+            .arg( root )
+            .arg( node.to_string() )
+            .stdout( Stdio::null() )
+            .stderr( Stdio::null() )
+            .spawn()
+            .unwrap();
+        Server { child }
+    }
+}
+
+impl Drop for Server {
+    fn drop( &mut self ) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
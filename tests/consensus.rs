@@ -0,0 +1,142 @@
+// Runs several servers on localhost as root nodes, and checks that manifest
+// edits survive losing the consensus leader, and reach a root node which
+// missed them after the log was compacted.
+
+extern crate turtlefs;
+
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+use turtlefs::digest::Digest;
+use turtlefs::manifest::{MANIFEST_PATH, FileEntry, ManifestEdit};
+use turtlefs::node::Node;
+use turtlefs::pool::{ConnectionPool, POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE};
+use turtlefs::protocol::{Opcode, Status};
+use turtlefs::request::{self, Request};
+use common::{Server, TempRoot};
+
+// give every server its own turtlefs-root, all listing the same root nodes.
+// The servers are stopped before their roots are removed, as long as they
+// are bound in this order.
+fn start_servers( count: usize ) -> ( Vec< TempRoot >, Vec< Node >, Vec< Option< Server > > ) {
+    let root_nodes: Vec< Node > = ( 0 .. count ).map( | _ | common::free_node() ).collect();
+    let nodes_yaml: String = root_nodes.iter()
+        .map( | node | format!( "- {}\n", node ) )
+        .collect();
+
+    let mut roots = Vec::new();
+    let mut servers = Vec::new();
+    for node in root_nodes.iter() {
+        let root = TempRoot::new( &format!( "consensus-{}", node.port ) );
+        fs::write( root.join( "nodes.yaml" ), &nodes_yaml ).unwrap();
+        fs::write( root.join( "store" ).join( "file_store.yaml" ), "" ).unwrap();
+        servers.push( Some( Server::start( &root, node ) ) );
+        roots.push( root );
+    }
+    ( roots, root_nodes, servers )
+}
+
+// the root node which answers manifest reads itself
+fn find_leader( pool: &ConnectionPool, root_nodes: &[Node] ) -> usize {
+    let deadline = Instant::now() + Duration::from_secs( 10 );
+    while Instant::now() < deadline {
+        for ( index, node ) in root_nodes.iter().enumerate() {
+            let request = Request {
+                node,
+                opcode: Opcode::Read,
+                file_name: MANIFEST_PATH,
+                args: Vec::new(),
+                payload: &[],
+            };
+            let response_buffer = &mut Vec::new();
+            if request::perform_request( pool, request, response_buffer ).status ==
+                Status::Ok as u8 {
+                return index;
+            }
+        }
+        thread::sleep( Duration::from_millis( 100 ) );
+    }
+    panic!( "no leader was elected" );
+}
+
+fn add_entry( node: &str ) -> ManifestEdit {
+    ManifestEdit::AddEntry( FileEntry { checksum: Digest::Crc32( 0xDB588331 ),
+                                        blocks: None,
+                                        nodes: vec![ node.parse().unwrap() ] } )
+}
+
+#[ test ]
+fn edits_survive_leader_failure() {
+    let ( _roots, root_nodes, mut servers ) = start_servers( 3 );
+    let pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE );
+
+    let leader = find_leader( &pool, &root_nodes );
+    assert_eq!( request::edit_manifest( &pool, &root_nodes, "/a.txt",
+                                        &add_entry( "127.0.0.1:1" ) ), Ok( 1 ) );
+
+    // an edit against an old version is rejected rather than applied
+    let stale_edit = vec![ String::from( "0" ), String::from( "REMOVE" ) ];
+    match request::perform_leader_request( &pool, &root_nodes, Opcode::Edit, "/a.txt",
+                                           stale_edit ) {
+        Err( ( Status::VersionConflict, _ ) ) => {},
+        other => panic!( "expected a version conflict, got {:?}", other ),
+    }
+
+    // other spellings of the same path edit the same entry
+    let respelled = request::edit_manifest( &pool, &root_nodes, "//./a.txt",
+                                            &add_entry( "127.0.0.1:1" ) );
+    assert!( respelled.as_ref().is_err_and( | why | why.contains( "already in" ) ),
+             "{:?}", respelled );
+
+    // the remaining two root nodes are still a majority
+    servers[ leader ] = None;
+    assert_eq!( request::edit_manifest( &pool, &root_nodes, "/b.txt",
+                                        &add_entry( "127.0.0.1:2" ) ), Ok( 2 ) );
+
+    let manifest = request::request_manifest( &pool, root_nodes.clone() ).unwrap();
+    assert_eq!( manifest.version, 2 );
+    assert!( manifest.entry( "/a.txt" ).is_some() );
+    assert!( manifest.entry( "/b.txt" ).is_some() );
+}
+
+#[ test ]
+fn lagging_members_catch_up_from_a_snapshot() {
+    let ( roots, root_nodes, mut servers ) = start_servers( 3 );
+    let pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE );
+
+    // enough edits for the log to be compacted while one root node is down
+    let leader = find_leader( &pool, &root_nodes );
+    let lagging = ( leader + 1 ) % root_nodes.len();
+    let other = ( leader + 2 ) % root_nodes.len();
+    servers[ lagging ] = None;
+    // asking the leader first saves following a redirect for every edit
+    let leader_first: Vec< Node > = root_nodes[ leader .. ].iter()
+        .chain( root_nodes[ .. leader ].iter() )
+        .cloned()
+        .collect();
+    for version in 1 ..= 300 {
+        assert_eq!( request::edit_manifest( &pool, &leader_first, &format!( "/{}.txt", version ),
+                                            &add_entry( "127.0.0.1:1" ) ), Ok( version ) );
+    }
+    let state_dir = | index: usize | roots[ index ].join( "raft" )
+        .join( root_nodes[ index ].to_string().replace( ':', "_" ) );
+    let state = fs::read_to_string( state_dir( leader ).join( "state.yaml" ) ).unwrap();
+    assert!( state.matches( "command:" ).count() < 256 );
+
+    // the leader and the restarted root node are only a majority once it
+    // has caught up
+    servers[ lagging ] = Some( Server::start( &roots[ lagging ], &root_nodes[ lagging ] ) );
+    servers[ other ] = None;
+    assert_eq!( request::edit_manifest( &pool, &root_nodes, "/last.txt",
+                                        &add_entry( "127.0.0.1:2" ) ), Ok( 301 ) );
+
+    let manifest = request::request_manifest( &pool, root_nodes.clone() ).unwrap();
+    assert_eq!( manifest.version, 301 );
+    assert!( manifest.entry( "/1.txt" ).is_some() );
+    assert!( manifest.entry( "/last.txt" ).is_some() );
+    let stored = fs::read_to_string( roots[ lagging ].join( "store" ).join( "file_store.yaml" ) )
+        .unwrap();
+    assert!( stored.contains( "/1.txt" ) );
+}
@@ -0,0 +1,70 @@
+// Runs a single root node on localhost, and checks that a download to a
+// local file picks up the ranges an earlier download recorded instead of
+// retrieving them again.
+
+extern crate turtlefs;
+
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+use turtlefs::{ClientError, TurtleClient};
+use turtlefs::digest::{Algorithm, Digest};
+use turtlefs::download;
+use common::{Server, TempRoot};
+
+#[ test ]
+fn downloads_resume_from_recorded_ranges() {
+    let root = TempRoot::new( "download" );
+
+    // the node's copy has lost its first 4000 bytes, so the download can
+    // only match the checksum if it keeps the ones it already has
+    let contents: Vec< u8 > = ( 0 .. 10_000u32 ).map( | i | ( i * 7 % 251 ) as u8 ).collect();
+    let checksum = Digest::compute( Algorithm::Crc32, &contents );
+    let mut damaged = contents.clone();
+    damaged[ .. 4000 ].iter_mut().for_each( | byte | *byte = 0 );
+    fs::write( root.join( "store" ).join( "data.bin" ), &damaged ).unwrap();
+
+    let node = common::free_node();
+    fs::write( root.join( "nodes.yaml" ), format!( "- {}\n", node ) ).unwrap();
+    fs::write( root.join( "store" ).join( "file_store.yaml" ),
+               format!( "/data.bin:\n  checksum: {}\n  nodes:\n    - {}\n", checksum, node ) )
+        .unwrap();
+    let _server = Server::start( &root, &node );
+
+    let client = TurtleClient::from_nodes( vec![ node.clone() ] );
+    let deadline = Instant::now() + Duration::from_secs( 10 );
+    while client.entry( "/data.bin" ).is_err() {
+        assert!( Instant::now() < deadline, "the server did not start" );
+        thread::sleep( Duration::from_millis( 100 ) );
+    }
+
+    let output = root.join( "data.copy" );
+    let mut part = contents[ .. 4000 ].to_vec();
+    part.resize( contents.len(), 0 );
+
+    // a sidecar for another file is ignored, and the node's bad copy is
+    // thrown away once it fails its checksum
+    fs::write( download::part_path( &output ), &part ).unwrap();
+    fs::write( download::ranges_path( &output ), "DB588331 10000 0\n0 4000\n" ).unwrap();
+    match client.download( "/data.bin", &output ) {
+        Err( ClientError::ChecksumMismatch { .. } ) => {},
+        other => panic!( "expected a checksum mismatch, got {:?}", other ),
+    }
+    assert!( !output.exists() );
+    assert!( !download::part_path( &output ).exists() );
+    assert!( !download::ranges_path( &output ).exists() );
+
+    // only the missing range is retrieved, and a line left half written by
+    // an interrupted download is ignored
+    fs::write( download::part_path( &output ), &part ).unwrap();
+    fs::write( download::ranges_path( &output ),
+               format!( "{} 10000 0\n0 4000\n4000 80", checksum ) ).unwrap();
+    let downloaded = client.download( "/data.bin", &output ).unwrap();
+    assert!( downloaded.resumed );
+    assert_eq!( downloaded.retrieved.checksum, checksum );
+    assert_eq!( fs::read( &output ).unwrap(), contents );
+    assert!( !download::part_path( &output ).exists() );
+    assert!( !download::ranges_path( &output ).exists() );
+}
@@ -0,0 +1,22 @@
+// Checks that a manifest written out by to_yaml loads back unchanged,
+// whatever its file names look like.
+
+extern crate turtlefs;
+
+use turtlefs::digest::Digest;
+use turtlefs::manifest::{FileEntry, Manifest};
+
+#[ test ]
+fn file_names_survive_a_round_trip() {
+    let entry = FileEntry { checksum: Digest::Crc32( 0x65694E61 ),
+                            blocks: None,
+                            nodes: vec![ "127.0.0.1:5550".parse().unwrap() ] };
+    let mut manifest = Manifest { version: 7, ..Manifest::default() };
+    for file_name in [ "/plain.txt", "/a: b", "/c #comment", "/&anchor", "/*alias", "/- item",
+                       "/\"quoted\"", "/back\\slash", "/new\nline", "/tab\there", "/bell\x07",
+                       "/'single'", "/{ flow: [ x ] }", "/é ü" ].iter() {
+        manifest.files.insert( file_name.to_string(), entry.clone() );
+    }
+
+    assert_eq!( Manifest::parse( &manifest.to_yaml() ), Ok( manifest ) );
+}
@@ -0,0 +1,24 @@
+// Checks that a request to a node which accepts connections but never
+// answers gives up after the pool's timeout instead of hanging.
+
+extern crate turtlefs;
+
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+use turtlefs::node::Node;
+use turtlefs::pool::{ConnectionPool, POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE};
+use turtlefs::request;
+
+#[ test ]
+fn stalled_nodes_time_out() {
+    // connections are completed by the listen backlog, but nothing is
+    // ever read from or written to them
+    let listener = TcpListener::bind( "127.0.0.1:0" ).unwrap();
+    let node: Node = listener.local_addr().unwrap().to_string().parse().unwrap();
+
+    let pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE )
+        .with_timeouts( Duration::from_millis( 500 ), Duration::from_millis( 200 ) );
+    let started = Instant::now();
+    assert_eq!( request::request_length( &pool, vec![ node ], "/hello.txt" ), None );
+    assert!( started.elapsed() < Duration::from_secs( 5 ) );
+}
@@ -0,0 +1,41 @@
+// Checks that request frames decode back to what was encoded, and that
+// lengths which don't fit in a frame are refused rather than truncated.
+
+extern crate turtlefs;
+
+use std::io;
+use turtlefs::protocol::{self, Opcode, REQUEST_HEADER_LEN};
+
+fn round_trip( path: &str, args: &[String] ) -> ( String, Vec< String > ) {
+    let frame = protocol::encode_request( Opcode::Edit, path, args, b"" ).unwrap();
+    let header = protocol::decode_request_header( &frame ).unwrap();
+    protocol::decode_request_body( &header, &frame[ REQUEST_HEADER_LEN .. ] ).unwrap()
+}
+
+fn refused( path: &str, args: &[String] ) -> bool {
+    match protocol::encode_request( Opcode::Edit, path, args, b"" ) {
+        Err( why ) => why.kind() == io::ErrorKind::InvalidInput,
+        Ok( _ ) => false,
+    }
+}
+
+#[ test ]
+fn oversized_frames_are_refused() {
+    // right up to the limits is fine
+    let longest_args = vec![ "a".repeat( u16::MAX as usize ) ];
+    let longest_path = format!( "/{}", "p".repeat( u16::MAX as usize - 1 ) );
+    let most_args: Vec< String > = ( 0 .. 255 ).map( | i | i.to_string() ).collect();
+    assert_eq!( round_trip( "/a", &longest_args ),
+                ( String::from( "/a" ), longest_args.clone() ) );
+    assert_eq!( round_trip( &longest_path, &[] ), ( longest_path.clone(), Vec::new() ) );
+    assert_eq!( round_trip( "/a", &most_args ), ( String::from( "/a" ), most_args.clone() ) );
+
+    // one past them is an error
+    let mut too_many_args = most_args;
+    too_many_args.push( String::from( "255" ) );
+    assert!( refused( "/a", &[ longest_args[ 0 ].clone() + "a" ] ) );
+    assert!( refused( &( longest_path + "p" ), &[] ) );
+    assert!( refused( "/a", &too_many_args ) );
+    assert!( protocol::encode_signed_request( Opcode::Edit, "/a", &too_many_args[ 4 .. ],
+                                              &too_many_args[ .. 4 ], b"" ).is_err() );
+}
@@ -0,0 +1,72 @@
+// Runs a server on localhost and reads one of its files through a
+// RemoteReader, checking reads and seeks against the local copy.
+
+extern crate turtlefs;
+
+mod common;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use turtlefs::pool::{ConnectionPool, POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE};
+use turtlefs::request;
+use turtlefs::RemoteReader;
+use common::{Server, TempRoot};
+
+#[ test ]
+fn reads_and_seeks_match_local_file() {
+    let root = TempRoot::new( "remote-reader" );
+
+    // not a multiple of the block size, so the last block is short
+    let contents: Vec< u8 > = ( 0 .. 10_000u32 ).map( | i | ( i * 7 % 251 ) as u8 ).collect();
+    fs::write( root.join( "store" ).join( "data.bin" ), &contents ).unwrap();
+
+    let node = common::free_node();
+    let _server = Server::start( &root, &node );
+
+    let pool = Arc::new( ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE ) );
+    let deadline = Instant::now() + Duration::from_secs( 10 );
+    let length = loop {
+        if let Some( length ) = request::request_length( &pool, vec![ node.clone() ], "/data.bin" ) {
+            break length;
+        }
+        assert!( Instant::now() < deadline, "the server did not start" );
+        thread::sleep( Duration::from_millis( 100 ) );
+    };
+    assert_eq!( length, contents.len() as u64 );
+
+    let mut reader = RemoteReader::new( pool.clone(), "/data.bin", vec![ node ], length )
+        .unwrap()
+        .with_block_size( 1000 )
+        .with_read_ahead( 2 )
+        .with_cached_blocks( 4 );
+
+    let mut whole = Vec::new();
+    reader.read_to_end( &mut whole ).unwrap();
+    assert_eq!( whole, contents );
+
+    // reads which straddle blocks, including ones evicted from the cache
+    let mut buf = [ 0u8; 1500 ];
+    for &start in [ 250u64, 9_000, 0, 4_999, 8_500 ].iter() {
+        assert_eq!( reader.seek( SeekFrom::Start( start ) ).unwrap(), start );
+        let end = std::cmp::min( start as usize + buf.len(), contents.len() );
+        reader.read_exact( &mut buf[ .. end - start as usize ] ).unwrap();
+        assert_eq!( &buf[ .. end - start as usize ], &contents[ start as usize .. end ] );
+    }
+
+    assert_eq!( reader.seek( SeekFrom::End( -10 ) ).unwrap(), length - 10 );
+    assert_eq!( reader.seek( SeekFrom::Current( -5 ) ).unwrap(), length - 15 );
+    let mut tail = Vec::new();
+    reader.read_to_end( &mut tail ).unwrap();
+    assert_eq!( tail, &contents[ contents.len() - 15 .. ] );
+
+    // past the end reads nothing, before the start is an error
+    reader.seek( SeekFrom::End( 100 ) ).unwrap();
+    assert_eq!( reader.read( &mut buf ).unwrap(), 0 );
+    assert!( reader.seek( SeekFrom::Current( -( length as i64 ) - 1_000 ) ).is_err() );
+
+    // there has to be a node to read from
+    assert!( RemoteReader::new( pool, "/data.bin", Vec::new(), length ).is_err() );
+}
@@ -0,0 +1,92 @@
+// Runs a server on localhost which only accepts requests signed with one of
+// the keys in its keys.yaml, and checks that replayed and badly signed
+// requests are refused.
+
+extern crate turtlefs;
+
+mod common;
+
+use std::fs;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+use turtlefs::node::Node;
+use turtlefs::pool::{ConnectionPool, POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE};
+use turtlefs::protocol::{self, Opcode, Status};
+use turtlefs::request::{self, Request};
+use turtlefs::signing::{Signature, SigningKey};
+use common::{Server, TempRoot};
+
+const ALICE_SECRET: &str = "6f1c0e5a9b3d2f4e8a7c6b5d4e3f2a1b";
+
+fn read_length( pool: &ConnectionPool, node: &Node ) -> ( u8, String ) {
+    let request = Request {
+        node,
+        opcode: Opcode::Length,
+        file_name: "/hello.txt",
+        args: Vec::new(),
+        payload: &[],
+    };
+    let response_buffer = &mut Vec::new();
+    let response = request::perform_request( pool, request, response_buffer );
+    ( response.status, String::from_utf8_lossy( response.message ).into_owned() )
+}
+
+// send a LENGTH request with the given signature over a new connection
+fn send_signed( node: &Node, signature: &Signature ) -> u8 {
+    let mut stream = TcpStream::connect( node.to_string() ).unwrap();
+    protocol::write_request( &mut stream, Opcode::Length, "/hello.txt", &[],
+                             Some( &signature.to_args() ), &[] ).unwrap();
+    protocol::read_response( &mut stream, &mut Vec::new() ).unwrap()
+}
+
+#[ test ]
+fn requires_fresh_signed_requests() {
+    let root = TempRoot::new( "signing" );
+    fs::write( root.join( "store" ).join( "hello.txt" ), "Hello\nWorld\n" ).unwrap();
+    fs::write( root.join( "keys.yaml" ),
+               format!( "signing_key: alice\n\
+                         require_signatures: true\n\
+                         keys:\n  \
+                           alice: {}\n", ALICE_SECRET ) ).unwrap();
+
+    let node = common::free_node();
+    let _server = Server::start( &root, &node );
+
+    // the server's own keys sign requests
+    let signed_pool = ConnectionPool::from_root( root.to_str().unwrap() ).unwrap();
+    assert!( signed_pool.signing_key().is_some() );
+    let deadline = Instant::now() + Duration::from_secs( 10 );
+    loop {
+        let ( status, message ) = read_length( &signed_pool, &node );
+        if status == Status::Ok as u8 {
+            assert_eq!( message, "12" );
+            break;
+        }
+        assert!( Instant::now() < deadline, "the server did not start: {}", message );
+        thread::sleep( Duration::from_millis( 100 ) );
+    }
+
+    // unsigned requests are refused
+    let unsigned_pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE );
+    assert_eq!( read_length( &unsigned_pool, &node ).0, Status::Unsigned as u8 );
+
+    // a signed request is only accepted once
+    let secret: Vec< u8 > = ( 0 .. ALICE_SECRET.len() / 2 )
+        .map( | i | u8::from_str_radix( &ALICE_SECRET[ i * 2 .. i * 2 + 2 ], 16 ).unwrap() )
+        .collect();
+    let alice = SigningKey::new( "alice", &secret );
+    let signature = alice.sign( Opcode::Length, "/hello.txt", &[], &[] ).unwrap();
+    assert_eq!( send_signed( &node, &signature ), Status::Ok as u8 );
+    assert_eq!( send_signed( &node, &signature ), Status::Replayed as u8 );
+
+    // signatures over a different request, or with an unknown key, are bad
+    let other_request = alice.sign( Opcode::Read, "/hello.txt", &[], &[] ).unwrap();
+    assert_eq!( send_signed( &node, &other_request ), Status::BadSignature as u8 );
+    let mallory = SigningKey::new( "mallory", &secret );
+    let unknown_key = mallory.sign( Opcode::Length, "/hello.txt", &[], &[] ).unwrap();
+    assert_eq!( send_signed( &node, &unknown_key ), Status::BadSignature as u8 );
+    let wrong_secret = SigningKey::new( "alice", &[ 0u8; 16 ] )
+        .sign( Opcode::Length, "/hello.txt", &[], &[] ).unwrap();
+    assert_eq!( send_signed( &node, &wrong_secret ), Status::BadSignature as u8 );
+}
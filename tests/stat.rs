@@ -0,0 +1,44 @@
+// Runs a server on localhost, and checks that STAT reports a crc32
+// checksum which YAML would otherwise load as a number.
+
+extern crate turtlefs;
+
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+use turtlefs::digest::{Algorithm, Digest};
+use turtlefs::stat::FileStat;
+use turtlefs::TurtleClient;
+use common::{Server, TempRoot};
+
+#[ test ]
+fn reports_checksums_that_look_like_numbers() {
+    let root = TempRoot::new( "stat" );
+    let node = common::free_node();
+    // crc32 65694E61, which is a float when left unquoted
+    let contents = b"stat 17\n";
+    let checksum = Digest::compute( Algorithm::Crc32, contents );
+    assert_eq!( checksum.to_string(), "65694E61" );
+    fs::write( root.join( "store" ).join( "float.txt" ), contents ).unwrap();
+    let _server = Server::start( &root, &node );
+
+    let client = TurtleClient::from_nodes( vec![ node.clone() ] );
+    let deadline = Instant::now() + Duration::from_secs( 10 );
+    let file_stat = loop {
+        match client.stat_node( "/float.txt", &node, Algorithm::Crc32 ) {
+            Ok( file_stat ) => break file_stat,
+            Err( why ) => assert!( Instant::now() < deadline, "the server did not start: {}",
+                                   why ),
+        }
+        thread::sleep( Duration::from_millis( 100 ) );
+    };
+    assert_eq!( file_stat.size, contents.len() as u64 );
+    assert_eq!( file_stat.checksum, checksum );
+
+    // as sent by servers which didn't quote it
+    let unquoted = FileStat::parse( "size: 8\nmodified: 0\npermissions: 644\n\
+                                     checksum: 65694E61\n" ).unwrap();
+    assert_eq!( unquoted.checksum, checksum );
+}
@@ -0,0 +1,64 @@
+// Runs a server on localhost, and checks that requests which name the
+// store itself or a directory are refused without anything being written.
+
+extern crate turtlefs;
+
+mod common;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use turtlefs::node::Node;
+use turtlefs::pool::{ConnectionPool, POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE};
+use turtlefs::protocol::{Opcode, Status};
+use turtlefs::request::{self, Request};
+use common::{Server, TempRoot};
+
+fn status( pool: &ConnectionPool, node: &Node, opcode: Opcode, file_name: &str,
+           payload: &[u8] ) -> u8 {
+    let request = Request { node, opcode, file_name, args: Vec::new(), payload };
+    request::perform_request( pool, request, &mut Vec::new() ).status
+}
+
+#[ test ]
+fn refuses_the_store_and_directories_as_files() {
+    let root = TempRoot::new( "store-path" );
+    fs::create_dir_all( root.join( "store" ).join( "dir" ) ).unwrap();
+    fs::write( root.join( "store" ).join( "hello.txt" ), "Hello\nWorld\n" ).unwrap();
+
+    let node = common::free_node();
+    let _server = Server::start( &root, &node );
+
+    let pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE );
+    let deadline = Instant::now() + Duration::from_secs( 10 );
+    while status( &pool, &node, Opcode::Length, "/hello.txt", &[] ) != Status::Ok as u8 {
+        assert!( Instant::now() < deadline, "the server did not start" );
+        thread::sleep( Duration::from_millis( 100 ) );
+    }
+
+    // the legacy text format
+    let mut stream = TcpStream::connect( node.to_string() ).unwrap();
+    stream.write_all( b"/:(WRITE)outside the store" ).unwrap();
+    stream.shutdown( Shutdown::Write ).unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end( &mut reply ).unwrap();
+    assert_eq!( reply.first(), Some( &( Status::Error as u8 ) ) );
+    assert!( String::from_utf8_lossy( &reply ).contains( "must name a file" ) );
+
+    let error = Status::Error as u8;
+    assert_eq!( status( &pool, &node, Opcode::Write, "/", b"outside the store" ), error );
+    assert_eq!( status( &pool, &node, Opcode::Write, "/dir", b"not a file" ), error );
+    assert_eq!( status( &pool, &node, Opcode::Read, "/", &[] ), error );
+    assert_eq!( status( &pool, &node, Opcode::Delete, "/dir", &[] ), error );
+
+    // nothing was written next to the store, or left behind inside it
+    let mut beside_store: Vec< _ > = fs::read_dir( &root ).unwrap()
+        .map( | entry | entry.unwrap().file_name() )
+        .collect();
+    beside_store.sort();
+    assert_eq!( beside_store, vec![ "store" ] );
+    assert!( root.join( "store" ).join( "dir" ).is_dir() );
+    assert_eq!( fs::read_dir( root.join( "store" ) ).unwrap().count(), 2 );
+}
@@ -0,0 +1,150 @@
+// Runs a server on localhost which only accepts TLS connections from
+// clients with a certificate, using a self-signed CA made up for the test.
+
+extern crate openssl;
+extern crate turtlefs;
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::{X509, X509Builder, X509NameBuilder};
+use openssl::x509::extension::{BasicConstraints, KeyUsage, SubjectAlternativeName};
+use turtlefs::node::Node;
+use turtlefs::pool::{ConnectionPool, POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE};
+use turtlefs::protocol::{Opcode, Status};
+use turtlefs::request::{self, Request};
+use turtlefs::tls::TlsSettings;
+use common::{Server, TempRoot};
+
+fn new_key() -> PKey< Private > {
+    let group = EcGroup::from_curve_name( Nid::X9_62_PRIME256V1 ).unwrap();
+    PKey::from_ec_key( EcKey::generate( &group ).unwrap() ).unwrap()
+}
+
+// a certificate for key, signed by the CA if one is given and otherwise
+// self-signed as a CA
+fn new_certificate( common_name: &str, serial: u32, key: &PKey< Private >,
+                    ca: Option< ( &X509, &PKey< Private > ) > ) -> X509 {
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text( "CN", common_name ).unwrap();
+    let name = name.build();
+
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version( 2 ).unwrap();
+    builder.set_serial_number(
+        &BigNum::from_u32( serial ).unwrap().to_asn1_integer().unwrap() ).unwrap();
+    builder.set_subject_name( &name ).unwrap();
+    builder.set_pubkey( key ).unwrap();
+    builder.set_not_before( &Asn1Time::days_from_now( 0 ).unwrap() ).unwrap();
+    builder.set_not_after( &Asn1Time::days_from_now( 1 ).unwrap() ).unwrap();
+    match ca {
+        Some( ( ca_certificate, ca_key ) ) => {
+            builder.set_issuer_name( ca_certificate.subject_name() ).unwrap();
+            let subject_alt_name = SubjectAlternativeName::new().ip( "127.0.0.1" )
+                .build( &builder.x509v3_context( Some( ca_certificate ), None ) ).unwrap();
+            builder.append_extension( subject_alt_name ).unwrap();
+            builder.sign( ca_key, MessageDigest::sha256() ).unwrap();
+        },
+        None => {
+            builder.set_issuer_name( &name ).unwrap();
+            builder.append_extension(
+                BasicConstraints::new().critical().ca().build().unwrap() ).unwrap();
+            builder.append_extension(
+                KeyUsage::new().key_cert_sign().crl_sign().build().unwrap() ).unwrap();
+            builder.sign( key, MessageDigest::sha256() ).unwrap();
+        },
+    }
+    builder.build()
+}
+
+fn write_pem( path: &Path, certificate: &X509, key: &PKey< Private > ) {
+    fs::write( path.with_extension( "crt" ), certificate.to_pem().unwrap() ).unwrap();
+    fs::write( path.with_extension( "key" ), key.private_key_to_pem_pkcs8().unwrap() ).unwrap();
+}
+
+fn read_length( pool: &ConnectionPool, node: &Node ) -> ( u8, String ) {
+    let request = Request {
+        node,
+        opcode: Opcode::Length,
+        file_name: "/hello.txt",
+        args: Vec::new(),
+        payload: &[],
+    };
+    let response_buffer = &mut Vec::new();
+    let response = request::perform_request( pool, request, response_buffer );
+    ( response.status, String::from_utf8_lossy( response.message ).into_owned() )
+}
+
+#[ test ]
+fn requires_tls_and_client_certificates() {
+    let root = TempRoot::new( "tls" );
+    fs::create_dir_all( root.join( "tls" ) ).unwrap();
+    fs::write( root.join( "store" ).join( "hello.txt" ), "Hello\nWorld\n" ).unwrap();
+
+    let ca_key = new_key();
+    let ca_certificate = new_certificate( "turtlefs test CA", 1, &ca_key, None );
+    fs::write( root.join( "tls" ).join( "ca.crt" ), ca_certificate.to_pem().unwrap() ).unwrap();
+    let node_key = new_key();
+    let node_certificate = new_certificate(
+        "127.0.0.1", 2, &node_key, Some( ( &ca_certificate, &ca_key ) ) );
+    write_pem( &root.join( "tls" ).join( "node" ), &node_certificate, &node_key );
+    fs::write( root.join( "tls.yaml" ),
+               "certificate: tls/node.crt\n\
+                private_key: tls/node.key\n\
+                ca: tls/ca.crt\n\
+                require_tls: true\n\
+                require_client_certificate: true\n" ).unwrap();
+
+    let node = common::free_node();
+    let _server = Server::start( &root, &node );
+
+    // the server's own settings make a client with a certificate
+    let tls_settings = TlsSettings::load( root.to_str().unwrap() ).unwrap().unwrap();
+    let tls_pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE )
+        .with_tls( tls_settings.connector().unwrap() );
+    let deadline = Instant::now() + Duration::from_secs( 10 );
+    loop {
+        let ( status, message ) = read_length( &tls_pool, &node );
+        if status == Status::Ok as u8 {
+            assert_eq!( message, "12" );
+            break;
+        }
+        assert!( Instant::now() < deadline, "the server did not start: {}", message );
+        thread::sleep( Duration::from_millis( 100 ) );
+    }
+
+    // plaintext requests are refused
+    let plain_pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE );
+    assert_eq!( read_length( &plain_pool, &node ).0, Status::TlsRequired as u8 );
+
+    // as are clients without a certificate
+    let anonymous_settings = TlsSettings { certificate: None, private_key: None,
+                                           ..tls_settings.clone() };
+    let anonymous_pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE )
+        .with_tls( anonymous_settings.connector().unwrap() );
+    let ( status, message ) = read_length( &anonymous_pool, &node );
+    assert_ne!( status, Status::Ok as u8 );
+    assert!( request::is_connection_failure( &node, &message ), "{}", message );
+
+    // and servers whose certificate isn't signed by the trusted CA
+    let other_ca_key = new_key();
+    let other_ca_certificate = new_certificate( "another CA", 3, &other_ca_key, None );
+    fs::write( root.join( "tls" ).join( "other-ca.crt" ),
+               other_ca_certificate.to_pem().unwrap() ).unwrap();
+    let untrusting_settings = TlsSettings { ca: Some( root.join( "tls" ).join( "other-ca.crt" ) ),
+                                            ..tls_settings.clone() };
+    let untrusting_pool = ConnectionPool::new( POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_NODE )
+        .with_tls( untrusting_settings.connector().unwrap() );
+    let ( status, message ) = read_length( &untrusting_pool, &node );
+    assert_ne!( status, Status::Ok as u8 );
+    assert!( message.contains( "certificate verify failed" ), "{}", message );
+}